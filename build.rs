@@ -0,0 +1,105 @@
+//! Builds decker_util for the devkit so it can be embedded in decker
+//!
+//! The helper ends up in $OUT_DIR/decker_util and gets picked from:
+//!   - DECKER_UTIL_PATH, a decker_util that is already built
+//!   - Building decker_util for x86_64-unknown-linux-musl
+//!
+//! With the 'external-util' feature, or when the build fails, the file is
+//! left empty and decker looks for decker_util when it runs instead
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const TARGET: &str = "x86_64-unknown-linux-musl";
+
+/// Build decker_util with a separate cargo, returns the path to the binary
+fn build_util(out_dir: &Path) -> Result<PathBuf, String> {
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let profile = env::var("PROFILE").unwrap();
+    let target_dir = out_dir.join("decker_util_target");
+
+    let mut cmd = Command::new(cargo);
+    cmd.current_dir("decker_util")
+        .arg("build")
+        .arg("--target")
+        .arg(TARGET)
+        .arg("--target-dir")
+        .arg(&target_dir);
+    if profile == "release" {
+        cmd.arg("--release");
+    }
+
+    // NOTE(patrik): The outer cargo sets these for decker, they shouldn't
+    // leak into the build of decker_util. The wrapper is set by clippy.
+    for var in [
+        "RUSTFLAGS",
+        "CARGO_ENCODED_RUSTFLAGS",
+        "CARGO_TARGET_DIR",
+        "CARGO_BUILD_TARGET",
+        "RUSTC_WORKSPACE_WRAPPER",
+    ] {
+        cmd.env_remove(var);
+    }
+
+    // NOTE(patrik): Only Linux can link musl binaries without a cross
+    // linker, use the same one as the Makefile on other hosts
+    let linker_var = "CARGO_TARGET_X86_64_UNKNOWN_LINUX_MUSL_LINKER";
+    let host = env::var("HOST").unwrap();
+    if !host.contains("linux") && env::var_os(linker_var).is_none() {
+        cmd.env(linker_var, "x86_64-linux-musl-gcc");
+    }
+
+    let status = cmd
+        .status()
+        .map_err(|e| format!("failed to run cargo: {}", e))?;
+    if !status.success() {
+        return Err(format!("cargo exited with {}", status));
+    }
+
+    Ok(target_dir.join(TARGET).join(profile).join("decker_util"))
+}
+
+fn main() {
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let dest = out_dir.join("decker_util");
+
+    println!("cargo:rerun-if-env-changed=DECKER_UTIL_PATH");
+    for path in [
+        "decker_util/src",
+        "decker_util/Cargo.toml",
+        "decker_protocol/src",
+        "decker_protocol/Cargo.toml",
+        "vdf/src",
+        "vdf/Cargo.toml",
+    ] {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+
+    if let Some(path) = env::var_os("DECKER_UTIL_PATH") {
+        std::fs::copy(&path, &dest).unwrap_or_else(|e| {
+            panic!("failed to copy DECKER_UTIL_PATH {:?}: {}", path, e)
+        });
+        return;
+    }
+
+    if env::var_os("CARGO_FEATURE_EXTERNAL_UTIL").is_some() {
+        std::fs::write(&dest, []).unwrap();
+        return;
+    }
+
+    match build_util(&out_dir) {
+        Ok(path) => {
+            std::fs::copy(&path, &dest).unwrap();
+        }
+        Err(e) => {
+            println!(
+                "cargo:warning=decker_util couldn't be built ({}), decker \
+                 will look for it at runtime. Add the {} target with rustup \
+                 or set DECKER_UTIL_PATH to embed it.",
+                e, TARGET
+            );
+            std::fs::write(&dest, []).unwrap();
+        }
+    }
+}
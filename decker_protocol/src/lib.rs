@@ -0,0 +1,677 @@
+//! The protocol between decker and decker_util
+//!
+//! decker runs 'decker_util request <json>' with a Request and decker_util
+//! prints a single Response as json on stdout. Both sides checks the
+//! version, decker starts with a Hello request after uploading decker_util
+//! so a mismatch is found before anything gets changed on the devkit.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// The version of the protocol, bump when a request or response changes
+pub const VERSION: u32 = 8;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Request {
+    pub version: u32,
+
+    #[serde(flatten)]
+    pub command: Command,
+}
+
+impl Request {
+    pub fn new(command: Command) -> Self {
+        Self {
+            version: VERSION,
+            command,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Command {
+    /// The version handshake
+    Hello,
+
+    /// Information about the devkit
+    Status,
+
+    /// List the deployed games
+    List,
+
+    /// Create the game directory, with remove_old the old files are removed
+    PrepareUpload {
+        game_id: String,
+        remove_old: bool,
+    },
+
+    CreateShortcut(CreateShortcut),
+
+    RemoveShortcut {
+        game_id: String,
+
+        /// Don't remove the game files
+        keep_files: bool,
+
+        /// Remove the grid artwork of the shortcut
+        artwork: bool,
+
+        /// Remove the compatibility tool mapping of the shortcut
+        compat_tool: bool,
+    },
+
+    /// Start the game through Steam, with wait the response is sent when
+    /// the game exits
+    Run {
+        game_id: String,
+        wait: bool,
+    },
+
+    /// Stop the processes of the game, they get SIGTERM and after the grace
+    /// period SIGKILL
+    Kill {
+        game_id: String,
+
+        /// Seconds to wait for the processes to exit after SIGTERM
+        grace_period: u64,
+    },
+
+    /// Find the log of a run, the latest run when no run is given
+    Logs {
+        game_id: String,
+        run: Option<u32>,
+    },
+}
+
+impl Command {
+    /// The game id the command works on, it needs to be checked before the
+    /// command runs
+    pub fn game_id(&self) -> Option<&str> {
+        match self {
+            Command::PrepareUpload { game_id, .. } |
+            Command::RemoveShortcut { game_id, .. } |
+            Command::Run { game_id, .. } |
+            Command::Kill { game_id, .. } |
+            Command::Logs { game_id, .. } => Some(game_id),
+            Command::CreateShortcut(options) => Some(&options.game_id),
+            Command::Hello | Command::Status | Command::List => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct CreateShortcut {
+    pub game_id: String,
+
+    /// The name shown in Steam, defaults to "Decker: <game_id>"
+    pub name: Option<String>,
+
+    pub exec: String,
+    pub starting_dir: String,
+    pub launch_options: String,
+    pub icon: String,
+    pub tags: Vec<String>,
+
+    /// Artwork to install, the paths are on the devkit
+    pub artwork: Vec<ArtworkFile>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ArtworkFile {
+    /// One of grid, wide_grid, hero, logo or icon
+    pub kind: String,
+    pub path: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Response {
+    pub version: u32,
+
+    #[serde(flatten)]
+    pub reply: Reply,
+}
+
+impl Response {
+    pub fn new(reply: Reply) -> Self {
+        Self {
+            version: VERSION,
+            reply,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Reply {
+    Ok(Output),
+    Error(Error),
+}
+
+/// The result of a command, there is one variant for every command
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Output {
+    Hello(Hello),
+    Status(Status),
+    List(List),
+    PrepareUpload(PrepareUpload),
+    CreateShortcut(CreateShortcutOutput),
+    RemoveShortcut(RemoveShortcutOutput),
+    Run(RunOutput),
+    Kill(KillOutput),
+    Logs(LogsOutput),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Hello {
+    /// The protocol version decker_util speaks
+    pub version: u32,
+
+    /// The crate version of decker_util
+    pub util_version: String,
+
+    /// The hash of the decker_util binary, decker uploads decker_util
+    /// again when it doesn't match the one it has
+    pub hash: String,
+}
+
+/// The fields of the os-release file we care about
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct OsRelease {
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub pretty_name: Option<String>,
+    pub build_id: Option<String>,
+    pub variant_id: Option<String>,
+    pub version_id: Option<String>,
+    pub version_codename: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Status {
+    #[serde(flatten)]
+    pub os: OsRelease,
+
+    /// Free space in bytes on the filesystem where the games are deployed
+    pub disk_free: Option<u64>,
+
+    /// Total space in bytes on the filesystem where the games are deployed
+    pub disk_total: Option<u64>,
+
+    /// Battery level in percent
+    pub battery_level: Option<u8>,
+
+    /// Battery status i.e "Charging" or "Discharging"
+    pub battery_status: Option<String>,
+
+    /// Uptime in seconds
+    pub uptime: Option<u64>,
+
+    pub steam_running: bool,
+}
+
+/// A shortcut created by decker
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ListShortcut {
+    pub user: String,
+    pub game_id: String,
+    pub appid: u32,
+    pub exe: String,
+    pub start_dir: String,
+}
+
+/// A deployed game
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ListGame {
+    pub game_id: String,
+    pub path: String,
+    pub size: u64,
+    pub deployed_at: Option<u64>,
+    pub shortcuts: Vec<ListShortcut>,
+
+    /// The game directory has no shortcut for any user
+    pub orphaned: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct List {
+    pub games: Vec<ListGame>,
+
+    /// Shortcuts created by decker where the game directory is gone
+    pub orphaned_shortcuts: Vec<ListShortcut>,
+
+    /// Users where the shortcuts couldn't be read
+    pub errors: Vec<UserError>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PrepareUpload {
+    pub path: String,
+
+    /// The game directory existed before
+    pub exists: bool,
+
+    pub removed_old_content: bool,
+}
+
+/// A field of a shortcut that got changed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FieldChange {
+    pub old: FieldValue,
+    pub new: FieldValue,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum FieldValue {
+    Text(String),
+    List(Vec<String>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ShortcutAction {
+    Created,
+    Updated,
+    Unchanged,
+}
+
+/// The result of creating/updating the shortcut for a single user
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ShortcutResult {
+    pub user: String,
+    pub action: ShortcutAction,
+    pub appid: u32,
+    pub changes: BTreeMap<String, FieldChange>,
+
+    /// The artwork files that got installed
+    pub artwork: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CreateShortcutOutput {
+    pub game_id: String,
+    pub users: Vec<ShortcutResult>,
+
+    /// Users where the shortcut or the artwork failed, the other users
+    /// still got the shortcut
+    pub errors: Vec<UserError>,
+}
+
+/// The shortcuts removed for a single user
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RemovedShortcuts {
+    pub user: String,
+    pub appids: Vec<u32>,
+
+    /// The artwork files that got removed
+    pub artwork: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RemoveShortcutOutput {
+    pub game_id: String,
+    pub removed_files: bool,
+    pub users: Vec<RemovedShortcuts>,
+
+    /// The appids that had a compatibility tool mapping removed
+    pub compat_tool: Vec<u32>,
+
+    /// The compatibility tool mappings couldn't be removed
+    pub compat_tool_error: Option<String>,
+
+    /// Users where the shortcut couldn't be removed, the rest still got
+    /// removed
+    pub errors: Vec<UserError>,
+}
+
+/// A run of a game started through the shortcut, the launcher writes it to
+/// .decker/runs/<run>.json and the output of the game to
+/// .decker/logs/<run>.log in the game directory
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RunInfo {
+    /// The number of the run, the first run is 1
+    pub run: u32,
+
+    /// The pid of the launcher
+    pub pid: u32,
+
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+
+    /// The exit code of the game, a game killed by a signal gets 128 + the
+    /// signal like in a shell
+    pub exit_code: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RunOutput {
+    pub appid: u32,
+
+    /// The run that got started, only when the request waited for the game
+    pub run: Option<RunInfo>,
+}
+
+/// A process of the game that got stopped
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KilledProcess {
+    pub pid: u32,
+
+    /// The name of the process from /proc/<pid>/comm
+    pub name: String,
+
+    /// The process didn't exit after SIGTERM and got SIGKILL
+    pub killed: bool,
+
+    /// A signal couldn't be sent to the process, i.e. it belongs to another
+    /// user
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KillOutput {
+    pub game_id: String,
+    pub processes: Vec<KilledProcess>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LogsOutput {
+    pub run: RunInfo,
+
+    /// The log file with the stdout and stderr of the game
+    pub path: String,
+}
+
+/// Something went wrong for a single Steam user
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UserError {
+    pub user: String,
+    pub error: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Error {
+    /// The request used a different version of the protocol
+    UnsupportedVersion { expected: u32, got: u32 },
+
+    /// The request couldn't be parsed
+    InvalidRequest { message: String },
+
+    /// The game id can't be used as a directory name
+    InvalidGameId { game_id: String },
+
+    /// A file or directory operation failed
+    Io { path: String, message: String },
+
+    /// The shortcut couldn't be created or updated for any of the users
+    ShortcutsFailed { errors: Vec<UserError> },
+
+    /// No user has a shortcut for the game
+    ShortcutNotFound { game_id: String },
+
+    /// The game can only be started when Steam is running
+    SteamNotRunning,
+
+    /// Steam couldn't be asked to start the game or the game never started
+    LaunchFailed { message: String },
+
+    /// A signal couldn't be sent to a process of the game
+    KillFailed { pid: u32, message: String },
+
+    /// The run doesn't exist, without a run the game has never run
+    RunNotFound { game_id: String, run: Option<u32> },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UnsupportedVersion { expected, got } => write!(
+                f,
+                "decker_util speaks protocol version {} but got version {}",
+                expected, got
+            ),
+            Error::InvalidRequest { message } => {
+                write!(f, "invalid request: {}", message)
+            }
+            Error::InvalidGameId { game_id } => {
+                write!(f, "invalid game id {:?}", game_id)
+            }
+            Error::Io { path, message } => write!(f, "{}: {}", path, message),
+            Error::ShortcutsFailed { errors } => {
+                for (i, error) in errors.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+
+                    write!(f, "user {}: {}", error.user, error.error)?;
+                }
+
+                Ok(())
+            }
+            Error::ShortcutNotFound { game_id } => {
+                write!(f, "no shortcut found for the game id {:?}", game_id)
+            }
+            Error::SteamNotRunning => write!(f, "Steam is not running"),
+            Error::LaunchFailed { message } => {
+                write!(f, "failed to launch the game: {}", message)
+            }
+            Error::KillFailed { pid, message } => {
+                write!(f, "failed to stop process {}: {}", pid, message)
+            }
+            Error::RunNotFound { game_id, run: None } => write!(
+                f,
+                "{:?} has not been started through the shortcut yet",
+                game_id
+            ),
+            Error::RunNotFound {
+                game_id,
+                run: Some(run),
+            } => write!(f, "{:?} has no run {}", game_id, run),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Check that the game id can be used as the name of the game directory,
+/// decker_util removes the directory so it can't point anywhere else
+pub fn is_valid_game_id(game_id: &str) -> bool {
+    !game_id.is_empty() &&
+        game_id != "." &&
+        game_id != ".." &&
+        !game_id.contains('/') &&
+        !game_id.chars().any(char::is_control)
+}
+
+/// Quote the string for a POSIX shell
+pub fn shell_quote(s: &str) -> String {
+    let is_safe =
+        |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+
+    if !s.is_empty() && s.chars().all(is_safe) {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    }
+}
+
+/// The sha256 of the data as a hex string
+pub fn hash(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_format() {
+        let request = Request::new(Command::PrepareUpload {
+            game_id: "game".to_string(),
+            remove_old: true,
+        });
+
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "version": VERSION,
+                "command": "prepare-upload",
+                "game_id": "game",
+                "remove_old": true,
+            })
+        );
+
+        let parsed: Request = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, request);
+    }
+
+    #[test]
+    fn create_shortcut_round_trip() {
+        let request = Request::new(Command::CreateShortcut(CreateShortcut {
+            game_id: "game".to_string(),
+            name: Some("My Game".to_string()),
+            exec: "/home/deck/decker-games/game/run.sh".to_string(),
+            artwork: vec![ArtworkFile {
+                kind: "hero".to_string(),
+                path: "/tmp/hero.png".to_string(),
+            }],
+            ..Default::default()
+        }));
+
+        let json = serde_json::to_string(&request).unwrap();
+        let parsed: Request = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, request);
+        assert_eq!(parsed.command.game_id(), Some("game"));
+    }
+
+    #[test]
+    fn response_format() {
+        let response = Response::new(Reply::Ok(Output::Hello(Hello {
+            version: VERSION,
+            util_version: "0.1.0".to_string(),
+            hash: hash(b"decker_util"),
+        })));
+
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "version": VERSION,
+                "ok": {
+                    "hello": {
+                        "version": VERSION,
+                        "util_version": "0.1.0",
+                        "hash": hash(b"decker_util"),
+                    }
+                }
+            })
+        );
+
+        let parsed: Response = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, response);
+    }
+
+    #[test]
+    fn error_format() {
+        let response =
+            Response::new(Reply::Error(Error::UnsupportedVersion {
+                expected: 1,
+                got: 2,
+            }));
+
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "version": VERSION,
+                "error": {
+                    "kind": "unsupported_version",
+                    "expected": 1,
+                    "got": 2,
+                }
+            })
+        );
+
+        let parsed: Response = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, response);
+    }
+
+    #[test]
+    fn field_changes() {
+        let change: FieldChange = serde_json::from_value(serde_json::json!({
+            "old": ["a"],
+            "new": ["a", "b"],
+        }))
+        .unwrap();
+        assert_eq!(
+            change.new,
+            FieldValue::List(vec!["a".to_string(), "b".to_string()])
+        );
+
+        let change: FieldChange = serde_json::from_value(serde_json::json!({
+            "old": "old name",
+            "new": "new name",
+        }))
+        .unwrap();
+        assert_eq!(change.old, FieldValue::Text("old name".to_string()));
+    }
+
+    #[test]
+    fn status_flattens_os_release() {
+        let status = Status {
+            os: OsRelease {
+                id: Some("steamos".to_string()),
+                ..Default::default()
+            },
+            disk_free: None,
+            disk_total: None,
+            battery_level: Some(50),
+            battery_status: None,
+            uptime: None,
+            steam_running: true,
+        };
+
+        let json = serde_json::to_value(&status).unwrap();
+        assert_eq!(json["id"], "steamos");
+
+        let parsed: Status = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, status);
+    }
+
+    #[test]
+    fn game_ids() {
+        assert!(is_valid_game_id("game"));
+        assert!(is_valid_game_id("Test Game"));
+        assert!(is_valid_game_id("game; rm -rf ~"));
+        assert!(is_valid_game_id("$(reboot)"));
+        assert!(is_valid_game_id("..game"));
+
+        assert!(!is_valid_game_id(""));
+        assert!(!is_valid_game_id("."));
+        assert!(!is_valid_game_id(".."));
+        assert!(!is_valid_game_id("../home"));
+        assert!(!is_valid_game_id("/home/deck"));
+        assert!(!is_valid_game_id("game\0"));
+        assert!(!is_valid_game_id("game\nother"));
+    }
+
+    #[test]
+    fn hash_hex() {
+        assert_eq!(
+            hash(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hash(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
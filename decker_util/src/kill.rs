@@ -0,0 +1,251 @@
+//! Stopping the processes of a game
+//!
+//! A process belongs to the game when its executable or working directory
+//! is inside the game directory. The launcher is skipped, it exits by
+//! itself when the game is gone and records the exit code of the game.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use decker_protocol::{Error, KillOutput, KilledProcess};
+
+use crate::get_game_dir;
+
+/// How often we check if the processes are gone during the grace period
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Read a link in /proc/<pid>, the kernel adds " (deleted)" when the file
+/// is gone i.e the game got redeployed while running
+fn read_proc_link(pid: u32, name: &str) -> Option<PathBuf> {
+    let path = std::fs::read_link(format!("/proc/{}/{}", pid, name)).ok()?;
+
+    let s = path.to_string_lossy();
+    match s.strip_suffix(" (deleted)") {
+        Some(s) => Some(PathBuf::from(s)),
+        None => Some(path),
+    }
+}
+
+fn process_name(pid: u32) -> String {
+    std::fs::read_to_string(format!("/proc/{}/comm", pid))
+        .map(|name| name.trim().to_string())
+        .unwrap_or_default()
+}
+
+/// Check if the process is still running, a zombie is already gone
+fn is_running(pid: u32) -> bool {
+    let stat = match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        Ok(stat) => stat,
+        Err(_) => return false,
+    };
+
+    // NOTE(patrik): The name is in parentheses and can contain spaces, the
+    // state comes after the last ')'
+    let state = stat
+        .rfind(')')
+        .and_then(|i| stat[i + 1..].split_whitespace().next());
+    !matches!(state, Some("Z") | Some("X") | None)
+}
+
+/// Find the processes with the executable or working directory inside the
+/// directory
+fn find_processes(dir: &Path) -> Vec<u32> {
+    let own_exe = std::env::current_exe().ok();
+    let own_pid = std::process::id();
+
+    let entries = match std::fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut pids: Vec<u32> = entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+        .filter(|&pid| pid != own_pid)
+        .filter(|&pid| {
+            let exe = read_proc_link(pid, "exe");
+            if exe.is_some() && exe == own_exe {
+                return false;
+            }
+
+            let cwd = read_proc_link(pid, "cwd");
+            [exe, cwd]
+                .iter()
+                .flatten()
+                .any(|path| path.starts_with(dir))
+        })
+        .collect();
+    pids.sort();
+
+    pids
+}
+
+fn send_signal(pid: u32, signal: libc::c_int) -> std::io::Result<()> {
+    // SAFETY: kill has no memory safety requirements
+    let res = unsafe { libc::kill(pid as libc::pid_t, signal) };
+    if res != 0 {
+        let error = std::io::Error::last_os_error();
+
+        // NOTE(patrik): The process exited by itself
+        if error.raw_os_error() == Some(libc::ESRCH) {
+            return Ok(());
+        }
+
+        return Err(error);
+    }
+
+    Ok(())
+}
+
+/// Send SIGTERM to the processes and SIGKILL to the ones still running after
+/// the grace period, a process that can't get a signal is skipped and the
+/// error is recorded for it
+fn stop_processes(pids: &[u32], grace_period: Duration) -> Vec<KilledProcess> {
+    let mut processes = Vec::new();
+    for &pid in pids {
+        let error = send_signal(pid, libc::SIGTERM).err();
+
+        processes.push(KilledProcess {
+            pid,
+            name: process_name(pid),
+            killed: false,
+            error: error.map(|e| e.to_string()),
+        });
+    }
+
+    let waiting = |process: &KilledProcess| {
+        process.error.is_none() && is_running(process.pid)
+    };
+
+    let started = Instant::now();
+    while started.elapsed() < grace_period {
+        if !processes.iter().any(waiting) {
+            return processes;
+        }
+
+        std::thread::sleep(POLL_INTERVAL);
+    }
+
+    for process in processes.iter_mut() {
+        if !waiting(process) {
+            continue;
+        }
+
+        match send_signal(process.pid, libc::SIGKILL) {
+            Ok(()) => process.killed = true,
+            Err(e) => process.error = Some(e.to_string()),
+        }
+    }
+
+    processes
+}
+
+pub fn kill(game_id: &str, grace_period: u64) -> Result<KillOutput, Error> {
+    let game_dir = get_game_dir(game_id);
+
+    // NOTE(patrik): The links in /proc are canonical paths
+    let game_dir = game_dir.canonicalize().unwrap_or(game_dir);
+
+    let pids = find_processes(&game_dir);
+    let processes = stop_processes(&pids, Duration::from_secs(grace_period));
+
+    Ok(KillOutput {
+        game_id: game_id.to_string(),
+        processes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process::{Child, Command};
+
+    use crate::test_util::TempDir;
+
+    fn spawn_in(dir: &Path, script: &str) -> Child {
+        Command::new("sh")
+            .arg("-c")
+            .arg(script)
+            .current_dir(dir)
+            .spawn()
+            .unwrap()
+    }
+
+    #[test]
+    fn finds_by_cwd() {
+        let temp = TempDir::new("find");
+        let dir = temp.path();
+        let other_temp = TempDir::new("find_other");
+        let other = other_temp.path();
+
+        let mut inside = spawn_in(dir, "sleep 100");
+        let mut outside = spawn_in(other, "sleep 100");
+
+        let pids = find_processes(dir);
+        assert!(pids.contains(&inside.id()));
+        assert!(!pids.contains(&outside.id()));
+
+        inside.kill().unwrap();
+        outside.kill().unwrap();
+        inside.wait().unwrap();
+        outside.wait().unwrap();
+    }
+
+    #[test]
+    fn term_then_kill() {
+        let temp = TempDir::new("kill");
+        let dir = temp.path();
+
+        let mut polite = spawn_in(dir, "sleep 100");
+        let mut stubborn =
+            spawn_in(dir, "trap '' TERM; while true; do sleep 0.1; done");
+
+        // NOTE(patrik): Give the shell time to set up the trap
+        std::thread::sleep(Duration::from_millis(200));
+
+        // NOTE(patrik): Without root init can't get a signal, the other
+        // processes still have to be stopped. As root init would get the
+        // signal for real so it's left out
+        let root = unsafe { libc::geteuid() } == 0;
+        let mut pids = vec![polite.id(), stubborn.id()];
+        if !root {
+            pids.insert(0, 1);
+        }
+
+        let processes = stop_processes(&pids, Duration::from_millis(500));
+        let processes = if root {
+            &processes[..]
+        } else {
+            assert!(processes[0].error.is_some());
+            assert!(!processes[0].killed);
+            &processes[1..]
+        };
+
+        assert_eq!(processes.len(), 2);
+        assert!(!processes[0].killed);
+        assert!(processes[1].killed);
+        assert_eq!(processes[0].error, None);
+        assert_eq!(processes[1].error, None);
+
+        polite.wait().unwrap();
+        stubborn.wait().unwrap();
+
+        // NOTE(patrik): The sleep started by the stubborn shell is left,
+        // it goes away by itself
+    }
+
+    #[test]
+    fn deleted_links() {
+        let temp = TempDir::new("deleted");
+        let dir = temp.path().to_path_buf();
+        let mut child = spawn_in(&dir, "sleep 100");
+        std::thread::sleep(Duration::from_millis(100));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(read_proc_link(child.id(), "cwd"), Some(dir.clone()));
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
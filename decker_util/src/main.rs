@@ -1,312 +1,1207 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::fs::File;
 use std::io::{Write, Read};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::ffi::OsStrExt;
+use std::ffi::CString;
+use std::sync::OnceLock;
 
-use serde::{Deserialize, Serialize};
 use clap::{Parser, Subcommand};
 
+use vdf::shortcuts::{ShortcutEntry, Shortcuts};
+
+use decker_protocol::{
+    Command, CreateShortcut, CreateShortcutOutput, Error, FieldChange,
+    FieldValue, Hello, List, ListGame, ListShortcut, Output, PrepareUpload,
+    RemoveShortcutOutput, RemovedShortcuts, Reply, Request, Response,
+    ShortcutAction, ShortcutResult, Status, UserError,
+};
+
+mod kill;
+mod os_release;
+mod run;
+
+#[cfg(test)]
+mod test_util;
+
+/// Directory inside the game directory where decker keeps its own files
+const DECKER_META_DIR: &str = ".decker";
+
+/// Set from the '--games-dir' argument
+static GAMES_DIR: OnceLock<PathBuf> = OnceLock::new();
+
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
     #[clap(subcommand)]
-    command: Command,
+    command: ArgCommand,
+
+    /// The directory the games are installed to, defaults to
+    /// ~/decker-games
+    #[clap(long, global = true)]
+    games_dir: Option<PathBuf>,
 }
 
 #[derive(Subcommand, Debug)]
-enum Command {
-    Status,
-    PrepareUpload {
-        #[clap(value_parser)]
-        game_id: String,
-
+enum ArgCommand {
+    /// Handle a request from decker, prints the response as json
+    Request {
+        /// The request as json
         #[clap(value_parser)]
-        remove_old: bool,
+        request: String,
     },
-    CreateShortcut {
-        #[clap(value_parser)]
-        game_id: String,
 
+    /// Run the game and record the run, the shortcut runs the game with
+    /// this
+    Launch {
+        /// The game directory, the run gets recorded in there
         #[clap(value_parser)]
-        exec: String,
+        game_dir: PathBuf,
 
-        #[clap(value_parser)]
-        starting_dir: String,
+        /// The command Steam would have run
+        #[clap(last = true, required = true)]
+        command: Vec<String>,
     },
 }
 
-#[derive(Serialize, Deserialize)]
-struct Status {
-    id: String,
-    name: String,
-    pretty_name: String,
-    build_id: String,
-    variant_id: String,
-    version_id: String,
-    version_codename: String,
+/// The suffix Steam uses for the different kinds of grid artwork
+fn artwork_suffix(kind: &str) -> Option<&'static str> {
+    match kind {
+        "grid" => Some("p"),
+        "wide_grid" => Some(""),
+        "hero" => Some("_hero"),
+        "logo" => Some("_logo"),
+        "icon" => Some("_icon"),
+        _ => None,
+    }
+}
+
+/// Convert an io error to the protocol error
+fn io_error(path: &Path, error: std::io::Error) -> Error {
+    Error::Io {
+        path: path.to_string_lossy().to_string(),
+        message: error.to_string(),
+    }
 }
 
-fn read_file<P>(path: P) -> String
+fn read_file<P>(path: P) -> std::io::Result<String>
 where
     P: AsRef<Path>,
 {
-    let mut file = File::open(path).unwrap();
+    let mut file = File::open(path)?;
 
     let mut result = String::new();
-    file.read_to_string(&mut result).unwrap();
+    file.read_to_string(&mut result)?;
 
-    result
+    Ok(result)
 }
 
-fn read_file_binary<P>(path: P) -> Vec<u8>
+fn read_file_binary<P>(path: P) -> std::io::Result<Vec<u8>>
 where
     P: AsRef<Path>,
 {
-    let mut file = File::open(path).unwrap();
+    let mut file = File::open(path)?;
 
     let mut result = Vec::new();
-    file.read_to_end(&mut result).unwrap();
+    file.read_to_end(&mut result)?;
 
-    result
+    Ok(result)
 }
 
-fn write_file_binary<P>(path: P, data: &Vec<u8>)
+/// Write the file through a temporary file that is renamed over the old
+/// one, that way Steam never sees a half written file even if we crash
+fn write_file_binary<P>(path: P, data: &[u8]) -> std::io::Result<()>
 where
     P: AsRef<Path>,
 {
-    let mut file = File::create(path).unwrap();
+    let path = path.as_ref();
 
-    file.write(data).unwrap();
-}
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".decker-tmp");
+    let temp = path.with_file_name(name);
 
-fn status() {
-    let info = read_file("/etc/os-release");
-    let info = info.trim_end();
+    let write = || {
+        let mut file = File::create(&temp)?;
 
-    let mut map = HashMap::new();
-    for split in info.split("\n") {
-        let mut splits = split.split("=");
-        let key = splits.next().unwrap();
-        let value = splits.next().unwrap();
+        // NOTE(patrik): Keep the permissions of the file we replace
+        if let Ok(metadata) = std::fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
 
-        let value = if &value[0..1] == "\"" {
-            &value[1..value.len() - 1]
-        } else {
-            value
-        };
+        file.write_all(data)?;
+        file.sync_all()?;
+
+        std::fs::rename(&temp, path)
+    };
+
+    let res = write();
+    if res.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+
+    res
+}
 
-        map.insert(key, value);
+/// Get the free and total space in bytes of the filesystem the path is on
+fn get_disk_space(path: &Path) -> Option<(u64, u64)> {
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+
+    // SAFETY: statvfs only writes to the struct we pass in and the path is
+    // a valid null terminated string
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    let res = unsafe { libc::statvfs(path.as_ptr(), &mut stat) };
+    if res != 0 {
+        return None;
     }
 
-    let status = Status {
-        id: map.get("ID").unwrap().to_string(),
-        name: map.get("NAME").unwrap().to_string(),
-        pretty_name: map.get("PRETTY_NAME").unwrap().to_string(),
-        build_id: map.get("BUILD_ID").unwrap().to_string(),
-        variant_id: map.get("VARIANT_ID").unwrap().to_string(),
-        version_id: map.get("VERSION_ID").unwrap().to_string(),
-        version_codename: map.get("VERSION_CODENAME").unwrap().to_string(),
+    let free = stat.f_bavail * stat.f_frsize;
+    let total = stat.f_blocks * stat.f_frsize;
+
+    Some((free, total))
+}
+
+/// Get the level and status of the first battery we can find
+fn get_battery() -> (Option<u8>, Option<String>) {
+    let dir = match std::fs::read_dir("/sys/class/power_supply") {
+        Ok(dir) => dir,
+        Err(_) => return (None, None),
     };
 
-    let s = serde_json::to_string_pretty(&status).unwrap();
-    print!("{}", s);
+    let mut paths: Vec<PathBuf> = dir
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let typ = std::fs::read_to_string(path.join("type"));
+            typ.map(|t| t.trim() == "Battery").unwrap_or(false)
+        })
+        .collect();
+    paths.sort();
+
+    let path = match paths.first() {
+        Some(path) => path,
+        None => return (None, None),
+    };
+
+    let level = std::fs::read_to_string(path.join("capacity"))
+        .ok()
+        .and_then(|s| s.trim().parse().ok());
+    let status = std::fs::read_to_string(path.join("status"))
+        .ok()
+        .map(|s| s.trim().to_string());
+
+    (level, status)
 }
 
-fn prepare_upload(game_id: String, remove_old: bool) {
+/// Get the uptime of the system in seconds
+fn get_uptime() -> Option<u64> {
+    let data = std::fs::read_to_string("/proc/uptime").ok()?;
+    let uptime = data.split_whitespace().next()?;
+    let uptime: f64 = uptime.parse().ok()?;
+
+    Some(uptime as u64)
+}
+
+/// Check if there is a process called "steam" running
+fn is_steam_running() -> bool {
+    let dir = match std::fs::read_dir("/proc") {
+        Ok(dir) => dir,
+        Err(_) => return false,
+    };
+
+    dir.flatten().any(|entry| {
+        let comm = std::fs::read_to_string(entry.path().join("comm"));
+        comm.map(|c| c.trim() == "steam").unwrap_or(false)
+    })
+}
+
+fn status() -> Status {
+    // NOTE(patrik): The games directory might not exist yet, use the closest
+    // directory that does
+    let games_dir = get_games_dir();
+    let disk_space = games_dir
+        .ancestors()
+        .find(|path| path.exists())
+        .and_then(get_disk_space);
+    let (battery_level, battery_status) = get_battery();
+
+    Status {
+        os: os_release::read().unwrap_or_default(),
+        disk_free: disk_space.map(|(free, _)| free),
+        disk_total: disk_space.map(|(_, total)| total),
+        battery_level,
+        battery_status,
+        uptime: get_uptime(),
+        steam_running: is_steam_running(),
+    }
+}
+
+/// Make sure the game id can't point outside of the decker-games directory,
+/// we remove the directory so this needs to be checked before anything else
+fn check_game_id(game_id: &str) -> Result<(), Error> {
+    if !decker_protocol::is_valid_game_id(game_id) {
+        return Err(Error::InvalidGameId {
+            game_id: game_id.to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+fn get_games_dir() -> PathBuf {
+    if let Some(path) = GAMES_DIR.get() {
+        return path.clone();
+    }
+
     let mut path = std::env::current_dir().unwrap();
     path.push("decker-games");
-    path.push(game_id);
 
-    if !path.exists() {
-        std::fs::create_dir_all(&path).unwrap();
-        let path = path.to_str().unwrap();
-        let data = serde_json::json!({
-            "exists": false,
-            "path": path,
-        });
+    path
+}
 
-        print!("{}", data.to_string());
-    } else {
-        if remove_old {
-            std::fs::remove_dir_all(&path).unwrap();
-            std::fs::create_dir_all(&path).unwrap();
+fn get_game_dir(game_id: &str) -> PathBuf {
+    get_games_dir().join(game_id)
+}
+
+/// The Steam install of the user decker_util runs as, the user isn't always
+/// called deck
+fn get_steam_dir() -> PathBuf {
+    let home = match std::env::var_os("HOME") {
+        Some(home) => PathBuf::from(home),
+        None => std::env::current_dir().unwrap_or_default(),
+    };
+
+    home.join(".steam/steam")
+}
+
+/// Get the user id and the path to the userdata directory of every Steam
+/// user on the device
+fn get_user_dirs() -> Result<Vec<(String, PathBuf)>, Error> {
+    let path = get_steam_dir().join("userdata");
+
+    let mut result = Vec::new();
+    for dir in std::fs::read_dir(&path).map_err(|e| io_error(&path, e))? {
+        let dir = dir.map_err(|e| io_error(&path, e))?;
+        let user = dir.file_name().to_string_lossy().to_string();
+
+        result.push((user, dir.path()));
+    }
+
+    Ok(result)
+}
+
+/// Record when the game was deployed, read back by the list command
+fn write_deploy_info(game_dir: &Path) -> Result<(), Error> {
+    let dir = game_dir.join(DECKER_META_DIR);
+    std::fs::create_dir_all(&dir).map_err(|e| io_error(&dir, e))?;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let data = serde_json::json!({ "deployed_at": now });
+
+    let path = dir.join("deploy.json");
+    write_file_binary(&path, data.to_string().as_bytes())
+        .map_err(|e| io_error(&path, e))
+}
+
+/// Get the last deploy time as a unix timestamp
+fn read_deployed_at(game_dir: &Path) -> Option<u64> {
+    let path = game_dir.join(DECKER_META_DIR).join("deploy.json");
+
+    if let Ok(data) = std::fs::read_to_string(path) {
+        let data: serde_json::Value = serde_json::from_str(&data).ok()?;
+        return data.get("deployed_at")?.as_u64();
+    }
+
+    // NOTE(patrik): Games deployed before we wrote the deploy info, the
+    // directory gets recreated on every deploy so the modified time is close
+    // enough
+    let modified = std::fs::metadata(game_dir).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs())
+}
+
+/// Get the size on disk of a directory in bytes
+fn get_dir_size(path: &Path) -> u64 {
+    let mut size = 0;
+
+    let dir = match std::fs::read_dir(path) {
+        Ok(dir) => dir,
+        Err(_) => return size,
+    };
+
+    for entry in dir.flatten() {
+        let metadata = match entry.path().symlink_metadata() {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+
+        // NOTE(patrik): st_blocks is always in 512 byte units
+        size += metadata.blocks() * 512;
+
+        if metadata.is_dir() {
+            size += get_dir_size(&entry.path());
         }
+    }
 
-        let path = path.to_str().unwrap();
-        let data = serde_json::json!({
-            "exists": true,
-            "removed_old_content": remove_old,
-            "path": path,
-        });
+    size
+}
+
+/// Remove everything in the directory except the entries in keep
+fn remove_dir_content(path: &Path, keep: &[&str]) -> Result<(), Error> {
+    for entry in std::fs::read_dir(path).map_err(|e| io_error(path, e))? {
+        let entry = entry.map_err(|e| io_error(path, e))?;
+        if keep.iter().any(|name| entry.file_name() == *name) {
+            continue;
+        }
+
+        let path = entry.path();
+        let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+        let res = if is_dir {
+            std::fs::remove_dir_all(&path)
+        } else {
+            std::fs::remove_file(&path)
+        };
+        res.map_err(|e| io_error(&path, e))?;
+    }
 
-        print!("{}", serde_json::to_string_pretty(&data).unwrap());
+    Ok(())
+}
+
+fn prepare_upload(
+    game_id: &str,
+    remove_old: bool,
+) -> Result<PrepareUpload, Error> {
+    let path = get_game_dir(game_id);
+
+    // NOTE(patrik): The runs and logs are kept so the numbering of the runs
+    // continues and the logs from before the deploy can still be read
+    let exists = path.exists();
+    if exists && remove_old {
+        remove_dir_content(&path, &[DECKER_META_DIR])?;
+
+        let meta_dir = path.join(DECKER_META_DIR);
+        if meta_dir.is_dir() {
+            remove_dir_content(&meta_dir, &["runs", "logs"])?;
+        }
     }
+
+    std::fs::create_dir_all(&path).map_err(|e| io_error(&path, e))?;
+    write_deploy_info(&path)?;
+
+    Ok(PrepareUpload {
+        path: path.to_string_lossy().to_string(),
+        exists,
+        removed_old_content: exists && remove_old,
+    })
 }
 
-fn gen_id(exe: &String, app_name: &String) -> u32 {
+fn gen_id(exe: &str, app_name: &str) -> u32 {
     let crc = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
-    let key = exe.clone() + app_name;
+    let key = exe.to_string() + app_name;
 
     crc.checksum(key.as_bytes()) | 0x80000000
 }
 
-fn create_shortcut_obj(
-    id: u32,
+/// What the shortcut of a deployed game should look like
+struct ShortcutOptions {
     game_id: String,
     app_name: String,
     exec: String,
     starting_dir: String,
-) -> vdf::Object {
-    let mut new_obj = vdf::Object::new();
+    launch_options: String,
+    icon: String,
+    tags: Vec<String>,
+    artwork: Vec<(String, PathBuf)>,
+}
 
-    new_obj.set_value("appid".to_string(), vdf::Value::Integer(id));
+impl ShortcutOptions {
+    fn new(request: CreateShortcut) -> Result<Self, Error> {
+        let mut artwork = Vec::new();
+        for file in request.artwork {
+            if artwork_suffix(&file.kind).is_none() {
+                return Err(Error::InvalidRequest {
+                    message: format!("unknown artwork kind '{}'", file.kind),
+                });
+            }
 
-    new_obj.set_value("AppName".to_string(), vdf::Value::String(app_name));
+            artwork.push((file.kind, PathBuf::from(file.path)));
+        }
 
-    new_obj.set_value("Exe".to_string(), vdf::Value::String(exec));
+        // NOTE(patrik): The launcher goes in the launch options and not in
+        // Exe, the appid is generated from Exe and the compatibility tool
+        // needs to see the game executable. With %command% the launcher
+        // wraps Proton too
+        let game_dir = get_game_dir(&request.game_id);
+        let launch_options =
+            run::wrap_launch_options(&request.launch_options, &game_dir);
+
+        Ok(Self {
+            app_name: request
+                .name
+                .unwrap_or_else(|| format!("Decker: {}", request.game_id)),
+            game_id: request.game_id,
+            exec: request.exec,
+            starting_dir: request.starting_dir,
+            launch_options,
+            icon: request.icon,
+            tags: request.tags,
+            artwork,
+        })
+    }
+}
+
+fn create_shortcut_entry(id: u32, options: &ShortcutOptions) -> ShortcutEntry {
+    let mut entry = ShortcutEntry::new(
+        id,
+        options.app_name.clone(),
+        options.exec.clone(),
+        options.starting_dir.clone(),
+    );
+    entry.launch_options = options.launch_options.clone();
+    entry.icon = options.icon.clone();
+    entry.tags = options.tags.clone();
+    entry.devkit = true;
+    entry.devkit_game_id = options.game_id.clone();
 
-    new_obj
-        .set_value("StartDir".to_string(), vdf::Value::String(starting_dir));
+    entry
+}
 
-    new_obj.set_value("icon".to_string(), vdf::Value::String("".to_string()));
+fn update_field(
+    changes: &mut BTreeMap<String, FieldChange>,
+    name: &str,
+    field: &mut String,
+    value: &str,
+) {
+    if field != value {
+        changes.insert(
+            name.to_string(),
+            FieldChange {
+                old: FieldValue::Text(field.clone()),
+                new: FieldValue::Text(value.to_string()),
+            },
+        );
+
+        *field = value.to_string();
+    }
+}
 
-    new_obj.set_value(
-        "ShortcutPath".to_string(),
-        vdf::Value::String("".to_string()),
+/// Update the fields decker owns, the rest of the fields i.e LastPlayTime
+/// are left alone
+///
+/// NOTE(patrik): Tags are only added, never removed. A tag that was dropped
+/// from the manifest stays because we can't tell it apart from a tag the
+/// user added in Steam.
+fn update_shortcut(
+    entry: &mut ShortcutEntry,
+    options: &ShortcutOptions,
+) -> BTreeMap<String, FieldChange> {
+    let mut changes = BTreeMap::new();
+
+    update_field(
+        &mut changes,
+        "AppName",
+        &mut entry.app_name,
+        &options.app_name,
     );
-
-    new_obj.set_value(
-        "LaunchOptions".to_string(),
-        vdf::Value::String("".to_string()),
+    update_field(&mut changes, "Exe", &mut entry.exe, &options.exec);
+    update_field(
+        &mut changes,
+        "StartDir",
+        &mut entry.start_dir,
+        &options.starting_dir,
     );
+    update_field(
+        &mut changes,
+        "LaunchOptions",
+        &mut entry.launch_options,
+        &options.launch_options,
+    );
+    update_field(&mut changes, "icon", &mut entry.icon, &options.icon);
 
-    new_obj.set_value("IsHidden".to_string(), vdf::Value::Integer(0));
+    let old_tags = entry.tags.clone();
+    for tag in options.tags.iter() {
+        if !entry.tags.contains(tag) {
+            entry.tags.push(tag.clone());
+        }
+    }
 
-    new_obj
-        .set_value("AllowDesktopConfig".to_string(), vdf::Value::Integer(1));
+    if entry.tags != old_tags {
+        changes.insert(
+            "tags".to_string(),
+            FieldChange {
+                old: FieldValue::List(old_tags),
+                new: FieldValue::List(entry.tags.clone()),
+            },
+        );
+    }
 
-    new_obj.set_value("AllowOverlay".to_string(), vdf::Value::Integer(1));
+    changes
+}
 
-    new_obj.set_value("OpenVR".to_string(), vdf::Value::Integer(0));
+fn write_shortcuts(path: &Path, shortcuts: &Shortcuts) -> Result<(), String> {
+    let data = shortcuts
+        .write()
+        .map_err(|e| format!("Failed to serialize {:?}: {}", path, e))?;
 
-    new_obj.set_value("Devkit".to_string(), vdf::Value::Integer(1));
+    write_file_binary(path, &data)
+        .map_err(|e| format!("Failed to write {:?}: {}", path, e))
+}
 
-    new_obj.set_value(
-        "DevkitGameID".to_string(),
-        vdf::Value::String(game_id.clone()),
-    );
+fn create_shortcut(
+    options: ShortcutOptions,
+) -> Result<CreateShortcutOutput, Error> {
+    let id = gen_id(&options.exec, &options.app_name);
+
+    let mut results = Vec::new();
+    let mut errors = Vec::new();
+
+    for (user, user_dir) in get_user_dirs()? {
+        let path = user_dir.join("config/shortcuts.vdf");
+
+        let data = if path.exists() {
+            match read_file_binary(&path) {
+                Ok(data) => data,
+                Err(e) => {
+                    errors.push(UserError {
+                        user,
+                        error: format!("Failed to read {:?}: {}", path, e),
+                    });
+                    continue;
+                }
+            }
+        } else {
+            Vec::new()
+        };
 
-    new_obj
-        .set_value("DevkitOverrideAppID".to_string(), vdf::Value::Integer(0));
+        let mut shortcuts = match Shortcuts::parse(&data) {
+            Ok(shortcuts) => shortcuts,
+            Err(e) => {
+                // NOTE(patrik): Leave the broken file alone and move on
+                // to the next user
+                errors.push(UserError {
+                    user,
+                    error: format!("Failed to parse {:?}: {}", path, e),
+                });
+                continue;
+            }
+        };
 
-    new_obj.set_value(
-        "LastPlayTime".to_string(),
-        vdf::Value::Integer(1663261394),
-    );
+        // NOTE(patrik): The appid changes if the exec changes so look for
+        // the game id first, that way the user keeps the collections and
+        // artwork that are tied to the appid
+        let existing =
+            if shortcuts.find_by_devkit_game_id(&options.game_id).is_some() {
+                shortcuts.find_by_devkit_game_id_mut(&options.game_id)
+            } else {
+                shortcuts.find_by_appid_mut(id)
+            };
+
+        let (action, appid, changes) = if let Some(entry) = existing {
+            let changes = update_shortcut(entry, &options);
+            if changes.is_empty() {
+                (ShortcutAction::Unchanged, entry.appid, changes)
+            } else {
+                (ShortcutAction::Updated, entry.appid, changes)
+            }
+        } else {
+            shortcuts.add(create_shortcut_entry(id, &options));
+            (ShortcutAction::Created, id, BTreeMap::new())
+        };
 
-    new_obj.set_value(
-        "FlatpakAppID".to_string(),
-        vdf::Value::String("".to_string()),
-    );
+        if action != ShortcutAction::Unchanged {
+            if let Err(e) = write_shortcuts(&path, &shortcuts) {
+                errors.push(UserError { user, error: e });
+                continue;
+            }
+        }
 
-    let tags = vdf::Object::new();
-    new_obj.set_value("tags".to_string(), vdf::Value::Object(tags));
+        let mut artwork = Vec::new();
+        for (kind, source) in options.artwork.iter() {
+            match install_artwork(&user_dir, appid, kind, source) {
+                Ok(path) => artwork.push(path),
+                Err(e) => errors.push(UserError {
+                    user: user.clone(),
+                    error: format!(
+                        "Failed to install the {} artwork {:?}: {}",
+                        kind, source, e
+                    ),
+                }),
+            }
+        }
 
-    new_obj
+        results.push(ShortcutResult {
+            user,
+            action,
+            appid,
+            changes,
+            artwork,
+        });
+    }
+
+    Ok(CreateShortcutOutput {
+        game_id: options.game_id,
+        users: results,
+        errors,
+    })
 }
 
-fn update_shortcut(_obj: &mut vdf::Object) {
-    // TODO(patrik): Update shortcut
-    println!("TODO: Update shortcut");
+/// Copy the artwork file into the grid directory, returns the path of the
+/// installed file
+fn install_artwork(
+    user_dir: &Path,
+    appid: u32,
+    kind: &str,
+    source: &Path,
+) -> std::io::Result<String> {
+    let grid_dir = user_dir.join("config/grid");
+    std::fs::create_dir_all(&grid_dir)?;
+
+    // NOTE(patrik): parse_artwork already checked the kind
+    let name = format!("{}{}", appid, artwork_suffix(kind).unwrap());
+
+    // NOTE(patrik): Steam picks up any image extension so the old file needs
+    // to go if the extension changed
+    remove_grid_files(&grid_dir, std::slice::from_ref(&name));
+
+    let mut dest = grid_dir.join(name);
+    if let Some(ext) = source.extension() {
+        dest.set_extension(ext);
+    }
+
+    std::fs::copy(source, &dest)?;
+
+    Ok(dest.to_string_lossy().to_string())
 }
 
-fn create_shortcut(game_id: String, exec: String, starting_dir: String) {
-    println!("Creating shortcut: {}", game_id);
+/// Remove the files in the grid directory with one of the names, the
+/// extension doesn't matter
+fn remove_grid_files(grid_dir: &Path, names: &[String]) -> Vec<String> {
+    let mut removed = Vec::new();
 
-    let app_name = format!("Decker: {}", game_id);
+    let dir = match std::fs::read_dir(grid_dir) {
+        Ok(dir) => dir,
+        Err(_) => return removed,
+    };
 
-    let id = gen_id(&exec, &app_name);
-    println!("ID: {}", id);
+    for entry in dir.flatten() {
+        let path = entry.path();
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
 
-    let path = Path::new("/home/deck/.steam/steam/userdata/");
-    for dir in std::fs::read_dir(path).unwrap() {
-        let mut path = dir.unwrap().path();
-        path.push("config/shortcuts.vdf");
+        if names.iter().any(|name| name == stem) &&
+            std::fs::remove_file(&path).is_ok()
+        {
+            removed.push(path.to_string_lossy().to_string());
+        }
+    }
+
+    removed
+}
 
+/// Remove the grid artwork files Steam stores for the appid
+fn remove_artwork(user_dir: &Path, appid: u32) -> Vec<String> {
+    let grid_dir = user_dir.join("config/grid");
+
+    let names: Vec<String> = ["", "p", "_hero", "_logo", "_icon"]
+        .iter()
+        .map(|suffix| format!("{}{}", appid, suffix))
+        .collect();
+
+    remove_grid_files(&grid_dir, &names)
+}
+
+/// Remove the compatibility tool mappings of the appids from config.vdf,
+/// returns the appids that had a mapping
+///
+/// NOTE(patrik): Only the mapping entries are cut out of the file, the rest
+/// of Steam's config is left exactly as it was
+fn remove_compat_tool_mappings(
+    path: &Path,
+    appids: &[u32],
+) -> Result<Vec<u32>, String> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let data = read_file(path)
+        .map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+
+    let keys = [
+        "InstallConfigStore",
+        "Software",
+        "Valve",
+        "Steam",
+        "CompatToolMapping",
+    ];
+    let appids: Vec<String> = appids.iter().map(|a| a.to_string()).collect();
+    let appids: Vec<&str> = appids.iter().map(|a| a.as_str()).collect();
+
+    let (data, removed) = vdf::text::remove_entries(&data, &keys, &appids)
+        .map_err(|e| format!("Failed to parse {:?}: {}", path, e))?;
+
+    if !removed.is_empty() {
+        write_file_binary(path, data.as_bytes())
+            .map_err(|e| format!("Failed to write {:?}: {}", path, e))?;
+    }
+
+    let mut result = Vec::new();
+    for appid in removed {
+        let appid = appid.parse().unwrap();
+        if !result.contains(&appid) {
+            result.push(appid);
+        }
+    }
+
+    Ok(result)
+}
+
+fn remove_shortcut(
+    game_id: &str,
+    keep_files: bool,
+    artwork: bool,
+    compat_tool: bool,
+) -> Result<RemoveShortcutOutput, Error> {
+    let mut results = Vec::new();
+    let mut errors = Vec::new();
+    let mut appids = Vec::new();
+
+    for (user, user_dir) in get_user_dirs()? {
+        let path = user_dir.join("config/shortcuts.vdf");
         if !path.exists() {
-            File::create(&path).unwrap();
+            continue;
         }
 
-        let data = read_file_binary(&path);
-        let mut obj = vdf::parse(&data).unwrap();
-        // println!("Obj: {:#?}", obj);
+        let data = match read_file_binary(&path) {
+            Ok(data) => data,
+            Err(e) => {
+                errors.push(UserError {
+                    user,
+                    error: format!("Failed to read {:?}: {}", path, e),
+                });
+                continue;
+            }
+        };
+
+        let mut shortcuts = match Shortcuts::parse(&data) {
+            Ok(shortcuts) => shortcuts,
+            Err(e) => {
+                errors.push(UserError {
+                    user,
+                    error: format!("Failed to parse {:?}: {}", path, e),
+                });
+                continue;
+            }
+        };
 
-        let shortcuts = obj.value_mut("Shortcuts");
-        if shortcuts.is_none() {
-            obj.set_value(
-                "Shortcuts".to_string(),
-                vdf::Value::Object(vdf::Object::new()),
-            );
+        let removed = shortcuts.remove_by_devkit_game_id(game_id);
+        if !removed.is_empty() {
+            if let Err(e) = write_shortcuts(&path, &shortcuts) {
+                errors.push(UserError { user, error: e });
+                continue;
+            }
         }
 
-        if let vdf::Value::Object(obj) = obj.value_mut("Shortcuts").unwrap() {
-            let mut found = false;
-
-            for value in obj.values_mut().iter_mut() {
-                if let vdf::Value::Object(obj) = &mut value.1 {
-                    let appid = obj.value("appid").unwrap();
-                    if let vdf::Value::Integer(appid) = appid {
-                        if *appid == id {
-                            update_shortcut(obj);
-                            found = true;
-                            break;
-                        }
-                    }
-                }
+        let mut removed_artwork = Vec::new();
+        for entry in removed.iter() {
+            if artwork {
+                removed_artwork.extend(remove_artwork(&user_dir, entry.appid));
             }
 
-            if !found {
-                let mut max = 0;
-                for value in obj.values() {
-                    let index = value.0.parse::<u32>().unwrap();
-                    max = index.max(max);
-                }
+            if !appids.contains(&entry.appid) {
+                appids.push(entry.appid);
+            }
+        }
+
+        results.push(RemovedShortcuts {
+            user,
+            appids: removed.iter().map(|e| e.appid).collect(),
+            artwork: removed_artwork,
+        });
+    }
+
+    let mut compat_tool_error = None;
+    let compat_tool = if compat_tool {
+        let path = get_steam_dir().join("config/config.vdf");
+        match remove_compat_tool_mappings(&path, &appids) {
+            Ok(removed) => removed,
+            Err(e) => {
+                compat_tool_error = Some(e);
+                Vec::new()
+            }
+        }
+    } else {
+        Vec::new()
+    };
+
+    let path = get_game_dir(game_id);
+    let removed_files = !keep_files && path.exists();
+    if removed_files {
+        std::fs::remove_dir_all(&path).map_err(|e| io_error(&path, e))?;
+    }
+
+    Ok(RemoveShortcutOutput {
+        game_id: game_id.to_string(),
+        removed_files,
+        users: results,
+        compat_tool,
+        compat_tool_error,
+        errors,
+    })
+}
+
+/// Check if the shortcut points inside the decker-games directory
+fn is_decker_shortcut(entry: &ShortcutEntry, games_dir: &Path) -> bool {
+    // NOTE(patrik): Steam adds quotes around the path when the user edits
+    // the shortcut
+    let exe = entry.exe.trim_matches('"');
 
-                let new_index = max + 1;
+    entry.devkit &&
+        !entry.devkit_game_id.is_empty() &&
+        Path::new(exe).starts_with(games_dir)
+}
 
-                let new_obj = create_shortcut_obj(
-                    id,
-                    game_id.clone(),
-                    app_name.clone(),
-                    exec.clone(),
-                    starting_dir.clone(),
-                );
+fn list() -> Result<List, Error> {
+    let games_dir = get_games_dir();
 
-                obj.set_value(
-                    new_index.to_string(),
-                    vdf::Value::Object(new_obj),
-                );
+    let mut games = Vec::new();
+    if let Ok(dir) = std::fs::read_dir(&games_dir) {
+        for entry in dir.flatten() {
+            let path = entry.path();
+            if !path.is_dir() {
+                continue;
             }
+
+            games.push(ListGame {
+                game_id: entry.file_name().to_string_lossy().to_string(),
+                path: path.to_string_lossy().to_string(),
+                size: get_dir_size(&path),
+                deployed_at: read_deployed_at(&path),
+                shortcuts: Vec::new(),
+                orphaned: true,
+            });
         }
+    }
+
+    games.sort_by(|a, b| a.game_id.cmp(&b.game_id));
+
+    let mut orphaned_shortcuts = Vec::new();
+    let mut errors = Vec::new();
+
+    for (user, user_dir) in get_user_dirs()? {
+        let path = user_dir.join("config/shortcuts.vdf");
+        if !path.exists() {
+            continue;
+        }
+
+        let data = match read_file_binary(&path) {
+            Ok(data) => data,
+            Err(e) => {
+                errors.push(UserError {
+                    user,
+                    error: format!("Failed to read {:?}: {}", path, e),
+                });
+                continue;
+            }
+        };
+
+        let shortcuts = match Shortcuts::parse(&data) {
+            Ok(shortcuts) => shortcuts,
+            Err(e) => {
+                errors.push(UserError {
+                    user,
+                    error: format!("Failed to parse {:?}: {}", path, e),
+                });
+                continue;
+            }
+        };
 
-        let new_data = vdf::write(&obj).unwrap();
-        write_file_binary(path, &new_data);
+        for entry in shortcuts.entries() {
+            if !is_decker_shortcut(entry, &games_dir) {
+                continue;
+            }
+
+            let shortcut = ListShortcut {
+                user: user.clone(),
+                game_id: entry.devkit_game_id.clone(),
+                appid: entry.appid,
+                exe: entry.exe.clone(),
+                start_dir: entry.start_dir.clone(),
+            };
+
+            match games.iter_mut().find(|g| g.game_id == shortcut.game_id) {
+                Some(game) => {
+                    game.orphaned = false;
+                    game.shortcuts.push(shortcut);
+                }
+                None => orphaned_shortcuts.push(shortcut),
+            }
+        }
     }
+
+    Ok(List {
+        games,
+        orphaned_shortcuts,
+        errors,
+    })
 }
 
-fn main() {
-    let args = Args::parse();
+/// The hash of our own binary, empty if it can't be read so decker uploads
+/// a new one
+fn self_hash() -> String {
+    std::fs::read("/proc/self/exe")
+        .map(|data| decker_protocol::hash(&data))
+        .unwrap_or_default()
+}
 
-    match args.command {
-        Command::Status => status(),
+/// Run the command of the request
+fn handle_command(command: Command) -> Result<Output, Error> {
+    if let Some(game_id) = command.game_id() {
+        check_game_id(game_id)?;
+    }
+
+    let output = match command {
+        Command::Hello => Output::Hello(Hello {
+            version: decker_protocol::VERSION,
+            util_version: env!("CARGO_PKG_VERSION").to_string(),
+            hash: self_hash(),
+        }),
+        Command::Status => Output::Status(status()),
+        Command::List => Output::List(list()?),
         Command::PrepareUpload {
             game_id,
             remove_old,
-        } => prepare_upload(game_id, remove_old),
-        Command::CreateShortcut {
+        } => Output::PrepareUpload(prepare_upload(&game_id, remove_old)?),
+        Command::CreateShortcut(request) => Output::CreateShortcut(
+            create_shortcut(ShortcutOptions::new(request)?)?,
+        ),
+        Command::RemoveShortcut {
             game_id,
-            exec,
-            starting_dir,
-        } => create_shortcut(game_id, exec, starting_dir),
+            keep_files,
+            artwork,
+            compat_tool,
+        } => Output::RemoveShortcut(remove_shortcut(
+            &game_id,
+            keep_files,
+            artwork,
+            compat_tool,
+        )?),
+        Command::Run { game_id, wait } => {
+            Output::Run(run::run(&game_id, wait)?)
+        }
+        Command::Kill {
+            game_id,
+            grace_period,
+        } => Output::Kill(kill::kill(&game_id, grace_period)?),
+        Command::Logs { game_id, run } => {
+            Output::Logs(run::logs(&game_id, run)?)
+        }
+    };
+
+    Ok(output)
+}
+
+/// Parse and run the request, the version is checked before the rest of
+/// the request gets parsed because a newer decker might send commands we
+/// don't know about
+fn handle_request(request: &str) -> Result<Output, Error> {
+    let invalid = |e: serde_json::Error| Error::InvalidRequest {
+        message: e.to_string(),
+    };
+
+    let value: serde_json::Value =
+        serde_json::from_str(request).map_err(invalid)?;
+
+    let version = value.get("version").and_then(|v| v.as_u64());
+    if version != Some(decker_protocol::VERSION as u64) {
+        return Err(Error::UnsupportedVersion {
+            expected: decker_protocol::VERSION,
+            got: version.unwrap_or(0) as u32,
+        });
+    }
+
+    let request: Request = serde_json::from_value(value).map_err(invalid)?;
+    handle_command(request.command)
+}
+
+fn main() {
+    let args = Args::parse();
+
+    if let Some(games_dir) = args.games_dir {
+        GAMES_DIR.set(games_dir).unwrap();
+    }
+
+    match args.command {
+        ArgCommand::Request { request } => {
+            let reply = match handle_request(&request) {
+                Ok(output) => Reply::Ok(output),
+                Err(e) => Reply::Error(e),
+            };
+
+            let failed = matches!(reply, Reply::Error(_));
+
+            let response = Response::new(reply);
+            println!("{}", serde_json::to_string(&response).unwrap());
+
+            if failed {
+                std::process::exit(1);
+            }
+        }
+
+        ArgCommand::Launch { game_dir, command } => {
+            std::process::exit(run::launch(&game_dir, &command));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::TempDir;
+
+    fn options() -> ShortcutOptions {
+        ShortcutOptions {
+            game_id: "game".to_string(),
+            app_name: "Game".to_string(),
+            exec: "/games/game/run.sh".to_string(),
+            starting_dir: "/games/game".to_string(),
+            launch_options: "%command%".to_string(),
+            icon: String::new(),
+            tags: vec!["Dev".to_string()],
+            artwork: Vec::new(),
+        }
+    }
+
+    fn text(s: &str) -> FieldValue {
+        FieldValue::Text(s.to_string())
+    }
+
+    fn list(tags: &[&str]) -> FieldValue {
+        FieldValue::List(tags.iter().map(|t| t.to_string()).collect())
+    }
+
+    #[test]
+    fn update_shortcut_unchanged() {
+        let mut entry = create_shortcut_entry(1, &options());
+        assert!(entry.devkit);
+        assert_eq!(entry.devkit_game_id, "game");
+
+        assert!(update_shortcut(&mut entry, &options()).is_empty());
+    }
+
+    #[test]
+    fn update_shortcut_changes() {
+        let mut entry = create_shortcut_entry(1, &options());
+        entry.last_play_time = 5;
+
+        let mut options = options();
+        options.app_name = "New Game".to_string();
+        options.exec = "/games/game/new.sh".to_string();
+        options.icon = "icon.png".to_string();
+
+        let changes = update_shortcut(&mut entry, &options);
+        assert_eq!(
+            changes.keys().collect::<Vec<_>>(),
+            ["AppName", "Exe", "icon"]
+        );
+        assert_eq!(
+            changes["Exe"],
+            FieldChange {
+                old: text("/games/game/run.sh"),
+                new: text("/games/game/new.sh"),
+            }
+        );
+        assert_eq!(changes["icon"].old, text(""));
+
+        assert_eq!(entry.app_name, "New Game");
+        assert_eq!(entry.exe, "/games/game/new.sh");
+        assert_eq!(entry.last_play_time, 5);
+    }
+
+    #[test]
+    fn update_shortcut_tags() {
+        let mut entry = create_shortcut_entry(1, &options());
+        entry.tags.push("Favorite".to_string());
+
+        // The tag dropped from the manifest and the tag the user added stay
+        let mut options = options();
+        options.tags = vec!["New".to_string()];
+
+        let changes = update_shortcut(&mut entry, &options);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(
+            changes["tags"],
+            FieldChange {
+                old: list(&["Dev", "Favorite"]),
+                new: list(&["Dev", "Favorite", "New"]),
+            }
+        );
+
+        assert!(update_shortcut(&mut entry, &options).is_empty());
+    }
+
+    #[test]
+    fn compat_tool_mappings() {
+        const CONFIG: &str = include_str!("../testdata/config.vdf");
+
+        let temp = TempDir::new("compat");
+        let dir = temp.path();
+
+        let path = dir.join("config.vdf");
+        std::fs::write(&path, CONFIG).unwrap();
+
+        let removed =
+            remove_compat_tool_mappings(&path, &[2147483649, 3012345678, 5])
+                .unwrap();
+        assert_eq!(removed, [2147483649, 3012345678]);
+
+        // Only the lines of the two entries are gone, the comment above the
+        // hand set entry is left
+        let entry = |appid: &str| {
+            let start = CONFIG.find(&format!("\t\"{}\"", appid)).unwrap();
+            let start = CONFIG[..start].rfind('\n').unwrap() + 1;
+            let end = start + CONFIG[start..].find("}\n").unwrap() + 2;
+            &CONFIG[start..end]
+        };
+        let expected = CONFIG
+            .replace(entry("2147483649"), "")
+            .replace(entry("3012345678"), "");
+        let data = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(data, expected);
+        assert!(data.contains("// Set by hand"));
+        assert!(data.contains("\"RunningOnWindows\"\t\t\"0\" [!$WIN32]"));
+
+        // Nothing left to remove so the file is not touched
+        assert!(remove_compat_tool_mappings(&path, &[2147483649])
+            .unwrap()
+            .is_empty());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), expected);
+
+        assert!(remove_compat_tool_mappings(&dir.join("missing"), &[1])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn write_file_replaces() {
+        let temp = TempDir::new("write");
+        let dir = temp.path();
+
+        let path = dir.join("shortcuts.vdf");
+        write_file_binary(&path, b"first").unwrap();
+        write_file_binary(&path, b"second").unwrap();
+        assert_eq!(read_file_binary(&path).unwrap(), b"second");
+
+        // Only the file itself is left
+        let names = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["shortcuts.vdf"]);
+
+        assert!(write_file_binary(dir.join("missing/file"), b"x").is_err());
+        assert!(read_file_binary(dir.join("missing")).is_err());
     }
 }
@@ -0,0 +1,274 @@
+//! Parser for the os-release file
+//!
+//! Follows https://www.freedesktop.org/software/systemd/man/os-release.html
+//! the file is a list of shell style KEY=VALUE assignments, blank lines and
+//! lines starting with '#' are ignored
+
+use std::collections::HashMap;
+
+use decker_protocol::OsRelease;
+
+/// Get the fields we care about, not all distros have all the fields so
+/// they are all optional
+pub fn parse(data: &str) -> OsRelease {
+    let mut fields = parse_fields(data);
+    let mut take = |key: &str| fields.remove(key);
+
+    OsRelease {
+        id: take("ID"),
+        name: take("NAME"),
+        pretty_name: take("PRETTY_NAME"),
+        build_id: take("BUILD_ID"),
+        variant_id: take("VARIANT_ID"),
+        version_id: take("VERSION_ID"),
+        version_codename: take("VERSION_CODENAME"),
+    }
+}
+
+/// Read the os-release file of the system, the spec says to fallback to
+/// /usr/lib/os-release if /etc/os-release doesn't exist
+pub fn read() -> Option<OsRelease> {
+    ["/etc/os-release", "/usr/lib/os-release"]
+        .iter()
+        .find_map(|path| std::fs::read_to_string(path).ok())
+        .map(|data| parse(&data))
+}
+
+/// Parse all the assignments in the file, invalid lines are skipped
+pub fn parse_fields(data: &str) -> HashMap<String, String> {
+    let mut result = HashMap::new();
+
+    for line in data.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // NOTE(patrik): Only split on the first '=' because the value can
+        // contain more of them
+        let (key, value) = match line.split_once('=') {
+            Some(split) => split,
+            None => continue,
+        };
+
+        if !is_valid_key(key) {
+            continue;
+        }
+
+        if let Some(value) = parse_value(value) {
+            result.insert(key.to_string(), value);
+        }
+    }
+
+    result
+}
+
+fn is_valid_key(key: &str) -> bool {
+    let mut chars = key.chars();
+
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Parse a shell style value, returns None if a quote is never closed
+fn parse_value(value: &str) -> Option<String> {
+    let mut result = String::new();
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            // Everything inside single quotes is literal
+            '\'' => loop {
+                match chars.next()? {
+                    '\'' => break,
+                    c => result.push(c),
+                }
+            },
+
+            // Inside double quotes a backslash only escapes the shell
+            // special characters, otherwise the backslash is kept
+            '"' => loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => match chars.next()? {
+                        c @ ('$' | '"' | '\\' | '`') => result.push(c),
+                        c => {
+                            result.push('\\');
+                            result.push(c);
+                        }
+                    },
+                    c => result.push(c),
+                }
+            },
+
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    result.push(c);
+                }
+            }
+
+            c => result.push(c),
+        }
+    }
+
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEAMOS: &str = r#"NAME="SteamOS"
+PRETTY_NAME="SteamOS"
+VERSION_CODENAME=holo
+ID=steamos
+ID_LIKE=arch
+ANSI_COLOR="1;35"
+HOME_URL="https://www.steampowered.com/"
+DOCUMENTATION_URL="https://support.steampowered.com/"
+SUPPORT_URL="https://support.steampowered.com/"
+BUG_REPORT_URL="https://support.steampowered.com/"
+LOGO=steamos
+VARIANT_ID=steamdeck
+VERSION_ID=3.4.6
+BUILD_ID=20230313.1
+"#;
+
+    const DEBIAN: &str = r#"PRETTY_NAME="Debian GNU/Linux 12 (bookworm)"
+NAME="Debian GNU/Linux"
+VERSION_ID="12"
+VERSION="12 (bookworm)"
+VERSION_CODENAME=bookworm
+ID=debian
+HOME_URL="https://www.debian.org/"
+SUPPORT_URL="https://www.debian.org/support"
+BUG_REPORT_URL="https://bugs.debian.org/"
+"#;
+
+    const FEDORA: &str = r#"NAME="Fedora Linux"
+VERSION="38 (Workstation Edition)"
+ID=fedora
+VERSION_ID=38
+VERSION_CODENAME=""
+PLATFORM_ID="platform:f38"
+PRETTY_NAME="Fedora Linux 38 (Workstation Edition)"
+ANSI_COLOR="0;38;2;60;110;180"
+LOGO=fedora-logo-icon
+CPE_NAME="cpe:/o:fedoraproject:fedora:38"
+DEFAULT_HOSTNAME="fedora"
+HOME_URL="https://fedoraproject.org/"
+SUPPORT_END=2024-05-14
+VARIANT="Workstation Edition"
+VARIANT_ID=workstation
+"#;
+
+    fn some(s: &str) -> Option<String> {
+        Some(s.to_string())
+    }
+
+    #[test]
+    fn steamos() {
+        let os = parse(STEAMOS);
+
+        assert_eq!(
+            os,
+            OsRelease {
+                id: some("steamos"),
+                name: some("SteamOS"),
+                pretty_name: some("SteamOS"),
+                build_id: some("20230313.1"),
+                variant_id: some("steamdeck"),
+                version_id: some("3.4.6"),
+                version_codename: some("holo"),
+            }
+        );
+    }
+
+    #[test]
+    fn debian_missing_fields() {
+        let os = parse(DEBIAN);
+
+        assert_eq!(os.id, some("debian"));
+        assert_eq!(os.pretty_name, some("Debian GNU/Linux 12 (bookworm)"));
+        assert_eq!(os.version_id, some("12"));
+        assert_eq!(os.version_codename, some("bookworm"));
+        assert_eq!(os.build_id, None);
+        assert_eq!(os.variant_id, None);
+    }
+
+    #[test]
+    fn fedora() {
+        let os = parse(FEDORA);
+
+        assert_eq!(os.id, some("fedora"));
+        assert_eq!(os.variant_id, some("workstation"));
+        assert_eq!(os.version_codename, some(""));
+        assert_eq!(os.build_id, None);
+    }
+
+    #[test]
+    fn comments_and_blank_lines() {
+        let data = "# A comment\n\n   \nID=arch\n  # Indented comment\n";
+        let fields = parse_fields(data);
+
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields["ID"], "arch");
+    }
+
+    #[test]
+    fn value_with_equals() {
+        let fields = parse_fields("CPE_NAME=\"a=b=c\"\nOTHER=x=y\n");
+
+        assert_eq!(fields["CPE_NAME"], "a=b=c");
+        assert_eq!(fields["OTHER"], "x=y");
+    }
+
+    #[test]
+    fn quoting() {
+        let data = r#"SINGLE='single "quoted" $value'
+DOUBLE="escaped \"quote\" \$dollar \\ \`tick\` \n"
+UNQUOTED=with\ space
+MIXED=a"b"'c'
+EMPTY=
+EMPTY_QUOTED=""
+"#;
+        let fields = parse_fields(data);
+
+        assert_eq!(fields["SINGLE"], r#"single "quoted" $value"#);
+        assert_eq!(fields["DOUBLE"], r#"escaped "quote" $dollar \ `tick` \n"#);
+        assert_eq!(fields["UNQUOTED"], "with space");
+        assert_eq!(fields["MIXED"], "abc");
+        assert_eq!(fields["EMPTY"], "");
+        assert_eq!(fields["EMPTY_QUOTED"], "");
+    }
+
+    #[test]
+    fn short_values() {
+        let fields = parse_fields("A=a\nB=\"\nC='\nD=\"d\"\n");
+
+        assert_eq!(fields["A"], "a");
+        assert_eq!(fields["D"], "d");
+
+        // NOTE(patrik): Unterminated quotes are invalid
+        assert!(!fields.contains_key("B"));
+        assert!(!fields.contains_key("C"));
+    }
+
+    #[test]
+    fn invalid_lines() {
+        let data = "no equals sign\n=value\n1KEY=value\nBAD-KEY=value\nOK=1\n";
+        let fields = parse_fields(data);
+
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields["OK"], "1");
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(parse(""), OsRelease::default());
+    }
+}
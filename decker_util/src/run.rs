@@ -0,0 +1,552 @@
+//! Starting games through Steam and keeping track of the runs
+//!
+//! The launch options of the shortcut puts 'decker_util launch' in front of
+//! the game, the launcher records every run in .decker/runs/<run>.json with
+//! the exit code of the game so decker can wait for the game to exit. The
+//! output of the game goes to .decker/logs/<run>.log and to the launcher's
+//! own stdout and stderr.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use vdf::shortcuts::Shortcuts;
+
+use decker_protocol::{Error, LogsOutput, RunInfo, RunOutput};
+
+use crate::{get_game_dir, get_user_dirs, DECKER_META_DIR};
+
+/// How long Steam gets to start the game before we give up waiting
+const START_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often the run file is checked when waiting for the game
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long the launcher gets to write the run file after it claimed the
+/// run
+const RECORD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the output is drained after the game exited
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn get_runs_dir(game_dir: &Path) -> PathBuf {
+    game_dir.join(DECKER_META_DIR).join("runs")
+}
+
+fn get_logs_dir(game_dir: &Path) -> PathBuf {
+    game_dir.join(DECKER_META_DIR).join("logs")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Get the number of the latest run, None if the game never ran
+fn latest_run(runs_dir: &Path) -> Option<u32> {
+    std::fs::read_dir(runs_dir)
+        .ok()?
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name();
+            let name = name.to_str()?;
+            name.strip_suffix(".json")?.parse().ok()
+        })
+        .max()
+}
+
+fn read_run(runs_dir: &Path, run: u32) -> Option<RunInfo> {
+    let data = std::fs::read(runs_dir.join(format!("{}.json", run))).ok()?;
+    serde_json::from_slice(&data).ok()
+}
+
+fn write_run(runs_dir: &Path, info: &RunInfo) -> std::io::Result<()> {
+    // NOTE(patrik): Write to a temporary file and rename it so a reader
+    // never sees a half written file
+    let path = runs_dir.join(format!("{}.json", info.run));
+    let temp = runs_dir.join(format!(".{}.json.tmp", info.run));
+    std::fs::write(&temp, serde_json::to_string(info)?)?;
+    std::fs::rename(temp, path)
+}
+
+/// Claim the next run number, the file is created right away so two
+/// launches at the same time gets different numbers
+fn claim_run(runs_dir: &Path) -> std::io::Result<u32> {
+    let mut run = latest_run(runs_dir).unwrap_or(0) + 1;
+    loop {
+        let path = runs_dir.join(format!("{}.json", run));
+        match OpenOptions::new().write(true).create_new(true).open(path) {
+            Ok(_) => return Ok(run),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                run += 1
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// A run that is being recorded in the runs and logs directories
+struct Record {
+    runs_dir: PathBuf,
+    info: RunInfo,
+    log: Arc<Mutex<File>>,
+}
+
+impl Record {
+    fn start(game_dir: &Path) -> std::io::Result<Self> {
+        let runs_dir = get_runs_dir(game_dir);
+        std::fs::create_dir_all(&runs_dir)?;
+
+        let logs_dir = get_logs_dir(game_dir);
+        std::fs::create_dir_all(&logs_dir)?;
+
+        let info = RunInfo {
+            run: claim_run(&runs_dir)?,
+            pid: std::process::id(),
+            started_at: now(),
+            finished_at: None,
+            exit_code: None,
+        };
+        write_run(&runs_dir, &info)?;
+
+        let log = File::create(logs_dir.join(format!("{}.log", info.run)))?;
+
+        Ok(Self {
+            runs_dir,
+            info,
+            log: Arc::new(Mutex::new(log)),
+        })
+    }
+
+    fn finish(mut self, exit_code: i32) -> std::io::Result<()> {
+        self.info.finished_at = Some(now());
+        self.info.exit_code = Some(exit_code);
+        write_run(&self.runs_dir, &self.info)
+    }
+}
+
+/// Put the launcher in front of %command% in the launch options, without
+/// %command% Steam adds the command in front of the launch options
+pub fn wrap_launch_options(launch_options: &str, game_dir: &Path) -> String {
+    let exe = std::env::current_exe().unwrap();
+    let launcher = format!(
+        "{} launch {} -- %command%",
+        decker_protocol::shell_quote(&exe.to_string_lossy()),
+        decker_protocol::shell_quote(&game_dir.to_string_lossy()),
+    );
+
+    if launch_options.contains("%command%") {
+        launch_options.replacen("%command%", &launcher, 1)
+    } else if launch_options.is_empty() {
+        launcher
+    } else {
+        format!("{} {}", launcher, launch_options)
+    }
+}
+
+/// Copy everything from the pipe to the output and the log
+fn tee<R, W>(
+    mut pipe: R,
+    mut output: W,
+    log: Option<Arc<Mutex<File>>>,
+) -> JoinHandle<()>
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    std::thread::spawn(move || {
+        let mut buf = [0; 8192];
+        loop {
+            let n = match pipe.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+
+            // NOTE(patrik): Nobody might be reading the output of the
+            // launcher, the log is what matters
+            let _ = output.write_all(&buf[..n]);
+            let _ = output.flush();
+
+            if let Some(log) = &log {
+                let _ = log.lock().unwrap().write_all(&buf[..n]);
+            }
+        }
+    })
+}
+
+/// Wait for the threads to finish until the deadline, the threads that are
+/// still running are left behind and end with the process
+fn join_until(threads: Vec<JoinHandle<()>>, deadline: Instant) {
+    for thread in threads {
+        while !thread.is_finished() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        if thread.is_finished() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Run the game and record the run, returns the exit code for the
+/// launcher to exit with
+pub fn launch(game_dir: &Path, command: &[String]) -> i32 {
+    // NOTE(patrik): The game still starts when the run can't be recorded,
+    // i.e. when the disk is full, it just can't be waited on or logged
+    let record = match Record::start(game_dir) {
+        Ok(record) => Some(record),
+        Err(e) => {
+            eprintln!("decker_util: failed to record the run: {}", e);
+            None
+        }
+    };
+    let log = record.as_ref().map(|record| record.log.clone());
+
+    let child = std::process::Command::new(&command[0])
+        .args(&command[1..])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+
+    let exit_code = match child {
+        Ok(mut child) => {
+            let stdout = tee(
+                child.stdout.take().unwrap(),
+                std::io::stdout(),
+                log.clone(),
+            );
+            let stderr = tee(
+                child.stderr.take().unwrap(),
+                std::io::stderr(),
+                log.clone(),
+            );
+
+            let status = child.wait().unwrap();
+
+            // NOTE(patrik): The pipes close when the game exits unless the
+            // game left processes behind that still has them open, i.e.
+            // wineserver, so only drain the output for a little while
+            join_until(vec![stdout, stderr], Instant::now() + DRAIN_TIMEOUT);
+
+            status
+                .code()
+                .or_else(|| status.signal().map(|signal| 128 + signal))
+                .unwrap_or(1)
+        }
+        Err(e) => {
+            let message = format!(
+                "decker_util: failed to run {:?}: {}\n",
+                command[0], e
+            );
+            eprint!("{}", message);
+            if let Some(log) = &log {
+                let _ = log.lock().unwrap().write_all(message.as_bytes());
+            }
+            127
+        }
+    };
+
+    if let Some(record) = record {
+        if let Err(e) = record.finish(exit_code) {
+            eprintln!("decker_util: failed to record the exit code: {}", e);
+        }
+    }
+
+    exit_code
+}
+
+/// Find the appid of the shortcut for the game, all users gets the same
+/// appid so the first shortcut wins
+fn find_appid(game_id: &str) -> Result<u32, Error> {
+    for (_, user_dir) in get_user_dirs()? {
+        let path = user_dir.join("config/shortcuts.vdf");
+        let data = match std::fs::read(&path) {
+            Ok(data) => data,
+            Err(_) => continue,
+        };
+
+        // NOTE(patrik): A broken file for one user shouldn't stop the game
+        // from starting for the other users
+        if let Ok(shortcuts) = Shortcuts::parse(&data) {
+            if let Some(entry) = shortcuts.find_by_devkit_game_id(game_id) {
+                return Ok(entry.appid);
+            }
+        }
+    }
+
+    Err(Error::ShortcutNotFound {
+        game_id: game_id.to_string(),
+    })
+}
+
+/// Ask the running Steam client to start the shortcut
+fn start_through_steam(appid: u32) -> Result<(), Error> {
+    // NOTE(patrik): The game id of a non-Steam shortcut is the appid in the
+    // upper 32 bits with the shortcut flag set
+    let steam_game_id = ((appid as u64) << 32) | 0x02000000;
+    let url = format!("steam://rungameid/{}", steam_game_id);
+
+    let status = std::process::Command::new("steam")
+        .arg(&url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_err(|e| Error::LaunchFailed {
+            message: format!("failed to run steam: {}", e),
+        })?;
+
+    if !status.success() {
+        return Err(Error::LaunchFailed {
+            message: format!("steam {} exited with {}", url, status),
+        });
+    }
+
+    Ok(())
+}
+
+/// How long ago the run file was last written
+fn claimed_for(runs_dir: &Path, run: u32) -> Duration {
+    std::fs::metadata(runs_dir.join(format!("{}.json", run)))
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .unwrap_or(Duration::MAX)
+}
+
+fn is_process_alive(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+/// Wait for the launcher to record a run after the previous run and then
+/// for the run to finish
+fn wait_for_run(
+    runs_dir: &Path,
+    previous: Option<u32>,
+) -> Result<RunInfo, Error> {
+    let started = Instant::now();
+    let run = loop {
+        match latest_run(runs_dir) {
+            Some(run) if Some(run) > previous => break run,
+            _ => {}
+        }
+
+        if started.elapsed() > START_TIMEOUT {
+            return Err(Error::LaunchFailed {
+                message: format!(
+                    "the game didn't start within {} seconds",
+                    START_TIMEOUT.as_secs()
+                ),
+            });
+        }
+
+        std::thread::sleep(POLL_INTERVAL);
+    };
+
+    loop {
+        // NOTE(patrik): The file is empty until the launcher wrote it, if
+        // it stays empty the launcher died right after it claimed the run
+        let info = match read_run(runs_dir, run) {
+            Some(info) => info,
+            None if claimed_for(runs_dir, run) > RECORD_TIMEOUT => {
+                return Err(Error::LaunchFailed {
+                    message: format!(
+                        "the launcher never recorded run {}",
+                        run
+                    ),
+                });
+            }
+            None => {
+                std::thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+        };
+
+        if info.exit_code.is_some() {
+            return Ok(info);
+        }
+
+        // NOTE(patrik): The launcher got killed before it could write the
+        // exit code, the game is most likely gone with it
+        if !is_process_alive(info.pid) {
+            return Ok(info);
+        }
+
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Find the log of the run, the latest run when no run is given
+pub fn logs(game_id: &str, run: Option<u32>) -> Result<LogsOutput, Error> {
+    let game_dir = get_game_dir(game_id);
+    let runs_dir = get_runs_dir(&game_dir);
+
+    let not_found = || Error::RunNotFound {
+        game_id: game_id.to_string(),
+        run,
+    };
+
+    let number = run
+        .or_else(|| latest_run(&runs_dir))
+        .ok_or_else(not_found)?;
+    let info = read_run(&runs_dir, number).ok_or_else(not_found)?;
+    let path = get_logs_dir(&game_dir).join(format!("{}.log", number));
+
+    Ok(LogsOutput {
+        run: info,
+        path: path.to_string_lossy().to_string(),
+    })
+}
+
+pub fn run(game_id: &str, wait: bool) -> Result<RunOutput, Error> {
+    let appid = find_appid(game_id)?;
+
+    if !crate::is_steam_running() {
+        return Err(Error::SteamNotRunning);
+    }
+
+    let game_dir = get_game_dir(game_id);
+    let runs_dir = get_runs_dir(&game_dir);
+    let previous = latest_run(&runs_dir);
+
+    start_through_steam(appid)?;
+
+    let run = if wait {
+        Some(wait_for_run(&runs_dir, previous)?)
+    } else {
+        None
+    };
+
+    Ok(RunOutput { appid, run })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn launch_options() {
+        let exe = std::env::current_exe().unwrap();
+        let launcher = format!(
+            "{} launch '/games/Test Game' -- %command%",
+            decker_protocol::shell_quote(&exe.to_string_lossy())
+        );
+        let dir = Path::new("/games/Test Game");
+
+        assert_eq!(wrap_launch_options("", dir), launcher);
+        assert_eq!(
+            wrap_launch_options("A=1 %command% --fast", dir),
+            format!("A=1 {} --fast", launcher)
+        );
+        assert_eq!(
+            wrap_launch_options("--fast", dir),
+            format!("{} --fast", launcher)
+        );
+    }
+
+    #[test]
+    fn runs() {
+        let temp = TempDir::new("runs");
+        let dir = temp.path();
+        let runs_dir = get_runs_dir(dir);
+
+        assert_eq!(latest_run(&runs_dir), None);
+
+        let code = launch(
+            dir,
+            &["sh".to_string(), "-c".to_string(), "exit 3".to_string()],
+        );
+        assert_eq!(code, 3);
+
+        let code = launch(
+            dir,
+            &["sh".to_string(), "-c".to_string(), "kill -9 $$".to_string()],
+        );
+        assert_eq!(code, 128 + 9);
+
+        let code = launch(dir, &["/nonexistent/game".to_string()]);
+        assert_eq!(code, 127);
+
+        let code = launch(
+            dir,
+            &[
+                "sh".to_string(),
+                "-c".to_string(),
+                "echo out; echo err >&2".to_string(),
+            ],
+        );
+        assert_eq!(code, 0);
+
+        // A process left behind with the pipes open doesn't keep the
+        // launcher running
+        let started = Instant::now();
+        let code = launch(
+            dir,
+            &[
+                "sh".to_string(),
+                "-c".to_string(),
+                "sleep 5 & echo left".to_string(),
+            ],
+        );
+        assert_eq!(code, 0);
+        assert!(started.elapsed() < Duration::from_secs(4));
+
+        assert_eq!(latest_run(&runs_dir), Some(5));
+        let info = read_run(&runs_dir, 1).unwrap();
+        assert_eq!(info.run, 1);
+        assert_eq!(info.exit_code, Some(3));
+        assert!(info.finished_at.is_some());
+
+        // A run that was claimed but never written gives up
+        let claimed = File::create(runs_dir.join("6.json")).unwrap();
+        claimed
+            .set_modified(SystemTime::now() - RECORD_TIMEOUT * 2)
+            .unwrap();
+        assert!(matches!(
+            wait_for_run(&runs_dir, Some(5)),
+            Err(Error::LaunchFailed { .. })
+        ));
+        std::fs::remove_file(runs_dir.join("6.json")).unwrap();
+
+        // A run that is already recorded is found right away
+        let info = wait_for_run(&runs_dir, Some(2)).unwrap();
+        assert_eq!(info.run, 5);
+        assert_eq!(info.exit_code, Some(0));
+
+        // The output of the game ends up in the log of the run
+        let logs_dir = get_logs_dir(dir);
+        let log = std::fs::read_to_string(logs_dir.join("4.log")).unwrap();
+        assert!(log.contains("out\n"));
+        assert!(log.contains("err\n"));
+
+        let log = std::fs::read_to_string(logs_dir.join("5.log")).unwrap();
+        assert_eq!(log, "left\n");
+
+        let log = std::fs::read_to_string(logs_dir.join("3.log")).unwrap();
+        assert!(log.contains("failed to run \"/nonexistent/game\""));
+    }
+
+    #[test]
+    fn launch_without_record() {
+        // NOTE(patrik): A file where the game directory should be so the
+        // meta directory can't be created
+        let temp = TempDir::new("without_record");
+        let dir = temp.path().join("not_a_dir");
+        std::fs::write(&dir, "").unwrap();
+
+        let code = launch(
+            &dir,
+            &["sh".to_string(), "-c".to_string(), "exit 4".to_string()],
+        );
+        assert_eq!(code, 4);
+    }
+}
@@ -0,0 +1,36 @@
+//! Helpers shared by the tests
+
+use std::path::{Path, PathBuf};
+
+/// A directory in the temp directory that is removed when dropped, even
+/// when the test panics
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "decker_util_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+
+        // NOTE(patrik): The links in /proc are canonical paths
+        let path = path.canonicalize().unwrap();
+
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,211 @@
+//! Running decker_util on the devkit, decker_util gets uploaded before it's
+//! used and every call is a request/response from decker_protocol
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+use decker_protocol::{Command, Output, Reply, Request, Response};
+
+use crate::device::Device;
+use crate::transport::{RemoteError, Transport};
+use crate::remote_command::RemoteCommand;
+use crate::{Error, Result};
+
+/// The helper program we send to the devkit, build.rs leaves it empty when
+/// decker is built without it
+const DECKER_UTIL_PROGRAM: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/decker_util"));
+
+/// Where decker_util gets uploaded to on the devkit
+const DECKER_UTIL_PATH: &str = "~/decker/decker_util";
+
+/// A decker_util call that failed
+#[derive(Debug)]
+pub enum UtilError {
+    /// decker_util didn't print a response, it probably crashed
+    NoResponse(RemoteError),
+
+    /// The response couldn't be parsed
+    InvalidResponse(serde_json::Error),
+
+    /// decker_util speaks a different version of the protocol
+    VersionMismatch { expected: u32, got: u32 },
+
+    /// decker_util ran the command but it failed
+    Failed(decker_protocol::Error),
+
+    /// The response was for a different command
+    UnexpectedOutput,
+}
+
+impl std::fmt::Display for UtilError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UtilError::NoResponse(_) => {
+                write!(f, "decker_util exited without a response")
+            }
+            UtilError::InvalidResponse(_) => {
+                write!(f, "failed to parse the response from decker_util")
+            }
+            UtilError::VersionMismatch { expected, got } => write!(
+                f,
+                "decker_util speaks protocol version {} but decker needs \
+                 version {}",
+                got, expected
+            ),
+            UtilError::Failed(e) => write!(f, "{}", e),
+            UtilError::UnexpectedOutput => {
+                write!(f, "decker_util answered with the wrong output")
+            }
+        }
+    }
+}
+
+impl std::error::Error for UtilError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            UtilError::NoResponse(e) => Some(e),
+            UtilError::InvalidResponse(e) => Some(e),
+            UtilError::VersionMismatch { .. } |
+            UtilError::Failed(_) |
+            UtilError::UnexpectedOutput => None,
+        }
+    }
+}
+
+/// Get the decker_util to upload, when it isn't embedded it's read from
+/// DECKER_UTIL or from next to the decker executable
+fn program() -> Result<Cow<'static, [u8]>> {
+    if !DECKER_UTIL_PROGRAM.is_empty() {
+        return Ok(Cow::Borrowed(DECKER_UTIL_PROGRAM));
+    }
+
+    let path = match std::env::var_os("DECKER_UTIL") {
+        Some(path) => PathBuf::from(path),
+        None => std::env::current_exe()
+            .map(|exe| exe.with_file_name("decker_util"))
+            .unwrap_or_else(|_| PathBuf::from("decker_util")),
+    };
+
+    std::fs::read(&path)
+        .map(Cow::Owned)
+        .map_err(|e| Error::FailedToReadUtil(path, e))
+}
+
+/// Check if the devkit already has our decker_util, anything other than a
+/// matching hello means it needs to be uploaded
+fn is_up_to_date(
+    device: &Device,
+    transport: &dyn Transport,
+    program: &[u8],
+) -> bool {
+    let output =
+        call(device, transport, Command::Hello, Error::FailedToHandshake);
+    match output {
+        Ok(Output::Hello(hello)) => {
+            hello.version == decker_protocol::VERSION &&
+                hello.hash == decker_protocol::hash(program)
+        }
+        _ => false,
+    }
+}
+
+/// Upload decker_util to the devkit unless it already has the same binary
+/// and make sure it speaks our version of the protocol
+pub fn upload(device: &Device, transport: &dyn Transport) -> Result<()> {
+    let program = program()?;
+    if is_up_to_date(device, transport, &program) {
+        return Ok(());
+    }
+
+    transport
+        .execute(&RemoteCommand::new("mkdir").arg("-p").path("~/decker"))?
+        .check()
+        .map_err(Error::FailedToCreateUtilDir)?;
+
+    let temp_file = mktemp::Temp::new_file().unwrap();
+    let mut file = File::create(&temp_file).unwrap();
+    file.write_all(&program).unwrap();
+
+    // NOTE(patrik): Upload to a temporary name and rename it when it's
+    // complete, an interrupted upload should never leave a broken
+    // decker_util behind
+    let upload_path =
+        format!("{}.{}.tmp", DECKER_UTIL_PATH, std::process::id());
+
+    transport
+        .upload(&temp_file, &upload_path)
+        .map_err(|e| Error::FailedToUploadUtil(Box::new(e)))?;
+    transport
+        .execute(&RemoteCommand::new("chmod").arg("+x").path(&upload_path))?
+        .check()
+        .map_err(Error::FailedToMakeUtilExecutable)?;
+    transport
+        .execute(
+            &RemoteCommand::new("mv")
+                .arg("-f")
+                .path(&upload_path)
+                .path(DECKER_UTIL_PATH),
+        )?
+        .check()
+        .map_err(Error::FailedToInstallUtil)?;
+
+    let output =
+        call(device, transport, Command::Hello, Error::FailedToHandshake)?;
+    let error = match output {
+        Output::Hello(hello) if hello.version == decker_protocol::VERSION => {
+            return Ok(());
+        }
+        Output::Hello(hello) => UtilError::VersionMismatch {
+            expected: decker_protocol::VERSION,
+            got: hello.version,
+        },
+        _ => UtilError::UnexpectedOutput,
+    };
+
+    Err(Error::FailedToHandshake(error))
+}
+
+/// Send the command to decker_util, the errors from decker_util gets
+/// wrapped with the step error so the user knows what failed
+pub fn call(
+    device: &Device,
+    transport: &dyn Transport,
+    command: Command,
+    step: fn(UtilError) -> Error,
+) -> Result<Output> {
+    let request = serde_json::to_string(&Request::new(command)).unwrap();
+    let cmd = RemoteCommand::new(DECKER_UTIL_PATH)
+        .arg("--games-dir")
+        .path(&device.install_root())
+        .arg("request")
+        .arg(&request);
+
+    let output = transport.execute(&cmd)?;
+
+    // NOTE(patrik): decker_util exits with an error code when the command
+    // fails but there is still a response to read
+    let response: Response = match serde_json::from_slice(&output.stdout) {
+        Ok(response) => response,
+        Err(e) => {
+            return match output.check() {
+                Ok(_) => Err(step(UtilError::InvalidResponse(e))),
+                Err(e) => Err(step(UtilError::NoResponse(e))),
+            }
+        }
+    };
+
+    if response.version != decker_protocol::VERSION {
+        return Err(step(UtilError::VersionMismatch {
+            expected: decker_protocol::VERSION,
+            got: response.version,
+        }));
+    }
+
+    match response.reply {
+        Reply::Ok(output) => Ok(output),
+        Reply::Error(e) => Err(step(UtilError::Failed(e))),
+    }
+}
@@ -0,0 +1,272 @@
+//! Device profiles, stored in devices.toml inside the data directory so the
+//! devkit doesn't need to be given on every command
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::devkit::DevkitClient;
+use crate::{Error, Result};
+
+/// Name of the config file inside the data directory
+const CONFIG_FILE: &str = "devices.toml";
+
+fn default_port() -> u16 {
+    22
+}
+
+fn default_devkit_port() -> u16 {
+    crate::devkit::DEFAULT_PORT
+}
+
+/// The user we fallback to if the devkit service can't tell us
+const DEFAULT_USERNAME: &str = "deck";
+
+/// A devkit we can connect to
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Device {
+    pub address: String,
+
+    /// The ssh port
+    #[serde(default = "default_port")]
+    pub port: u16,
+
+    /// The user to login as, the devkit service gets asked if it's not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+
+    /// The port of the devkit service
+    #[serde(default = "default_devkit_port")]
+    pub devkit_port: u16,
+
+    /// The private ssh key, defaults to the key decker creates
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<PathBuf>,
+
+    /// The directory the games gets installed to, defaults to
+    /// /home/<username>/decker-games
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub install_root: Option<String>,
+}
+
+impl Device {
+    pub fn new(address: String) -> Self {
+        Self {
+            address,
+            port: default_port(),
+            username: None,
+            devkit_port: default_devkit_port(),
+            key: None,
+            install_root: None,
+        }
+    }
+
+    pub fn username(&self) -> &str {
+        self.username.as_deref().unwrap_or(DEFAULT_USERNAME)
+    }
+
+    /// The user@address used by ssh
+    pub fn host(&self) -> String {
+        format!("{}@{}", self.username(), self.address)
+    }
+
+    pub fn devkit_client(&self) -> DevkitClient {
+        DevkitClient::new(&self.address, self.devkit_port)
+    }
+
+    /// Ask the devkit service for the user to login as if it's not set,
+    /// falls back to "deck" if the service can't be reached
+    pub fn resolve_username(&mut self) {
+        if self.username.is_none() {
+            self.username = self.devkit_client().login_name().ok();
+        }
+    }
+
+    pub fn key_path(&self) -> PathBuf {
+        self.key.clone().unwrap_or_else(crate::get_private_key_path)
+    }
+
+    pub fn install_root(&self) -> String {
+        match &self.install_root {
+            Some(root) => root.clone(),
+            None => format!("/home/{}/decker-games", self.username()),
+        }
+    }
+}
+
+/// The content of devices.toml
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Config {
+    /// Name of the device used when '-d' is not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+
+    #[serde(default)]
+    pub devices: BTreeMap<String, Device>,
+}
+
+impl Config {
+    pub fn path() -> PathBuf {
+        crate::get_data_dir().join(CONFIG_FILE)
+    }
+
+    /// Load the config, no config file is the same as an empty config
+    pub fn load() -> Result<Self> {
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let data = std::fs::read_to_string(path)
+            .map_err(Error::FailedToReadDeviceConfig)?;
+
+        toml::from_str(&data).map_err(Error::FailedToParseDeviceConfig)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let data = toml::to_string(self)
+            .map_err(Error::FailedToSerializeDeviceConfig)?;
+
+        std::fs::write(Self::path(), data)
+            .map_err(Error::FailedToWriteDeviceConfig)
+    }
+
+    /// Get the device to use from the '-d' argument, it can be the name of
+    /// a device, an address or the hostname of a devkit found with mDNS.
+    /// Without '-d' the default device is used
+    pub fn resolve(&self, name_or_addr: Option<&str>) -> Result<Device> {
+        match name_or_addr {
+            Some(name) => match self.devices.get(name) {
+                Some(device) => Ok(device.clone()),
+                None => {
+                    // NOTE(patrik): Fallback to the name if we can't find the
+                    // devkit, it might still resolve through DNS
+                    let address = match crate::mdns::find_address(name) {
+                        Some(address) => address.to_string(),
+                        None => name.to_string(),
+                    };
+
+                    Ok(Device::new(address))
+                }
+            },
+
+            None => {
+                let name = self
+                    .default
+                    .as_ref()
+                    .ok_or(Error::MissingDevkitAddress)?;
+
+                self.devices
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| Error::UnknownDevice(name.clone()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+default = "deck"
+
+[devices.deck]
+address = "192.168.1.10"
+
+[devices.other]
+address = "192.168.1.20"
+port = 2222
+username = "dev"
+devkit_port = 32010
+key = "/keys/id_other"
+install_root = "/data/games"
+"#;
+
+    #[test]
+    fn defaults() {
+        let device = Device::new("192.168.1.10".to_string());
+        assert_eq!(device.port, 22);
+        assert_eq!(device.devkit_port, crate::devkit::DEFAULT_PORT);
+        assert_eq!(device.username(), "deck");
+        assert_eq!(device.host(), "deck@192.168.1.10");
+        assert_eq!(device.install_root(), "/home/deck/decker-games");
+        assert_eq!(device.key_path(), crate::get_private_key_path());
+    }
+
+    #[test]
+    fn game_dir_follows_the_user() {
+        let mut device = Device::new("192.168.1.10".to_string());
+        device.username = Some("dev".to_string());
+        assert_eq!(device.install_root(), "/home/dev/decker-games");
+        assert_eq!(device.host(), "dev@192.168.1.10");
+
+        device.install_root = Some("/data/games".to_string());
+        assert_eq!(device.install_root(), "/data/games");
+    }
+
+    #[test]
+    fn parse_config() {
+        let config: Config = toml::from_str(CONFIG).unwrap();
+
+        // Missing values gets the same defaults as a new device
+        let deck = &config.devices["deck"];
+        assert_eq!(deck.port, 22);
+        assert_eq!(deck.devkit_port, crate::devkit::DEFAULT_PORT);
+        assert!(deck.username.is_none());
+        assert_eq!(deck.key_path(), crate::get_private_key_path());
+        assert_eq!(deck.install_root(), "/home/deck/decker-games");
+
+        let other = &config.devices["other"];
+        assert_eq!(other.port, 2222);
+        assert_eq!(other.devkit_port, 32010);
+        assert_eq!(other.username(), "dev");
+        assert_eq!(other.key_path(), PathBuf::from("/keys/id_other"));
+        assert_eq!(other.install_root(), "/data/games");
+
+        // The unset options are left out when saved
+        let data = toml::to_string(&config).unwrap();
+        let deck = data.split("[devices.deck]").nth(1).unwrap();
+        let deck = deck.split("[devices.other]").next().unwrap();
+        assert!(!deck.contains("username"));
+        assert!(!deck.contains("key"));
+        assert!(!deck.contains("install_root"));
+    }
+
+    #[test]
+    fn resolve() {
+        let config: Config = toml::from_str(CONFIG).unwrap();
+
+        assert_eq!(config.resolve(None).unwrap().address, "192.168.1.10");
+        assert_eq!(
+            config.resolve(Some("other")).unwrap().address,
+            "192.168.1.20"
+        );
+
+        // An address that isn't a device gets the defaults
+        let device = config.resolve(Some("10.0.0.1")).unwrap();
+        assert_eq!(device.address, "10.0.0.1");
+        assert_eq!(device.port, 22);
+        assert!(device.username.is_none());
+    }
+
+    #[test]
+    fn resolve_errors() {
+        let config = Config::default();
+        assert!(matches!(
+            config.resolve(None),
+            Err(Error::MissingDevkitAddress)
+        ));
+
+        let config = Config {
+            default: Some("gone".to_string()),
+            devices: BTreeMap::new(),
+        };
+        assert!(matches!(
+            config.resolve(None),
+            Err(Error::UnknownDevice(name)) if name == "gone"
+        ));
+    }
+}
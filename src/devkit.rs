@@ -0,0 +1,261 @@
+//! Client for the HTTP service that runs on a devkit
+//!
+//! The service is what the official devkit client talks to, it has the
+//! following endpoints:
+//!   - GET  /login-name       The user we should ssh in as
+//!   - GET  /properties.json  Information about the devkit
+//!   - POST /register         Add a ssh public key to the authorized keys
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::{Error, Result};
+
+/// The port the devkit service listens on by default
+pub const DEFAULT_PORT: u16 = 32000;
+
+pub struct DevkitClient {
+    base_url: String,
+    client: reqwest::blocking::Client,
+}
+
+impl DevkitClient {
+    pub fn new(address: &str, port: u16) -> Self {
+        // NOTE(patrik): IPv6 addresses needs brackets in the url
+        let base_url = if address.contains(':') {
+            format!("http://[{}]:{}", address, port)
+        } else {
+            format!("http://{}:{}", address, port)
+        };
+
+        // NOTE(patrik): Don't hang for long if the service isn't running,
+        // the login name gets asked for on every command
+        let client = reqwest::blocking::Client::builder()
+            .connect_timeout(Duration::from_secs(5))
+            .build()
+            .unwrap();
+
+        Self { base_url, client }
+    }
+
+    fn get(&self, path: &str) -> Result<reqwest::blocking::Response> {
+        let url = format!("{}{}", self.base_url, path);
+
+        let res = self
+            .client
+            .get(url)
+            .send()
+            .map_err(Error::DevkitRequestFailed)?;
+
+        if res.status().is_success() {
+            Ok(res)
+        } else {
+            Err(Error::DevkitRequestUnknownStatus(res.status().as_u16()))
+        }
+    }
+
+    /// Get the name of the user we should login as
+    pub fn login_name(&self) -> Result<String> {
+        let res = self.get("/login-name")?;
+        let name = res.text().map_err(Error::DevkitRequestFailed)?;
+
+        Ok(name.trim().to_string())
+    }
+
+    /// Get the properties of the devkit
+    pub fn properties(&self) -> Result<BTreeMap<String, Value>> {
+        let res = self.get("/properties.json")?;
+        let text = res.text().map_err(Error::DevkitRequestFailed)?;
+
+        serde_json::from_str(&text).map_err(Error::FailedToParseDevkitResponse)
+    }
+
+    /// Register the public key so we can ssh into the devkit
+    pub fn register(&self, public_key: &str) -> Result<()> {
+        let url = format!("{}/register", self.base_url);
+
+        let res = self
+            .client
+            .post(url)
+            .body(public_key.to_string())
+            .send()
+            .map_err(Error::RegisterRequestFailed)?;
+
+        if res.status().is_client_error() {
+            let res = res
+                .text()
+                .map_err(Error::FailedToRetriveRegisterRequestText)?;
+            let res: Value = serde_json::from_str(&res)
+                .map_err(Error::FailedToParseErrorJson)?;
+            if let Some(error) = res.get("error") {
+                if let Some(error) = error.as_str() {
+                    Err(Error::FailedToRegister(error.to_owned()))
+                } else {
+                    Err(Error::FailedToRegisterWithoutMessage)
+                }
+            } else {
+                Err(Error::FailedToRegisterWithoutMessage)
+            }
+        } else if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(Error::RegisterRequestUnknownStatus(res.status().as_u16()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    /// A request the mock server got
+    #[derive(Debug)]
+    struct Request {
+        method: String,
+        path: String,
+        body: String,
+    }
+
+    /// Start a mock server that answers `responses.len()` requests with the
+    /// responses in order, returns the port and the requests it got
+    fn mock_server(
+        responses: Vec<(u16, &'static str)>,
+    ) -> (u16, mpsc::Receiver<Request>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            for (status, body) in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let mut parts = line.split_whitespace();
+                let method = parts.next().unwrap().to_string();
+                let path = parts.next().unwrap().to_string();
+
+                let mut content_length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+
+                    let header = header.to_lowercase();
+                    if let Some(len) = header.strip_prefix("content-length:") {
+                        content_length = len.trim().parse().unwrap();
+                    }
+                }
+
+                let mut request_body = vec![0; content_length];
+                reader.read_exact(&mut request_body).unwrap();
+
+                let response = format!(
+                    "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: \
+                     close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+
+                sender
+                    .send(Request {
+                        method,
+                        path,
+                        body: String::from_utf8(request_body).unwrap(),
+                    })
+                    .unwrap();
+            }
+        });
+
+        (port, receiver)
+    }
+
+    #[test]
+    fn login_name() {
+        let (port, requests) = mock_server(vec![(200, "steam\n")]);
+        let client = DevkitClient::new("127.0.0.1", port);
+
+        assert_eq!(client.login_name().unwrap(), "steam");
+
+        let request = requests.recv().unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/login-name");
+    }
+
+    #[test]
+    fn properties() {
+        let body = r#"{"txtvers": 1, "login": "deck", "settings": "{}"}"#;
+        let (port, requests) = mock_server(vec![(200, body)]);
+        let client = DevkitClient::new("127.0.0.1", port);
+
+        let properties = client.properties().unwrap();
+        assert_eq!(properties["login"], "deck");
+        assert_eq!(properties["txtvers"], 1);
+
+        assert_eq!(requests.recv().unwrap().path, "/properties.json");
+    }
+
+    #[test]
+    fn register() {
+        let (port, requests) = mock_server(vec![(200, "")]);
+        let client = DevkitClient::new("127.0.0.1", port);
+
+        client.register("ssh-rsa AAAA test\n").unwrap();
+
+        let request = requests.recv().unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/register");
+        assert_eq!(request.body, "ssh-rsa AAAA test\n");
+    }
+
+    #[test]
+    fn register_errors() {
+        let (port, _requests) = mock_server(vec![
+            (403, r#"{"error": "Not in devkit mode"}"#),
+            (400, r#"{"message": "no error key"}"#),
+            (500, ""),
+        ]);
+        let client = DevkitClient::new("127.0.0.1", port);
+
+        let res = client.register("key");
+        assert!(
+            matches!(&res, Err(Error::FailedToRegister(e)) if e == "Not in devkit mode"),
+            "{:?}",
+            res
+        );
+
+        let res = client.register("key");
+        assert!(matches!(res, Err(Error::FailedToRegisterWithoutMessage)));
+
+        let res = client.register("key");
+        assert!(matches!(res, Err(Error::RegisterRequestUnknownStatus(500))));
+    }
+
+    #[test]
+    fn bad_status() {
+        let (port, _requests) = mock_server(vec![(404, "")]);
+        let client = DevkitClient::new("127.0.0.1", port);
+
+        let res = client.login_name();
+        assert!(matches!(res, Err(Error::DevkitRequestUnknownStatus(404))));
+    }
+
+    #[test]
+    fn ipv6_url() {
+        let client = DevkitClient::new("::1", DEFAULT_PORT);
+        assert_eq!(client.base_url, "http://[::1]:32000");
+    }
+}
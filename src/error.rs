@@ -0,0 +1,697 @@
+//! The error type of decker, every error has a message for the user and
+//! an exit code
+
+use std::path::PathBuf;
+
+use crate::decker_util::UtilError;
+use crate::transport;
+
+/// Custom error enum
+#[derive(Debug)]
+pub enum Error {
+    /// Failed to send POST request to /register
+    RegisterRequestFailed(reqwest::Error),
+
+    /// Failed to get the text result from the POST request
+    FailedToRetriveRegisterRequestText(reqwest::Error),
+
+    /// Failed to parse the error json from the request
+    FailedToParseErrorJson(serde_json::Error),
+
+    /// Failed to register the host
+    FailedToRegister(String),
+
+    /// Failed to register but no message was supplied
+    FailedToRegisterWithoutMessage,
+
+    /// POST request with unknown status
+    RegisterRequestUnknownStatus(u16),
+
+    /// Failed to open the public key file
+    FailedToOpenPublicKeyFile(std::io::Error),
+
+    /// Failed to read the public key file
+    FailedToReadPublicKeyFile(std::io::Error),
+
+    /// Failed to execute 'ssh'
+    FailedToExecuteSSH(std::io::Error),
+
+    /// Failed to execute 'ssh-keygen'
+    FailedToExecuteSSHKeygen(std::io::Error),
+
+    /// Failed to execute 'scp'
+    FailedToExecuteSCP(std::io::Error),
+
+    /// Failed to execute 'rsync'
+    FailedToExecuteRSync(std::io::Error),
+
+    /// Failed to open the connection to the devkit
+    FailedToConnect(std::io::Error),
+
+    /// Error from the native ssh session
+    SSHFailed(ssh2::Error),
+
+    /// The devkit didn't accept the key
+    SSHAuthenticationFailed,
+
+    /// The host key is different from the one in known_hosts
+    HostKeyMismatch(String),
+
+    /// The host isn't in known_hosts and the key wasn't accepted
+    UnknownHostKey(String),
+
+    /// Failed to read a file that should be uploaded
+    FailedToReadLocalFile(std::io::Error),
+
+    /// Failed to write a file on the devkit
+    FailedToWriteRemoteFile(std::io::Error),
+
+    /// Failed to read the output of a command on the devkit
+    FailedToReadRemoteOutput(std::io::Error),
+
+    /// Failed to write the output of a command on the devkit to our stdout
+    FailedToWriteOutput(std::io::Error),
+
+    /// scp or rsync failed
+    TransferFailed(transport::RemoteError),
+
+    /// Failed to create the directory for decker_util
+    FailedToCreateUtilDir(transport::RemoteError),
+
+    /// decker was built without decker_util and it couldn't be read from
+    /// the path
+    FailedToReadUtil(PathBuf, std::io::Error),
+
+    /// Failed to upload decker_util
+    FailedToUploadUtil(Box<Error>),
+
+    /// Failed to make decker_util executable
+    FailedToMakeUtilExecutable(transport::RemoteError),
+
+    /// Failed to move the uploaded decker_util into place
+    FailedToInstallUtil(transport::RemoteError),
+
+    /// decker_util doesn't answer or speaks a different protocol version
+    FailedToHandshake(UtilError),
+
+    /// 'decker_util prepare-upload' failed
+    FailedToPrepareUpload(UtilError),
+
+    /// Failed to start the game through Steam
+    FailedToRunGame(UtilError),
+
+    /// Failed to stop the game
+    FailedToKillGame(UtilError),
+
+    /// Failed to find the log of the run
+    FailedToReadLogs(UtilError),
+
+    /// 'cat' or 'tail' of the log failed
+    FailedToStreamLogs(transport::RemoteError),
+
+    /// Failed to upload the game files
+    FailedToSyncGameFiles(Box<Error>),
+
+    /// Failed to remove or create the changed files in watch mode
+    FailedToUpdateGameFiles(transport::RemoteError),
+
+    /// Failed to watch the game file directory for changes
+    FailedToWatch(PathBuf, notify::Error),
+
+    /// Failed to create the directory for the artwork
+    FailedToCreateArtworkDir(transport::RemoteError),
+
+    /// Failed to upload an artwork file
+    FailedToUploadArtwork(&'static str, Box<Error>),
+
+    /// 'decker_util create-shortcut' failed
+    FailedToCreateShortcut(UtilError),
+
+    /// 'decker_util remove-shortcut' failed
+    FailedToRemoveShortcut(UtilError),
+
+    /// 'decker_util list' failed
+    FailedToListGames(UtilError),
+
+    /// 'decker_util status' failed
+    FailedToGetStatus(UtilError),
+
+    /// The command needs a devkit but '-d' was not given
+    MissingDevkitAddress,
+
+    /// Failed to read the manifest file
+    FailedToReadManifest(std::io::Error),
+
+    /// Failed to parse the manifest file
+    FailedToParseManifest(toml::de::Error),
+
+    /// Failed to write the manifest file
+    FailedToWriteManifest(std::io::Error),
+
+    /// The manifest file already exists
+    ManifestAlreadyExists(PathBuf),
+
+    /// The game id can't be used as a directory name on the devkit
+    InvalidGameId(String),
+
+    /// A deploy option was neither in the manifest or on the command line
+    MissingDeployOption(&'static str),
+
+    /// Failed to read the device config file
+    FailedToReadDeviceConfig(std::io::Error),
+
+    /// Failed to parse the device config file
+    FailedToParseDeviceConfig(toml::de::Error),
+
+    /// Failed to serialize the device config
+    FailedToSerializeDeviceConfig(toml::ser::Error),
+
+    /// Failed to write the device config file
+    FailedToWriteDeviceConfig(std::io::Error),
+
+    /// There is no device with the name
+    UnknownDevice(String),
+
+    /// The path of the ssh key can't be passed to rsync
+    InvalidKeyPath(PathBuf),
+
+    /// Failed to send or receive the mDNS packets
+    DiscoveryFailed(std::io::Error),
+
+    /// Failed to send a request to the devkit service
+    DevkitRequestFailed(reqwest::Error),
+
+    /// The devkit service returned an unexpected status
+    DevkitRequestUnknownStatus(u16),
+
+    /// Failed to parse the response from the devkit service
+    FailedToParseDevkitResponse(serde_json::Error),
+}
+
+/// Custom result type with our custom error enum
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Exit code for bad arguments, manifest or device config, same as clap
+const EXIT_USAGE: i32 = 2;
+
+/// Exit code for when we can't connect or login to the devkit
+const EXIT_CONNECTION: i32 = 3;
+
+/// Exit code for errors from the devkit service
+const EXIT_DEVKIT_SERVICE: i32 = 4;
+
+/// Exit code for commands or transfers that failed on the devkit
+const EXIT_REMOTE: i32 = 5;
+
+/// Exit code for problems on the host i.e missing programs or files
+const EXIT_HOST: i32 = 6;
+
+/// Exit code for every error of 'run --wait', the other codes are the exit
+/// code of the game
+pub const EXIT_RUN_FAILED: i32 = 125;
+
+impl Error {
+    /// The class of the error, used in the json output
+    pub fn kind(&self) -> &'static str {
+        match self.exit_code() {
+            EXIT_USAGE => "usage",
+            EXIT_CONNECTION => "connection",
+            EXIT_DEVKIT_SERVICE => "devkit_service",
+            EXIT_REMOTE => "remote",
+            _ => "host",
+        }
+    }
+
+    /// The exit code of the process, each class of errors gets its own code
+    /// so scripts can tell them apart
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::MissingDevkitAddress |
+            Error::FailedToReadManifest(_) |
+            Error::FailedToParseManifest(_) |
+            Error::ManifestAlreadyExists(_) |
+            Error::InvalidGameId(_) |
+            Error::MissingDeployOption(_) |
+            Error::FailedToReadDeviceConfig(_) |
+            Error::FailedToParseDeviceConfig(_) |
+            Error::UnknownDevice(_) |
+            Error::InvalidKeyPath(_) => EXIT_USAGE,
+
+            Error::FailedToConnect(_) |
+            Error::SSHFailed(_) |
+            Error::SSHAuthenticationFailed |
+            Error::HostKeyMismatch(_) |
+            Error::UnknownHostKey(_) |
+            Error::DiscoveryFailed(_) => EXIT_CONNECTION,
+
+            Error::RegisterRequestFailed(_) |
+            Error::FailedToRetriveRegisterRequestText(_) |
+            Error::FailedToParseErrorJson(_) |
+            Error::FailedToRegister(_) |
+            Error::FailedToRegisterWithoutMessage |
+            Error::RegisterRequestUnknownStatus(_) |
+            Error::DevkitRequestFailed(_) |
+            Error::DevkitRequestUnknownStatus(_) |
+            Error::FailedToParseDevkitResponse(_) => EXIT_DEVKIT_SERVICE,
+
+            Error::FailedToWriteRemoteFile(_) |
+            Error::FailedToReadRemoteOutput(_) |
+            Error::TransferFailed(_) |
+            Error::FailedToCreateUtilDir(_) |
+            Error::FailedToMakeUtilExecutable(_) |
+            Error::FailedToInstallUtil(_) |
+            Error::FailedToHandshake(_) |
+            Error::FailedToPrepareUpload(_) |
+            Error::FailedToCreateArtworkDir(_) |
+            Error::FailedToCreateShortcut(_) |
+            Error::FailedToRemoveShortcut(_) |
+            Error::FailedToListGames(_) |
+            Error::FailedToGetStatus(_) |
+            Error::FailedToRunGame(_) |
+            Error::FailedToKillGame(_) |
+            Error::FailedToReadLogs(_) |
+            Error::FailedToStreamLogs(_) |
+            Error::FailedToUpdateGameFiles(_) => EXIT_REMOTE,
+
+            Error::FailedToOpenPublicKeyFile(_) |
+            Error::FailedToReadPublicKeyFile(_) |
+            Error::FailedToExecuteSSH(_) |
+            Error::FailedToExecuteSSHKeygen(_) |
+            Error::FailedToExecuteSCP(_) |
+            Error::FailedToExecuteRSync(_) |
+            Error::FailedToReadLocalFile(_) |
+            Error::FailedToWriteOutput(_) |
+            Error::FailedToWriteManifest(_) |
+            Error::FailedToSerializeDeviceConfig(_) |
+            Error::FailedToWriteDeviceConfig(_) |
+            Error::FailedToReadUtil(..) |
+            Error::FailedToWatch(..) => EXIT_HOST,
+
+            // NOTE(patrik): The step only adds context, the class comes from
+            // what went wrong
+            Error::FailedToUploadUtil(e) |
+            Error::FailedToSyncGameFiles(e) |
+            Error::FailedToUploadArtwork(_, e) => e.exit_code(),
+        }
+    }
+}
+
+/// Message for a program that failed to start, not being installed is the
+/// most common reason so that gets a hint
+fn write_program_error(
+    f: &mut std::fmt::Formatter<'_>,
+    program: &str,
+    hint: &str,
+    error: &std::io::Error,
+) -> std::fmt::Result {
+    if error.kind() == std::io::ErrorKind::NotFound {
+        write!(f, "{} not found on PATH; {}", program, hint)
+    } else {
+        write!(f, "failed to run {}", program)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::RegisterRequestFailed(_) => write!(
+                f,
+                "failed to send the register request to the devkit service, \
+                 make sure the devkit is in devkit mode"
+            ),
+            Error::FailedToRetriveRegisterRequestText(_) => {
+                write!(f, "failed to read the register response")
+            }
+            Error::FailedToParseErrorJson(_) => {
+                write!(f, "failed to parse the register error response")
+            }
+            Error::FailedToRegister(msg) => {
+                write!(f, "the devkit refused the registration: {}", msg)
+            }
+            Error::FailedToRegisterWithoutMessage => {
+                write!(f, "the devkit refused the registration")
+            }
+            Error::RegisterRequestUnknownStatus(status) => write!(
+                f,
+                "the register request failed with HTTP status {}",
+                status
+            ),
+            Error::FailedToOpenPublicKeyFile(_) => {
+                write!(f, "failed to open the public ssh key")
+            }
+            Error::FailedToReadPublicKeyFile(_) => {
+                write!(f, "failed to read the public ssh key")
+            }
+            Error::FailedToExecuteSSH(e) => write_program_error(
+                f,
+                "ssh",
+                "install OpenSSH or use --transport native",
+                e,
+            ),
+            Error::FailedToExecuteSSHKeygen(e) => write_program_error(
+                f,
+                "ssh-keygen",
+                "install OpenSSH to create the devkit key",
+                e,
+            ),
+            Error::FailedToExecuteSCP(e) => write_program_error(
+                f,
+                "scp",
+                "install OpenSSH or use --transport native",
+                e,
+            ),
+            Error::FailedToExecuteRSync(e) => write_program_error(
+                f,
+                "rsync",
+                "install it or use --transport native",
+                e,
+            ),
+            Error::FailedToConnect(_) => write!(
+                f,
+                "failed to connect to the devkit, make sure it's turned on \
+                 and on the same network"
+            ),
+            Error::SSHFailed(_) => write!(f, "the ssh session failed"),
+            Error::SSHAuthenticationFailed => write!(
+                f,
+                "the devkit didn't accept the ssh key, make sure the devkit \
+                 is in devkit mode"
+            ),
+            Error::HostKeyMismatch(address) => write!(
+                f,
+                "the host key of {} has changed, remove the old key with \
+                 'ssh-keygen -R {}' if the devkit was reinstalled",
+                address, address
+            ),
+            Error::UnknownHostKey(address) => write!(
+                f,
+                "the host key of {} is unknown, connect once from a terminal \
+                 to accept it or add it to ~/.ssh/known_hosts",
+                address
+            ),
+            Error::FailedToReadLocalFile(_) => {
+                write!(f, "failed to read a file that should be uploaded")
+            }
+            Error::FailedToWriteRemoteFile(_) => {
+                write!(f, "failed to write a file on the devkit")
+            }
+            Error::FailedToReadRemoteOutput(_) => {
+                write!(
+                    f,
+                    "failed to read the output of a command on the devkit"
+                )
+            }
+            Error::FailedToWriteOutput(_) => {
+                write!(f, "failed to write the output to stdout")
+            }
+            Error::TransferFailed(_) => write!(f, "the file transfer failed"),
+            Error::FailedToCreateUtilDir(_) => {
+                write!(f, "failed to create ~/decker on the devkit")
+            }
+            Error::FailedToReadUtil(path, _) => write!(
+                f,
+                "decker was built without decker_util and it couldn't be \
+                 read from {}; set DECKER_UTIL to a decker_util built for \
+                 x86_64-unknown-linux-musl",
+                path.display()
+            ),
+            Error::FailedToUploadUtil(_) => {
+                write!(f, "failed to upload decker_util to the devkit")
+            }
+            Error::FailedToMakeUtilExecutable(_) => {
+                write!(f, "failed to make decker_util executable")
+            }
+            Error::FailedToInstallUtil(_) => {
+                write!(f, "failed to move decker_util into place")
+            }
+            Error::FailedToPrepareUpload(_) => {
+                write!(f, "failed to prepare the game directory on the devkit")
+            }
+            Error::FailedToSyncGameFiles(_) => {
+                write!(f, "failed to upload the game files")
+            }
+            Error::FailedToUpdateGameFiles(_) => {
+                write!(f, "failed to update the game files on the devkit")
+            }
+            Error::FailedToWatch(path, _) => {
+                write!(f, "failed to watch {} for changes", path.display())
+            }
+            Error::FailedToCreateArtworkDir(_) => {
+                write!(
+                    f,
+                    "failed to create the artwork directory on the devkit"
+                )
+            }
+            Error::FailedToUploadArtwork(kind, _) => {
+                write!(f, "failed to upload the {} artwork", kind)
+            }
+            Error::FailedToCreateShortcut(_) => {
+                write!(f, "failed to create the Steam shortcut")
+            }
+            Error::FailedToRemoveShortcut(_) => {
+                write!(f, "failed to remove the game")
+            }
+            Error::FailedToListGames(_) => {
+                write!(f, "failed to list the games on the devkit")
+            }
+            Error::FailedToGetStatus(_) => {
+                write!(f, "failed to get the status of the devkit")
+            }
+            Error::FailedToRunGame(_) => {
+                write!(f, "failed to start the game")
+            }
+            Error::FailedToKillGame(_) => {
+                write!(f, "failed to stop the game")
+            }
+            Error::FailedToReadLogs(_) => {
+                write!(f, "failed to find the log of the game")
+            }
+            Error::FailedToStreamLogs(_) => {
+                write!(f, "failed to read the log of the game")
+            }
+            Error::FailedToHandshake(_) => {
+                write!(f, "failed to start decker_util on the devkit")
+            }
+            Error::MissingDevkitAddress => write!(
+                f,
+                "no devkit given, use '-d <address>' or set a default device \
+                 with 'decker device add'"
+            ),
+            Error::FailedToReadManifest(_) => {
+                write!(f, "failed to read {}", crate::manifest::MANIFEST_FILE)
+            }
+            Error::FailedToParseManifest(_) => {
+                write!(f, "failed to parse {}", crate::manifest::MANIFEST_FILE)
+            }
+            Error::FailedToWriteManifest(_) => {
+                write!(f, "failed to write {}", crate::manifest::MANIFEST_FILE)
+            }
+            Error::ManifestAlreadyExists(path) => write!(
+                f,
+                "{} already exists, use --force to overwrite it",
+                path.display()
+            ),
+            Error::InvalidGameId(game_id) => write!(
+                f,
+                "invalid game id {:?}, it can't be empty, '.' or '..' or \
+                 contain '/' or control characters",
+                game_id
+            ),
+            Error::MissingDeployOption(option) => write!(
+                f,
+                "missing {}, give it on the command line or in {}",
+                option,
+                crate::manifest::MANIFEST_FILE
+            ),
+            Error::FailedToReadDeviceConfig(_) => {
+                write!(f, "failed to read the device config")
+            }
+            Error::FailedToParseDeviceConfig(_) => write!(
+                f,
+                "failed to parse the device config {}",
+                crate::device::Config::path().display()
+            ),
+            Error::FailedToSerializeDeviceConfig(_) => {
+                write!(f, "failed to serialize the device config")
+            }
+            Error::FailedToWriteDeviceConfig(_) => {
+                write!(f, "failed to write the device config")
+            }
+            Error::UnknownDevice(name) => write!(
+                f,
+                "unknown device '{}', see 'decker device list'",
+                name
+            ),
+            Error::InvalidKeyPath(path) => write!(
+                f,
+                "the ssh key path {:?} isn't valid UTF-8, rsync can't use \
+                 it, move the key or use --transport native",
+                path
+            ),
+            Error::DiscoveryFailed(_) => {
+                write!(f, "failed to search for devkits on the network")
+            }
+            Error::DevkitRequestFailed(_) => {
+                write!(f, "failed to talk to the devkit service")
+            }
+            Error::DevkitRequestUnknownStatus(status) => write!(
+                f,
+                "the devkit service answered with HTTP status {}",
+                status
+            ),
+            Error::FailedToParseDevkitResponse(_) => {
+                write!(f, "failed to parse the answer from the devkit service")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::RegisterRequestFailed(e) |
+            Error::FailedToRetriveRegisterRequestText(e) |
+            Error::DevkitRequestFailed(e) => Some(e),
+
+            Error::FailedToParseErrorJson(e) |
+            Error::FailedToParseDevkitResponse(e) => Some(e),
+
+            Error::FailedToOpenPublicKeyFile(e) |
+            Error::FailedToReadPublicKeyFile(e) |
+            Error::FailedToExecuteSSH(e) |
+            Error::FailedToExecuteSSHKeygen(e) |
+            Error::FailedToExecuteSCP(e) |
+            Error::FailedToExecuteRSync(e) |
+            Error::FailedToConnect(e) |
+            Error::FailedToReadLocalFile(e) |
+            Error::FailedToWriteRemoteFile(e) |
+            Error::FailedToReadRemoteOutput(e) |
+            Error::FailedToWriteOutput(e) |
+            Error::FailedToReadManifest(e) |
+            Error::FailedToWriteManifest(e) |
+            Error::FailedToReadDeviceConfig(e) |
+            Error::FailedToWriteDeviceConfig(e) |
+            Error::FailedToReadUtil(_, e) |
+            Error::DiscoveryFailed(e) => Some(e),
+
+            Error::SSHFailed(e) => Some(e),
+
+            Error::TransferFailed(e) |
+            Error::FailedToCreateUtilDir(e) |
+            Error::FailedToMakeUtilExecutable(e) |
+            Error::FailedToInstallUtil(e) |
+            Error::FailedToCreateArtworkDir(e) |
+            Error::FailedToStreamLogs(e) |
+            Error::FailedToUpdateGameFiles(e) => Some(e),
+
+            Error::FailedToWatch(_, e) => Some(e),
+
+            Error::FailedToHandshake(e) |
+            Error::FailedToPrepareUpload(e) |
+            Error::FailedToCreateShortcut(e) |
+            Error::FailedToRemoveShortcut(e) |
+            Error::FailedToListGames(e) |
+            Error::FailedToGetStatus(e) |
+            Error::FailedToRunGame(e) |
+            Error::FailedToKillGame(e) |
+            Error::FailedToReadLogs(e) => Some(e),
+
+            Error::FailedToUploadUtil(e) |
+            Error::FailedToSyncGameFiles(e) |
+            Error::FailedToUploadArtwork(_, e) => Some(e.as_ref()),
+
+            Error::FailedToParseManifest(e) |
+            Error::FailedToParseDeviceConfig(e) => Some(e),
+
+            Error::FailedToSerializeDeviceConfig(e) => Some(e),
+
+            Error::FailedToRegister(_) |
+            Error::FailedToRegisterWithoutMessage |
+            Error::RegisterRequestUnknownStatus(_) |
+            Error::SSHAuthenticationFailed |
+            Error::HostKeyMismatch(_) |
+            Error::UnknownHostKey(_) |
+            Error::ManifestAlreadyExists(_) |
+            Error::InvalidGameId(_) |
+            Error::MissingDeployOption(_) |
+            Error::MissingDevkitAddress |
+            Error::UnknownDevice(_) |
+            Error::InvalidKeyPath(_) |
+            Error::DevkitRequestUnknownStatus(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn io() -> std::io::Error {
+        std::io::Error::from(std::io::ErrorKind::NotFound)
+    }
+
+    fn remote() -> transport::RemoteError {
+        transport::RemoteError {
+            exit_code: 1,
+            stderr: String::new(),
+        }
+    }
+
+    #[test]
+    fn exit_codes() {
+        let toml_error = toml::from_str::<toml::Value>("=").unwrap_err();
+
+        let cases = [
+            (Error::MissingDevkitAddress, 2, "usage"),
+            (Error::FailedToReadManifest(io()), 2, "usage"),
+            (Error::FailedToParseManifest(toml_error), 2, "usage"),
+            (Error::MissingDeployOption("exec"), 2, "usage"),
+            (Error::InvalidGameId("../x".to_string()), 2, "usage"),
+            (Error::UnknownDevice("deck".to_string()), 2, "usage"),
+            (Error::InvalidKeyPath(PathBuf::from("key")), 2, "usage"),
+            (Error::FailedToConnect(io()), 3, "connection"),
+            (Error::SSHAuthenticationFailed, 3, "connection"),
+            (Error::HostKeyMismatch("deck".to_string()), 3, "connection"),
+            (Error::UnknownHostKey("deck".to_string()), 3, "connection"),
+            (Error::DiscoveryFailed(io()), 3, "connection"),
+            (Error::FailedToRegisterWithoutMessage, 4, "devkit_service"),
+            (Error::DevkitRequestUnknownStatus(500), 4, "devkit_service"),
+            (Error::TransferFailed(remote()), 5, "remote"),
+            (Error::FailedToStreamLogs(remote()), 5, "remote"),
+            (
+                Error::FailedToCreateShortcut(UtilError::UnexpectedOutput),
+                5,
+                "remote",
+            ),
+            (Error::FailedToExecuteSSH(io()), 6, "host"),
+            (Error::FailedToReadLocalFile(io()), 6, "host"),
+            (Error::FailedToWriteDeviceConfig(io()), 6, "host"),
+        ];
+
+        for (error, code, kind) in cases {
+            assert_eq!(error.exit_code(), code, "{:?}", error);
+            assert_eq!(error.kind(), kind, "{:?}", error);
+        }
+    }
+
+    #[test]
+    fn exit_code_of_the_cause() {
+        // The steps that wrap another error get the class of that error
+        let error = Error::FailedToSyncGameFiles(Box::new(
+            Error::FailedToConnect(io()),
+        ));
+        assert_eq!(error.exit_code(), 3);
+        assert_eq!(error.kind(), "connection");
+
+        let error = Error::FailedToUploadUtil(Box::new(
+            Error::FailedToReadLocalFile(io()),
+        ));
+        assert_eq!(error.exit_code(), 6);
+
+        let error = Error::FailedToUploadArtwork(
+            "grid",
+            Box::new(Error::TransferFailed(remote())),
+        );
+        assert_eq!(error.exit_code(), 5);
+    }
+}
@@ -12,60 +12,34 @@
 //      - Install: libssl-dev musl-dev
 //
 
-use serde_json::Value;
-
 use clap::{Parser, Subcommand};
 
 use std::fs::File;
-use std::io::{Write, Read};
+use std::io::Read;
 use std::process::Command;
 use std::path::{Path, PathBuf};
-
-/// The helper program we send to the devkit
-const DECKER_UTIL_PROGRAM: &[u8] = include_bytes!("../target/decker_util");
-
-/// Custom error enum
-#[derive(Debug)]
-enum Error {
-    /// Failed to send POST request to /register
-    RegisterRequestFailed(reqwest::Error),
-
-    /// Failed to get the text result from the POST request
-    FailedToRetriveRegisterRequestText(reqwest::Error),
-
-    /// Failed to parse the error json from the request
-    FailedToParseErrorJson(serde_json::Error),
-
-    /// Failed to register the host
-    FailedToRegister(String),
-
-    /// Failed to register but no message was supplied
-    FailedToRegisterWithoutMessage,
-
-    /// POST request with unknown status
-    RegisterRequestUnknownStatus(u16),
-
-    /// Failed to open the public key file
-    FailedToOpenPublicKeyFile(std::io::Error),
-
-    /// Failed to read the public key file
-    FailedToReadPublicKeyFile(std::io::Error),
-
-    /// Failed to execute 'ssh'
-    FailedToExecuteSSH(std::io::Error),
-
-    /// Failed to execute 'ssh-keygen'
-    FailedToExecuteSSHKeygen(std::io::Error),
-
-    /// Failed to execute 'scp'
-    FailedToExecuteSCP(std::io::Error),
-
-    /// Failed to execute 'rsync'
-    FailedToExecuteRSync(std::io::Error),
-}
-
-/// Custom result type with our custom error enum
-type Result<T> = std::result::Result<T, Error>;
+use std::collections::BTreeMap;
+
+use decker_protocol::{
+    ArtworkFile, Command as UtilCommand, List, Output, ShortcutAction,
+};
+
+use decker_util::UtilError;
+use manifest::{Artwork, Manifest};
+use device::Device;
+use error::{Error, Result, EXIT_RUN_FAILED};
+use remote_command::RemoteCommand;
+use transport::{RemoteError, Transport, TransportKind};
+
+mod decker_util;
+mod device;
+mod devkit;
+mod error;
+mod manifest;
+mod mdns;
+mod remote_command;
+mod transport;
+mod watch;
 
 /// Command line arguments the program accepts
 #[derive(Parser, Debug)]
@@ -74,8 +48,68 @@ struct Args {
     #[clap(subcommand)]
     command: ArgCommand,
 
+    /// The devkit to use, the name of a saved device, an address or the
+    /// hostname of a devkit on the local network. Defaults to the default
+    /// device
     #[clap(short, value_parser)]
-    devkit_addr: String,
+    devkit_addr: Option<String>,
+
+    /// How to connect to the devkit, 'subprocess' uses the ssh, scp and
+    /// rsync programs
+    #[clap(long, value_enum, default_value_t)]
+    transport: TransportKind,
+
+    /// Print errors as JSON on stderr, for CI and other programs
+    #[clap(long)]
+    json: bool,
+}
+
+/// Deploy arguments, the arguments not given gets read from decker.toml
+#[derive(clap::Args, Debug)]
+struct DeployArgs {
+    /// The game id the deployment should use
+    #[clap(value_parser)]
+    game_id: Option<String>,
+
+    /// The program the deployment should run when the user runs the game
+    #[clap(value_parser)]
+    exec: Option<String>,
+
+    /// The directory on the host machine where the game files,
+    /// so we can copy them to the devkit
+    #[clap(value_parser)]
+    game_file_dir: Option<PathBuf>,
+
+    /// The starting directory the deployment should start in when the
+    /// user run the game, relative to the game directory
+    #[clap(short, long, value_parser)]
+    starting_dir: Option<String>,
+
+    /// The name shown in Steam
+    #[clap(long)]
+    name: Option<String>,
+
+    /// Argument passed to the program, replaces the args in the manifest
+    #[clap(long = "arg", allow_hyphen_values = true)]
+    args: Vec<String>,
+
+    /// Pattern of files that should not be sent to the devkit, added to
+    /// the excludes in the manifest
+    #[clap(long)]
+    exclude: Vec<String>,
+
+    /// Environment variable set when the game runs as KEY=VALUE
+    #[clap(long, value_parser = parse_env_var)]
+    env: Vec<(String, String)>,
+
+    /// Extra Steam launch options i.e "gamemoderun %command%"
+    #[clap(long, allow_hyphen_values = true)]
+    launch_options: Option<String>,
+
+    /// Path to the manifest, defaults to decker.toml in the current
+    /// directory
+    #[clap(long)]
+    manifest: Option<PathBuf>,
 }
 
 /// Command line command
@@ -83,27 +117,340 @@ struct Args {
 enum ArgCommand {
     /// Deploy game
     Deploy {
-        /// The game id the deployment should use
+        #[clap(flatten)]
+        deploy: DeployArgs,
+
+        /// Keep running and upload the files that changes in the game file
+        /// directory
+        #[clap(long)]
+        watch: bool,
+
+        /// Restart the game after every upload if it's running, only with
+        /// --watch
+        #[clap(long, requires = "watch")]
+        restart: bool,
+    },
+
+    /// Create a decker.toml in the current directory
+    Init {
+        /// The game id, defaults to the name of the current directory
+        #[clap(value_parser)]
+        game_id: Option<String>,
+
+        /// The program the deployment should run
+        #[clap(long, default_value = "run_game.sh")]
+        exec: String,
+
+        /// Overwrite the manifest if it already exists
+        #[clap(long)]
+        force: bool,
+    },
+
+    /// Remove a deployed game and its shortcut
+    Remove {
+        /// The game id used when the game was deployed
         #[clap(value_parser)]
         game_id: String,
 
-        /// The program the deployment should run when the user runs the game
+        /// Also remove the grid artwork of the shortcut
+        #[clap(long)]
+        artwork: bool,
+
+        /// Also remove the compatibility tool (i.e Proton) mapping of the
+        /// shortcut
+        #[clap(long)]
+        compat_tool: bool,
+    },
+
+    /// List the deployed games
+    List {
+        /// Print the list as json
+        #[clap(long)]
+        json: bool,
+    },
+
+    /// Show information about the devkit
+    Status,
+
+    /// Start a deployed game through Steam
+    // NOTE(patrik): Only the game id and the manifest are used without
+    // --deploy-first, the other deploy options would be ignored
+    #[clap(group(
+        clap::ArgGroup::new("deploy_options")
+            .multiple(true)
+            .requires("deploy_first")
+            .args([
+                "exec",
+                "game_file_dir",
+                "starting_dir",
+                "name",
+                "args",
+                "exclude",
+                "env",
+                "launch_options",
+            ])
+    ))]
+    Run {
+        #[clap(flatten)]
+        deploy: DeployArgs,
+
+        /// Wait for the game to exit and exit with the exit code of the
+        /// game, decker exits with 125 if it fails itself
+        #[clap(long)]
+        wait: bool,
+
+        /// Deploy the game before starting it
+        #[clap(long)]
+        deploy_first: bool,
+    },
+
+    /// Stop a running game, the processes get SIGTERM and SIGKILL if they
+    /// are still running after the grace period
+    Kill {
+        /// The game id used when the game was deployed
         #[clap(value_parser)]
-        exec: String,
+        game_id: String,
 
-        /// The starting directory the deployment should start in when the
-        /// user run the game
-        #[clap(short, long, value_parser)]
-        starting_dir: Option<String>,
+        /// Seconds to wait before the processes get SIGKILL
+        #[clap(long, default_value_t = 5)]
+        grace_period: u64,
+    },
 
-        /// The directory on the host machine where the game files,
-        /// so we can copy them to the devkit
+    /// Show the stdout and stderr of a game started through the shortcut
+    Logs {
+        /// The game id used when the game was deployed
         #[clap(value_parser)]
-        game_file_dir: String,
+        game_id: String,
+
+        /// Keep printing the output until the game exits
+        #[clap(long, short)]
+        follow: bool,
+
+        /// Show the log of this run instead of the latest one
+        #[clap(long)]
+        run: Option<u32>,
     },
 
     /// Run shell
     Shell,
+
+    /// Find the devkits on the local network
+    Discover {
+        /// How long to wait for answers in seconds
+        #[clap(long, default_value_t = 2.0)]
+        timeout: f64,
+    },
+
+    /// Manage the saved devices
+    Device {
+        #[clap(subcommand)]
+        command: DeviceCommand,
+    },
+}
+
+/// Device command
+#[derive(Subcommand, Debug)]
+enum DeviceCommand {
+    /// Add a device or update a device with the same name
+    Add {
+        /// The name used with '-d'
+        #[clap(value_parser)]
+        name: String,
+
+        /// The address of the devkit
+        #[clap(value_parser)]
+        address: String,
+
+        /// The ssh port
+        #[clap(long, default_value_t = 22)]
+        port: u16,
+
+        /// The user on the devkit, asked from the devkit when not given
+        #[clap(long)]
+        username: Option<String>,
+
+        /// The port of the devkit service
+        #[clap(long, default_value_t = devkit::DEFAULT_PORT)]
+        devkit_port: u16,
+
+        /// The private ssh key to use, defaults to the key decker creates
+        #[clap(long)]
+        key: Option<PathBuf>,
+
+        /// The directory the games gets installed to on the devkit
+        #[clap(long)]
+        install_root: Option<String>,
+
+        /// Make the device the default device
+        #[clap(long)]
+        default: bool,
+    },
+
+    /// Remove a device
+    Remove {
+        #[clap(value_parser)]
+        name: String,
+    },
+
+    /// List the devices
+    List,
+
+    /// Set the device used when '-d' is not given
+    SetDefault {
+        #[clap(value_parser)]
+        name: String,
+    },
+}
+
+/// Get the game id from the arguments or decker.toml
+fn find_game_id(args: &DeployArgs) -> Result<String> {
+    let game_id = match &args.game_id {
+        Some(game_id) => game_id.clone(),
+        None => Manifest::find(args.manifest.as_deref())?
+            .and_then(|manifest| manifest.game_id)
+            .ok_or(Error::MissingDeployOption("game_id"))?,
+    };
+    check_game_id(&game_id)?;
+
+    Ok(game_id)
+}
+
+/// The deploy options after merging decker.toml and the command line
+#[derive(Debug)]
+struct DeployConfig {
+    game_id: String,
+    name: Option<String>,
+
+    /// The game directory on the devkit
+    game_dir: String,
+
+    /// Full path to the program on the devkit
+    exec: String,
+
+    /// Full path to the starting directory on the devkit
+    starting_dir: String,
+
+    source_dir: PathBuf,
+    exclude: Vec<String>,
+
+    /// The Steam launch options with the env vars and the args included
+    launch_options: String,
+
+    artwork: Artwork,
+}
+
+impl DeployConfig {
+    /// Merge the arguments with the manifest, the arguments wins
+    fn new(args: DeployArgs, device: &Device) -> Result<Self> {
+        let manifest =
+            Manifest::find(args.manifest.as_deref())?.unwrap_or_default();
+
+        let game_id = args
+            .game_id
+            .or(manifest.game_id)
+            .ok_or(Error::MissingDeployOption("game_id"))?;
+        check_game_id(&game_id)?;
+        let exec = args
+            .exec
+            .or(manifest.exec)
+            .ok_or(Error::MissingDeployOption("exec"))?;
+        let source_dir = args
+            .game_file_dir
+            .or(manifest.source_dir)
+            .ok_or(Error::MissingDeployOption("game_file_dir"))?;
+
+        let game_args = if args.args.is_empty() {
+            manifest.args
+        } else {
+            args.args
+        };
+
+        let mut exclude = manifest.exclude;
+        exclude.extend(args.exclude);
+
+        let mut env = manifest.env;
+        env.extend(args.env);
+
+        let launch_options = args
+            .launch_options
+            .or(manifest.launch_options)
+            .unwrap_or_default();
+        let launch_options =
+            build_launch_options(&env, &launch_options, &game_args);
+
+        let game_dir = format!("{}/{}", device.install_root(), game_id);
+        let exec = format!("{}/{}", game_dir, exec);
+
+        let starting_dir = match args.starting_dir.or(manifest.starting_dir) {
+            Some(dir) if dir.starts_with('/') => dir,
+            Some(dir) => {
+                let dir = dir.trim_start_matches("./").trim_end_matches('/');
+                if dir.is_empty() || dir == "." {
+                    game_dir.clone()
+                } else {
+                    format!("{}/{}", game_dir, dir)
+                }
+            }
+            None => game_dir.clone(),
+        };
+
+        Ok(Self {
+            game_id,
+            name: args.name.or(manifest.name),
+            game_dir,
+            exec,
+            starting_dir,
+            source_dir,
+            exclude,
+            launch_options,
+            artwork: manifest.artwork,
+        })
+    }
+}
+
+/// Check the game id before it's used in any path or command on the devkit
+fn check_game_id(game_id: &str) -> Result<()> {
+    if decker_protocol::is_valid_game_id(game_id) {
+        Ok(())
+    } else {
+        Err(Error::InvalidGameId(game_id.to_string()))
+    }
+}
+
+/// Build the Steam launch options, the env vars goes in front of %command%
+/// and the args at the end
+fn build_launch_options(
+    env: &BTreeMap<String, String>,
+    launch_options: &str,
+    args: &[String],
+) -> String {
+    let mut parts = Vec::new();
+    for (key, value) in env.iter() {
+        parts.push(format!("{}={}", key, remote_command::quote(value)));
+    }
+
+    // NOTE(patrik): Without %command% Steam appends the launch options to
+    // the command, that doesn't work for env vars
+    if !env.is_empty() && !launch_options.contains("%command%") {
+        parts.push("%command%".to_string());
+    }
+
+    if !launch_options.is_empty() {
+        parts.push(launch_options.to_string());
+    }
+
+    parts.extend(args.iter().map(|arg| remote_command::quote(arg)));
+
+    parts.join(" ")
+}
+
+fn parse_env_var(s: &str) -> std::result::Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => {
+            Ok((key.to_string(), value.to_string()))
+        }
+        _ => Err(format!("expected KEY=VALUE, got '{}'", s)),
+    }
 }
 
 /// Get the path to the program data directory
@@ -122,32 +469,30 @@ fn get_private_key_path() -> PathBuf {
     res
 }
 
-/// Get the path to the public key file
-fn get_public_key_path() -> PathBuf {
-    let mut res = get_private_key_path();
+/// Get the path to the public key file of the private key
+fn get_public_key_path(private_key: &Path) -> PathBuf {
+    let mut res = private_key.to_path_buf();
     res.set_extension("pub");
 
     res
 }
 
 /// Read the public key and return the content
-fn get_public_key() -> Result<String> {
-    let path = get_public_key_path();
+fn get_public_key(private_key: &Path) -> Result<String> {
+    let path = get_public_key_path(private_key);
 
     let mut file =
-        File::open(path).map_err(|e| Error::FailedToOpenPublicKeyFile(e))?;
+        File::open(path).map_err(Error::FailedToOpenPublicKeyFile)?;
 
     let mut result = String::new();
     file.read_to_string(&mut result)
-        .map_err(|e| Error::FailedToReadPublicKeyFile(e))?;
+        .map_err(Error::FailedToReadPublicKeyFile)?;
 
     Ok(result)
 }
 
 /// Create the ssh keys needed for the devkit
-fn create_ssh_keys() -> Result<()> {
-    let path = get_private_key_path();
-
+fn create_ssh_keys(path: &Path) -> Result<()> {
     Command::new("ssh-keygen")
         .arg("-f")
         .arg(path)
@@ -158,22 +503,19 @@ fn create_ssh_keys() -> Result<()> {
         .arg("-N")
         .arg("")
         .output()
-        .map_err(|e| Error::FailedToExecuteSSHKeygen(e))?;
+        .map_err(Error::FailedToExecuteSSHKeygen)?;
 
     Ok(())
 }
 
 /// Register the host i.e send the ssh public key
-fn register(addr: &str) -> Result<()> {
-    let private_key = get_private_key_path();
+fn register(device: &Device) -> Result<()> {
+    let private_key = device.key_path();
     if !private_key.exists() {
-        create_ssh_keys()?;
+        create_ssh_keys(&private_key)?;
     }
 
-    // TODO(patrik): Move port
-    let url = format!("http://{}:32000/register", addr);
-
-    let mut public_key = get_public_key()?;
+    let mut public_key = get_public_key(&private_key)?;
     // TODO(patrik): We might not need to have the magic value because
     // registering without it works
 
@@ -188,249 +530,1125 @@ fn register(addr: &str) -> Result<()> {
     // Push the magic phrase
     public_key.push_str(" 900b919520e4cf601998a71eec318fec\n");
 
-    let client = reqwest::blocking::Client::new();
-    let res = client
-        .post(url)
-        .body(public_key)
-        .send()
-        .map_err(|e| Error::RegisterRequestFailed(e))?;
-
-    if res.status().is_client_error() {
-        let res = res
-            .text()
-            .map_err(|e| Error::FailedToRetriveRegisterRequestText(e))?;
-        let res: Value = serde_json::from_str(&res)
-            .map_err(|e| Error::FailedToParseErrorJson(e))?;
-        if let Some(error) = res.get("error") {
-            if let Some(error) = error.as_str() {
-                Err(Error::FailedToRegister(error.to_owned()))
-            } else {
-                Err(Error::FailedToRegisterWithoutMessage)
+    device.devkit_client().register(&public_key)
+}
+
+/// Deploy game to the devkit
+fn deploy(
+    device: &Device,
+    transport: &dyn Transport,
+    config: &DeployConfig,
+) -> Result<()> {
+    decker_util::upload(device, transport)?;
+
+    let command = UtilCommand::PrepareUpload {
+        game_id: config.game_id.clone(),
+        remove_old: true,
+    };
+    match decker_util::call(
+        device,
+        transport,
+        command,
+        Error::FailedToPrepareUpload,
+    )? {
+        Output::PrepareUpload(_) => {}
+        _ => {
+            return Err(Error::FailedToPrepareUpload(
+                UtilError::UnexpectedOutput,
+            ))
+        }
+    }
+
+    transport
+        .sync(&config.source_dir, &config.game_dir, &config.exclude)
+        .map_err(|e| Error::FailedToSyncGameFiles(Box::new(e)))?;
+
+    let mut shortcut = decker_protocol::CreateShortcut {
+        game_id: config.game_id.clone(),
+        name: config.name.clone(),
+        exec: config.exec.clone(),
+        starting_dir: config.starting_dir.clone(),
+        launch_options: config.launch_options.clone(),
+        ..Default::default()
+    };
+
+    // NOTE(patrik): The artwork gets uploaded next to the game files and
+    // decker_util copies it into the grid directory of every user
+    let artwork = config.artwork.files();
+    if !artwork.is_empty() {
+        let artwork_dir = format!("{}/.decker/artwork", config.game_dir);
+        transport
+            .execute(
+                &RemoteCommand::new("mkdir").arg("-p").path(&artwork_dir),
+            )?
+            .check()
+            .map_err(Error::FailedToCreateArtworkDir)?;
+
+        for (kind, path) in artwork {
+            let mut dest = format!("{}/{}", artwork_dir, kind);
+            if let Some(ext) = path.extension() {
+                dest.push('.');
+                dest.push_str(&ext.to_string_lossy());
             }
-        } else {
-            Err(Error::FailedToRegisterWithoutMessage)
+
+            transport.upload(path, &dest).map_err(|e| {
+                Error::FailedToUploadArtwork(kind, Box::new(e))
+            })?;
+
+            if kind == "icon" {
+                shortcut.icon = dest.clone();
+            }
+
+            shortcut.artwork.push(ArtworkFile {
+                kind: kind.to_string(),
+                path: dest,
+            });
         }
-    } else if res.status().is_success() {
-        Ok(())
-    } else {
-        Err(Error::RegisterRequestUnknownStatus(res.status().as_u16()))
     }
+
+    let output = decker_util::call(
+        device,
+        transport,
+        UtilCommand::CreateShortcut(shortcut),
+        Error::FailedToCreateShortcut,
+    )?;
+    let output = match output {
+        Output::CreateShortcut(output) => output,
+        _ => {
+            return Err(Error::FailedToCreateShortcut(
+                UtilError::UnexpectedOutput,
+            ))
+        }
+    };
+
+    for user in output.users.iter() {
+        let action = match user.action {
+            ShortcutAction::Created => "created",
+            ShortcutAction::Updated => "updated",
+            ShortcutAction::Unchanged => "unchanged",
+        };
+
+        println!(
+            "User {}: shortcut {} (appid {})",
+            user.user, action, user.appid
+        );
+    }
+
+    // NOTE(patrik): The deploy only failed if no user got the shortcut, a
+    // missing artwork or a broken user is a warning
+    if output.users.is_empty() && !output.errors.is_empty() {
+        return Err(Error::FailedToCreateShortcut(UtilError::Failed(
+            decker_protocol::Error::ShortcutsFailed {
+                errors: output.errors,
+            },
+        )));
+    }
+
+    for error in output.errors.iter() {
+        println!("Warning: user {}: {}", error.user, error.error);
+    }
+
+    Ok(())
 }
 
-/// Execute command on the devkit
-fn execute_simple_ssh(
-    addr: &str,
-    username: &str,
-    cmd: &str,
-) -> Result<std::process::Output> {
-    let host = format!("{}@{}", username, addr);
+/// Create a decker.toml in the current directory
+fn init(game_id: Option<String>, exec: &str, force: bool) -> Result<()> {
+    let path = PathBuf::from(manifest::MANIFEST_FILE);
+    if path.exists() && !force {
+        return Err(Error::ManifestAlreadyExists(path));
+    }
 
-    let key = get_private_key_path();
+    let game_id = game_id.unwrap_or_else(|| {
+        let dir = std::env::current_dir().unwrap();
+        dir.file_name().unwrap().to_string_lossy().to_string()
+    });
 
-    Command::new("ssh")
-        .arg("-oBatchMode=yes")
-        .arg("-i")
-        .arg(key)
-        .arg(host)
-        .arg(cmd)
-        .output()
-        .map_err(|e| Error::FailedToExecuteSSH(e))
-}
-
-/// Transfer files to/from the devkit
-fn execute_simple_scp<S, D>(
-    addr: &str,
-    username: &str,
-    source: S,
-    dest: D,
-) -> Result<std::process::Output>
-where
-    S: AsRef<Path>,
-    D: AsRef<Path>,
-{
-    let host = format!("{}@{}", username, addr);
-
-    let source = source.as_ref();
-    let dest = dest.as_ref();
-    let dest = format!("{}:{}", host, dest.to_str().unwrap());
-
-    let key = get_private_key_path();
-
-    Command::new("scp")
-        .arg("-oBatchMode=yes")
-        .arg("-i")
-        .arg(key)
-        .arg(source)
-        .arg(dest)
-        .output()
-        .map_err(|e| Error::FailedToExecuteSCP(e))
-}
-
-/// Sync files from/to the devkit
-fn execute_simple_rsync<S, D>(
-    addr: &str,
-    username: &str,
-    source: S,
-    dest: D,
-) -> Result<std::process::Output>
-where
-    S: AsRef<Path>,
-    D: AsRef<Path>,
-{
-    let host = format!("{}@{}", username, addr);
-
-    let source = source.as_ref();
-    let dest = dest.as_ref();
-    let dest = format!("{}:{}", host, dest.to_str().unwrap());
-
-    let key = get_private_key_path();
-
-    Command::new("rsync")
-        .arg("-e")
-        .arg(format!("ssh -i \"{}\"", key.to_str().unwrap()))
-        .arg("-r")
-        .arg(source)
-        .arg(dest)
-        .output()
-        .map_err(|e| Error::FailedToExecuteRSync(e))
+    std::fs::write(&path, manifest::template(&game_id, exec))
+        .map_err(Error::FailedToWriteManifest)?;
+
+    println!("Created {}", path.display());
+
+    Ok(())
 }
 
-/// Check if we already is registered
-fn check_if_registered(addr: &str, username: &str) -> Result<bool> {
-    let output = execute_simple_ssh(addr, username, "ls")?;
+/// Remove a deployed game from the devkit
+fn remove(
+    device: &Device,
+    transport: &dyn Transport,
+    game_id: &str,
+    artwork: bool,
+    compat_tool: bool,
+) -> Result<()> {
+    decker_util::upload(device, transport)?;
+
+    let command = UtilCommand::RemoveShortcut {
+        game_id: game_id.to_string(),
+        keep_files: false,
+        artwork,
+        compat_tool,
+    };
+    let output = decker_util::call(
+        device,
+        transport,
+        command,
+        Error::FailedToRemoveShortcut,
+    )?;
+    let output = match output {
+        Output::RemoveShortcut(output) => output,
+        _ => {
+            return Err(Error::FailedToRemoveShortcut(
+                UtilError::UnexpectedOutput,
+            ))
+        }
+    };
+
+    if output.removed_files {
+        println!("Removed the game files for '{}'", output.game_id);
+    }
 
-    Ok(output.status.success())
+    for user in output.users.iter() {
+        let appids: Vec<String> =
+            user.appids.iter().map(|a| a.to_string()).collect();
+        println!(
+            "User {}: removed shortcut (appid {})",
+            user.user,
+            appids.join(", ")
+        );
+
+        for path in user.artwork.iter() {
+            println!("  Removed artwork {}", path);
+        }
+    }
+
+    if !output.compat_tool.is_empty() {
+        let appids: Vec<String> =
+            output.compat_tool.iter().map(|a| a.to_string()).collect();
+        println!("Removed compatibility tool for appid {}", appids.join(", "));
+    }
+
+    if let Some(error) = output.compat_tool_error {
+        println!("Warning: {}", error);
+    }
+
+    for error in output.errors.iter() {
+        println!("Warning: user {}: {}", error.user, error.error);
+    }
+
+    Ok(())
 }
 
-/// Debug print the output
-fn _simple_print_output(output: &std::process::Output) {
-    if output.status.success() {
-        println!("{}", std::str::from_utf8(&output.stdout).unwrap());
-    } else {
-        println!("Error: {}", std::str::from_utf8(&output.stderr).unwrap());
+/// Start the game through Steam, with wait the exit code of the game is
+/// returned when it exits
+fn run_game(
+    device: &Device,
+    transport: &dyn Transport,
+    game_id: &str,
+    wait: bool,
+) -> Result<Option<i32>> {
+    decker_util::upload(device, transport)?;
+
+    let command = UtilCommand::Run {
+        game_id: game_id.to_string(),
+        wait,
+    };
+    let output =
+        decker_util::call(device, transport, command, Error::FailedToRunGame)?;
+    let output = match output {
+        Output::Run(output) => output,
+        _ => return Err(Error::FailedToRunGame(UtilError::UnexpectedOutput)),
+    };
+
+    println!("Started '{}' (appid {})", game_id, output.appid);
+
+    let run = match output.run {
+        Some(run) => run,
+        None => return Ok(None),
+    };
+
+    match run.exit_code {
+        Some(code) => {
+            println!("'{}' exited with code {}", game_id, code);
+            Ok(Some(code))
+        }
+        None => {
+            // NOTE(patrik): The launcher got killed so there is no exit
+            // code, treat it like a crash
+            println!("'{}' stopped without an exit code", game_id);
+            Ok(Some(1))
+        }
     }
 }
 
-/// Deploy game to the devkit
-fn deploy(
-    addr: &str,
-    username: &str,
+/// Stop the processes of the game on the devkit, returns false if the game
+/// wasn't running
+fn kill_game(
+    device: &Device,
+    transport: &dyn Transport,
     game_id: &str,
-    exec: &str,
-    starting_dir: &str,
-    game_file_dir: &str,
+    grace_period: u64,
+) -> Result<bool> {
+    decker_util::upload(device, transport)?;
+
+    let command = UtilCommand::Kill {
+        game_id: game_id.to_string(),
+        grace_period,
+    };
+    let output = decker_util::call(
+        device,
+        transport,
+        command,
+        Error::FailedToKillGame,
+    )?;
+    let output = match output {
+        Output::Kill(output) => output,
+        _ => return Err(Error::FailedToKillGame(UtilError::UnexpectedOutput)),
+    };
+
+    if output.processes.is_empty() {
+        println!("'{}' is not running", game_id);
+        return Ok(false);
+    }
+
+    let mut failed = None;
+    for process in output.processes {
+        if let Some(error) = process.error {
+            println!(
+                "Failed to stop {} ({}): {}",
+                process.pid, process.name, error
+            );
+
+            failed.get_or_insert(decker_protocol::Error::KillFailed {
+                pid: process.pid,
+                message: error,
+            });
+        } else if process.killed {
+            println!(
+                "Killed {} ({}) after {} seconds",
+                process.pid, process.name, grace_period
+            );
+        } else {
+            println!("Stopped {} ({})", process.pid, process.name);
+        }
+    }
+
+    // NOTE(patrik): The processes that could be stopped are stopped, the
+    // command still fails so scripts know something is left running
+    if let Some(error) = failed {
+        return Err(Error::FailedToKillGame(UtilError::Failed(error)));
+    }
+
+    Ok(true)
+}
+
+/// Print the log of a run, with follow the log is printed until the game
+/// exits
+fn logs(
+    device: &Device,
+    transport: &dyn Transport,
+    game_id: &str,
+    follow: bool,
+    run: Option<u32>,
 ) -> Result<()> {
-    execute_simple_ssh(addr, username, "mkdir -p ~/decker")?;
+    decker_util::upload(device, transport)?;
+
+    let command = UtilCommand::Logs {
+        game_id: game_id.to_string(),
+        run,
+    };
+    let output = decker_util::call(
+        device,
+        transport,
+        command,
+        Error::FailedToReadLogs,
+    )?;
+    let output = match output {
+        Output::Logs(output) => output,
+        _ => return Err(Error::FailedToReadLogs(UtilError::UnexpectedOutput)),
+    };
+
+    // NOTE(patrik): The header goes to stderr so stdout only has the output
+    // of the game
+    let run = &output.run;
+    let started = format_time_ago(Some(run.started_at));
+    match run.exit_code {
+        Some(code) => eprintln!(
+            "Run {} of '{}', started {}, exited with code {}",
+            run.run, game_id, started, code
+        ),
+        None if run.finished_at.is_some() => eprintln!(
+            "Run {} of '{}', started {}, stopped without an exit code",
+            run.run, game_id, started
+        ),
+        None => {
+            eprintln!("Run {} of '{}', started {}", run.run, game_id, started)
+        }
+    }
 
-    {
-        let temp_file = mktemp::Temp::new_file().unwrap();
-        let mut file = File::create(&temp_file).unwrap();
-        file.write(DECKER_UTIL_PROGRAM).unwrap();
+    let cmd = if follow && run.finished_at.is_none() {
+        // NOTE(patrik): tail stops by itself when the launcher exits
+        RemoteCommand::new("tail")
+            .arg("-n")
+            .arg("+1")
+            .arg(&format!("--pid={}", run.pid))
+            .arg("-f")
+            .arg("--")
+            .path(&output.path)
+    } else {
+        RemoteCommand::new("cat").arg("--").path(&output.path)
+    };
+
+    let exit_code = transport.execute_streaming(&cmd)?;
+    if exit_code != 0 {
+        return Err(Error::FailedToStreamLogs(RemoteError {
+            exit_code,
+            stderr: String::new(),
+        }));
+    }
+
+    Ok(())
+}
+
+/// Format a size in bytes i.e "12.3 MiB"
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
 
-        execute_simple_scp(addr, username, temp_file, "~/decker/decker_util")?;
-        execute_simple_ssh(addr, username, "chmod +x ~/decker/decker_util")?;
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
     }
 
-    let cmd = format!("~/decker/decker_util prepare-upload {} true", game_id);
-    let _output = execute_simple_ssh(addr, username, &cmd);
-    // simple_print_output(&output);
+    if unit == 0 {
+        format!("{} {}", size, UNITS[unit])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
 
-    let cmd = format!(
-        "~/decker/decker_util create-shortcut {} {} {}",
-        game_id, exec, starting_dir
-    );
+/// Format a unix timestamp relative to now i.e "3 hours ago"
+fn format_time_ago(time: Option<u64>) -> String {
+    let time = match time {
+        Some(time) => time,
+        None => return "-".to_string(),
+    };
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    // NOTE(patrik): The clocks of the host and the devkit might not match
+    let secs = now.saturating_sub(time);
+
+    let (value, unit) = match secs {
+        0..=59 => return "just now".to_string(),
+        60..=3599 => (secs / 60, "minute"),
+        3600..=86399 => (secs / 3600, "hour"),
+        _ => (secs / 86400, "day"),
+    };
+
+    if value == 1 {
+        format!("1 {} ago", unit)
+    } else {
+        format!("{} {}s ago", value, unit)
+    }
+}
+
+/// Format a duration in seconds i.e "2d 3h 15m"
+fn format_duration(secs: u64) -> String {
+    let days = secs / 86400;
+    let hours = (secs % 86400) / 3600;
+    let minutes = (secs % 3600) / 60;
+
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, minutes)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else {
+        format!("{}m", minutes)
+    }
+}
+
+/// Print rows as a table with aligned columns
+fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in rows {
+        for (width, column) in widths.iter_mut().zip(row) {
+            *width = (*width).max(column.chars().count());
+        }
+    }
+
+    let print_row = |row: &[String]| {
+        let mut line = String::new();
+        for (column, width) in row.iter().zip(widths.iter()) {
+            line.push_str(&format!("{:<width$}  ", column, width = width));
+        }
+
+        println!("{}", line.trim_end());
+    };
+
+    let headers: Vec<String> = headers.iter().map(|h| h.to_string()).collect();
+    print_row(&headers);
+
+    for row in rows {
+        print_row(row);
+    }
+}
+
+/// Print the deployed games as a human readable table
+fn print_list(list: &List) {
+    if list.games.is_empty() {
+        println!("No games deployed");
+    } else {
+        let mut rows = Vec::new();
+        for game in list.games.iter() {
+            let size = format_size(game.size);
+            let deployed = format_time_ago(game.deployed_at);
+
+            if game.orphaned {
+                rows.push(vec![
+                    game.game_id.clone(),
+                    size,
+                    deployed,
+                    "-".to_string(),
+                    "-".to_string(),
+                    "(orphaned directory, no shortcut)".to_string(),
+                    String::new(),
+                ]);
+                continue;
+            }
 
-    let _output = execute_simple_ssh(addr, username, &cmd)?;
-    // TODO(patrik): Check for error from output
-    // simple_print_output(&output);
+            for shortcut in game.shortcuts.iter() {
+                rows.push(vec![
+                    game.game_id.clone(),
+                    size.clone(),
+                    deployed.clone(),
+                    shortcut.user.clone(),
+                    shortcut.appid.to_string(),
+                    shortcut.exe.clone(),
+                    shortcut.start_dir.clone(),
+                ]);
+            }
+        }
 
-    let mut game_file_dir = game_file_dir.to_string();
-    if game_file_dir.chars().nth(game_file_dir.len() - 1).unwrap() != '/' {
-        game_file_dir.push('/');
+        print_table(
+            &[
+                "GAME ID",
+                "SIZE",
+                "DEPLOYED",
+                "USER",
+                "APPID",
+                "EXE",
+                "START DIR",
+            ],
+            &rows,
+        );
     }
 
-    let source = game_file_dir;
+    if !list.orphaned_shortcuts.is_empty() {
+        println!();
+        println!("Orphaned shortcuts (the game directory is missing):");
+
+        let rows: Vec<Vec<String>> = list
+            .orphaned_shortcuts
+            .iter()
+            .map(|shortcut| {
+                vec![
+                    shortcut.game_id.clone(),
+                    shortcut.user.clone(),
+                    shortcut.appid.to_string(),
+                    shortcut.exe.clone(),
+                    shortcut.start_dir.clone(),
+                ]
+            })
+            .collect();
+
+        print_table(&["GAME ID", "USER", "APPID", "EXE", "START DIR"], &rows);
+    }
 
-    let dest = format!("~/decker-games/{}", game_id);
+    for error in list.errors.iter() {
+        println!();
+        println!("Warning: user {}: {}", error.user, error.error);
+    }
+}
+
+/// List the games deployed to the devkit
+fn list(device: &Device, transport: &dyn Transport, json: bool) -> Result<()> {
+    decker_util::upload(device, transport)?;
+
+    let output = decker_util::call(
+        device,
+        transport,
+        UtilCommand::List,
+        Error::FailedToListGames,
+    )?;
+    let list = match output {
+        Output::List(list) => list,
+        _ => {
+            return Err(Error::FailedToListGames(UtilError::UnexpectedOutput))
+        }
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&list).unwrap());
+        return Ok(());
+    }
 
-    let _output = execute_simple_rsync(addr, username, source, dest)?;
-    // simple_print_output(&output);
+    print_list(&list);
 
     Ok(())
 }
 
-/// Run shell on the devkit
-fn run_shell(addr: &str, username: &str) -> Result<()> {
-    let host = format!("{}@{}", username, addr);
+/// Show information about the devkit
+fn status(device: &Device, transport: &dyn Transport) -> Result<()> {
+    decker_util::upload(device, transport)?;
+
+    let output = decker_util::call(
+        device,
+        transport,
+        UtilCommand::Status,
+        Error::FailedToGetStatus,
+    )?;
+    let status = match output {
+        Output::Status(status) => status,
+        _ => {
+            return Err(Error::FailedToGetStatus(UtilError::UnexpectedOutput))
+        }
+    };
 
-    let key = get_private_key_path();
+    let disk = match (status.disk_free, status.disk_total) {
+        (Some(free), Some(total)) => {
+            format!("{} free of {}", format_size(free), format_size(total))
+        }
+        _ => "-".to_string(),
+    };
+
+    let battery = match (status.battery_level, status.battery_status) {
+        (Some(level), Some(status)) => format!("{}% ({})", level, status),
+        (Some(level), None) => format!("{}%", level),
+        _ => "-".to_string(),
+    };
+
+    let uptime = status.uptime.map(format_duration);
+    let steam = if status.steam_running {
+        "Running"
+    } else {
+        "Not running"
+    };
 
-    Command::new("ssh")
-        .arg("-oBatchMode=yes")
-        .arg("-oStrictHostKeyChecking=no")
-        .arg("-i")
-        .arg(key)
-        .arg(host)
-        .status()
-        .map_err(|e| Error::FailedToExecuteSSH(e))?;
+    println!(
+        "OS:        {}",
+        status.os.pretty_name.as_deref().unwrap_or("-")
+    );
+    println!("Name:      {}", status.os.name.as_deref().unwrap_or("-"));
+    println!(
+        "Variant:   {}",
+        status.os.variant_id.as_deref().unwrap_or("-")
+    );
+    println!(
+        "Version:   {}",
+        status.os.version_id.as_deref().unwrap_or("-")
+    );
+    println!(
+        "Build:     {}",
+        status.os.build_id.as_deref().unwrap_or("-")
+    );
+    println!("Disk:      {}", disk);
+    println!("Battery:   {}", battery);
+    println!("Uptime:    {}", uptime.as_deref().unwrap_or("-"));
+    println!("Steam:     {}", steam);
+
+    // NOTE(patrik): The devkit service is optional for the status, we
+    // already know the devkit is reachable over ssh
+    match device.devkit_client().properties() {
+        Ok(properties) => {
+            println!("Devkit service properties:");
+            for (key, value) in properties {
+                let value = match value {
+                    serde_json::Value::String(value) => value,
+                    value => value.to_string(),
+                };
+                println!("  {}: {}", key, value);
+            }
+        }
+        Err(_) => println!("Devkit service not reachable"),
+    }
 
     Ok(())
 }
 
-/// Run the program
-fn run(args: Args, addr: &str) -> Result<()> {
-    let username = "deck";
+/// List the devkits found on the local network
+fn discover(timeout: f64) -> Result<()> {
+    let timeout = std::time::Duration::from_secs_f64(timeout);
+    let devkits = mdns::discover(timeout).map_err(Error::DiscoveryFailed)?;
 
-    if !check_if_registered(addr, username)? {
-        register(addr)?;
+    if devkits.is_empty() {
+        println!("No devkits found");
+        return Ok(());
     }
 
-    match args.command {
-        ArgCommand::Deploy {
-            game_id,
-            exec,
-            starting_dir,
-            game_file_dir,
+    let rows: Vec<Vec<String>> = devkits
+        .iter()
+        .map(|devkit| {
+            let addresses: Vec<String> =
+                devkit.addresses.iter().map(|a| a.to_string()).collect();
+            let properties: Vec<String> = devkit
+                .properties
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect();
+
+            vec![
+                devkit.name.clone(),
+                devkit.hostname.clone(),
+                addresses.join(", "),
+                devkit.port.to_string(),
+                properties.join(" "),
+            ]
+        })
+        .collect();
+
+    print_table(
+        &["NAME", "HOSTNAME", "ADDRESS", "PORT", "PROPERTIES"],
+        &rows,
+    );
+
+    Ok(())
+}
+
+/// Manage the saved devices
+fn device_command(command: DeviceCommand) -> Result<()> {
+    let mut config = device::Config::load()?;
+
+    match command {
+        DeviceCommand::Add {
+            name,
+            address,
+            port,
+            username,
+            devkit_port,
+            key,
+            install_root,
+            default,
         } => {
-            // TODO(patrik): Extract the path to decker-games
-            let exec = format!(
-                "/home/{}/decker-games/{}/{}",
-                username, game_id, exec
+            let device = Device {
+                address,
+                port,
+                username,
+                devkit_port,
+                key,
+                install_root,
+            };
+
+            let updated =
+                config.devices.insert(name.clone(), device).is_some();
+
+            // NOTE(patrik): The first device becomes the default device
+            if default || config.default.is_none() {
+                config.default = Some(name.clone());
+            }
+
+            config.save()?;
+
+            if updated {
+                println!("Updated device '{}'", name);
+            } else {
+                println!("Added device '{}'", name);
+            }
+        }
+
+        DeviceCommand::Remove { name } => {
+            if config.devices.remove(&name).is_none() {
+                return Err(Error::UnknownDevice(name));
+            }
+
+            if config.default.as_ref() == Some(&name) {
+                config.default = None;
+            }
+
+            config.save()?;
+
+            println!("Removed device '{}'", name);
+        }
+
+        DeviceCommand::List => {
+            if config.devices.is_empty() {
+                println!("No devices, add one with 'decker device add'");
+                return Ok(());
+            }
+
+            let rows: Vec<Vec<String>> = config
+                .devices
+                .iter()
+                .map(|(name, device)| {
+                    let default = config.default.as_ref() == Some(name);
+
+                    vec![
+                        if default { "*" } else { "" }.to_string(),
+                        name.clone(),
+                        device.address.clone(),
+                        device.port.to_string(),
+                        device.username.as_deref().unwrap_or("-").to_string(),
+                        device.devkit_port.to_string(),
+                        device.key_path().display().to_string(),
+                        device.install_root(),
+                    ]
+                })
+                .collect();
+
+            print_table(
+                &[
+                    "",
+                    "NAME",
+                    "ADDRESS",
+                    "PORT",
+                    "USER",
+                    "DEVKIT PORT",
+                    "KEY",
+                    "INSTALL ROOT",
+                ],
+                &rows,
             );
+        }
 
-            let starting_dir = if let Some(starting_dir) = starting_dir {
-                starting_dir
+        DeviceCommand::SetDefault { name } => {
+            if !config.devices.contains_key(&name) {
+                return Err(Error::UnknownDevice(name));
+            }
+
+            config.default = Some(name.clone());
+            config.save()?;
+
+            println!("Default device is now '{}'", name);
+        }
+    }
+
+    Ok(())
+}
+
+/// Run the program
+fn run(
+    command: ArgCommand,
+    device: &Device,
+    transport_kind: TransportKind,
+) -> Result<()> {
+    // NOTE(patrik): Catch a bad game id before connecting to the devkit
+    if let ArgCommand::Remove { game_id, .. } |
+    ArgCommand::Kill { game_id, .. } |
+    ArgCommand::Logs { game_id, .. } = &command
+    {
+        check_game_id(game_id)?;
+    }
+
+    let transport = match transport::connect(device, transport_kind) {
+        Err(Error::SSHAuthenticationFailed) => {
+            register(device)?;
+            transport::connect(device, transport_kind)?
+        }
+        transport => transport?,
+    };
+    let transport = transport.as_ref();
+
+    match command {
+        ArgCommand::Deploy {
+            deploy: args,
+            watch,
+            restart,
+        } => {
+            let config = DeployConfig::new(args, device)?;
+            if watch {
+                watch::watch(device, transport, &config, restart)?;
+            } else {
+                deploy(device, transport, &config)?;
+            }
+        }
+
+        // NOTE(patrik): Handled in main because they don't need a devkit
+        ArgCommand::Init { .. } |
+        ArgCommand::Discover { .. } |
+        ArgCommand::Device { .. } => unreachable!(),
+
+        ArgCommand::Remove {
+            game_id,
+            artwork,
+            compat_tool,
+        } => remove(device, transport, &game_id, artwork, compat_tool)?,
+
+        ArgCommand::List { json } => list(device, transport, json)?,
+
+        ArgCommand::Status => status(device, transport)?,
+
+        ArgCommand::Run {
+            deploy: args,
+            wait,
+            deploy_first,
+        } => {
+            let game_id = if deploy_first {
+                let config = DeployConfig::new(args, device)?;
+                deploy(device, transport, &config)?;
+                config.game_id
             } else {
-                // TODO(patrik): Extract the path to decker-games
-                format!("/home/{}/decker-games/{}", username, game_id)
+                find_game_id(&args)?
             };
 
-            deploy(
-                &addr,
-                username,
-                &game_id,
-                &exec,
-                &starting_dir,
-                &game_file_dir,
-            )?;
+            // NOTE(patrik): decker exits with the exit code of the game so
+            // scripts can check it
+            if let Some(code) = run_game(device, transport, &game_id, wait)? {
+                std::process::exit(code);
+            }
+        }
+
+        ArgCommand::Kill {
+            game_id,
+            grace_period,
+        } => {
+            kill_game(device, transport, &game_id, grace_period)?;
         }
 
-        ArgCommand::Shell => run_shell(&addr, "deck")?,
+        ArgCommand::Logs {
+            game_id,
+            follow,
+            run,
+        } => logs(device, transport, &game_id, follow, run)?,
+
+        ArgCommand::Shell => transport.shell()?,
     }
 
     Ok(())
 }
 
-/// Entry point
-fn main() -> Result<()> {
-    let args = Args::parse();
+/// Print the error and the errors that caused it to stderr
+fn print_error(error: &Error, exit_code: i32, json: bool) {
+    let mut causes = Vec::new();
+    let mut source = std::error::Error::source(error);
+    while let Some(e) = source {
+        causes.push(e.to_string());
+        source = e.source();
+    }
+
+    if json {
+        let data = serde_json::json!({
+            "error": {
+                "kind": error.kind(),
+                "message": error.to_string(),
+                "causes": causes,
+                "exit_code": exit_code,
+            }
+        });
 
-    let addr = args.devkit_addr.clone();
+        eprintln!("{}", data);
+    } else {
+        eprintln!("Error: {}", error);
+        for cause in causes {
+            eprintln!("  Caused by: {}", cause);
+        }
+    }
+}
 
+/// Run the command line
+fn start(args: Args) -> Result<()> {
     let path = get_data_dir();
     std::fs::create_dir_all(path).unwrap();
 
-    println!("Device Address: {}", addr);
+    let command = match args.command {
+        ArgCommand::Init {
+            game_id,
+            exec,
+            force,
+        } => return init(game_id, &exec, force),
+
+        ArgCommand::Discover { timeout } => return discover(timeout),
+
+        ArgCommand::Device { command } => return device_command(command),
+
+        command => command,
+    };
 
-    run(args, &addr)
+    let config = device::Config::load()?;
+    let mut device = config.resolve(args.devkit_addr.as_deref())?;
+    device.resolve_username();
+
+    // NOTE(patrik): stderr so stdout only has the output of the command,
+    // i.e. the log of the game or the JSON from list
+    eprintln!("Device Address: {}", device.address);
+
+    run(command, &device, args.transport)
+}
+
+/// Entry point
+fn main() {
+    // NOTE(patrik): With 'run --wait' decker exits with the exit code of the
+    // game, its own errors get a code of their own so they can't be mistaken
+    // for the exit code of the game
+    let args = match Args::try_parse() {
+        Ok(args) => args,
+        Err(e)
+            if e.use_stderr() && std::env::args().any(|a| a == "--wait") =>
+        {
+            let _ = e.print();
+            std::process::exit(EXIT_RUN_FAILED);
+        }
+        Err(e) => e.exit(),
+    };
+    let json = args.json;
+    let waits = matches!(args.command, ArgCommand::Run { wait: true, .. });
+
+    if let Err(e) = start(args) {
+        let exit_code = if waits {
+            EXIT_RUN_FAILED
+        } else {
+            e.exit_code()
+        };
+        print_error(&e, exit_code, json);
+        std::process::exit(exit_code);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deploy_args(manifest: &Path) -> DeployArgs {
+        DeployArgs {
+            game_id: None,
+            exec: None,
+            game_file_dir: None,
+            starting_dir: None,
+            name: None,
+            args: Vec::new(),
+            exclude: Vec::new(),
+            env: Vec::new(),
+            launch_options: None,
+            manifest: Some(manifest.to_path_buf()),
+        }
+    }
+
+    const MANIFEST: &str = r#"
+game_id = "manifest-game"
+name = "Manifest Game"
+exec = "bin/game"
+args = ["--manifest"]
+starting_dir = "./bin/"
+source_dir = "build"
+exclude = [".git"]
+launch_options = "gamemoderun %command%"
+
+[env]
+A = "1"
+B = "two words"
+"#;
+
+    #[test]
+    fn deploy_config_from_manifest() {
+        let temp = mktemp::Temp::new_dir().unwrap();
+        let dir = temp.to_path_buf();
+        let path = dir.join("decker.toml");
+        std::fs::write(&path, MANIFEST).unwrap();
+
+        let device = Device::new("127.0.0.1".to_string());
+        let config = DeployConfig::new(deploy_args(&path), &device).unwrap();
+
+        assert_eq!(config.game_id, "manifest-game");
+        assert_eq!(config.name.as_deref(), Some("Manifest Game"));
+        assert_eq!(config.game_dir, "/home/deck/decker-games/manifest-game");
+        assert_eq!(
+            config.exec,
+            "/home/deck/decker-games/manifest-game/bin/game"
+        );
+        assert_eq!(
+            config.starting_dir,
+            "/home/deck/decker-games/manifest-game/bin"
+        );
+        assert_eq!(config.source_dir, dir.join("build"));
+        assert_eq!(config.exclude, [".git"]);
+        assert_eq!(
+            config.launch_options,
+            "A=1 B='two words' gamemoderun %command% --manifest"
+        );
+    }
+
+    #[test]
+    fn deploy_config_arguments_win() {
+        let temp = mktemp::Temp::new_dir().unwrap();
+        let dir = temp.to_path_buf();
+        let path = dir.join("decker.toml");
+        std::fs::write(&path, MANIFEST).unwrap();
+
+        let args = DeployArgs {
+            game_id: Some("cli-game".to_string()),
+            exec: Some("run.sh".to_string()),
+            game_file_dir: Some(PathBuf::from("/src")),
+            starting_dir: Some("/opt".to_string()),
+            name: Some("Cli Game".to_string()),
+            args: vec!["--cli".to_string()],
+            exclude: vec!["*.log".to_string()],
+            env: vec![("B".to_string(), "3".to_string())],
+            launch_options: Some("mangohud".to_string()),
+            ..deploy_args(&path)
+        };
+
+        let mut device = Device::new("127.0.0.1".to_string());
+        device.username = Some("dev".to_string());
+        let config = DeployConfig::new(args, &device).unwrap();
+
+        assert_eq!(config.game_id, "cli-game");
+        assert_eq!(config.name.as_deref(), Some("Cli Game"));
+        assert_eq!(config.exec, "/home/dev/decker-games/cli-game/run.sh");
+        assert_eq!(config.starting_dir, "/opt");
+        assert_eq!(config.source_dir, PathBuf::from("/src"));
+
+        // The args and launch options are replaced, the excludes and the
+        // env vars are merged
+        assert_eq!(config.exclude, [".git", "*.log"]);
+        assert_eq!(config.launch_options, "A=1 B=3 %command% mangohud --cli");
+    }
+
+    #[test]
+    fn run_deploy_options_need_deploy_first() {
+        let parse = |args: &[&str]| {
+            Args::try_parse_from(["decker", "run"].iter().chain(args))
+        };
+
+        assert!(parse(&["game"]).is_ok());
+        assert!(parse(&["game", "--wait", "--manifest", "x.toml"]).is_ok());
+        assert!(parse(&["game", "run.sh"]).is_err());
+        assert!(parse(&["game", "--starting-dir", "bin"]).is_err());
+        assert!(parse(&["game", "run.sh", "./build"]).is_err());
+        assert!(parse(&["game", "--env", "A=1", "--name", "Game"]).is_err());
+        assert!(
+            parse(&["game", "run.sh", "./build", "--deploy-first"]).is_ok()
+        );
+    }
+
+    #[test]
+    fn deploy_config_missing_options() {
+        let temp = mktemp::Temp::new_dir().unwrap();
+        let dir = temp.to_path_buf();
+        let path = dir.join("decker.toml");
+        std::fs::write(&path, "game_id = \"game\"\n").unwrap();
+
+        let device = Device::new("127.0.0.1".to_string());
+        assert!(matches!(
+            DeployConfig::new(deploy_args(&path), &device),
+            Err(Error::MissingDeployOption("exec"))
+        ));
+
+        let args = DeployArgs {
+            exec: Some("run.sh".to_string()),
+            ..deploy_args(&path)
+        };
+        assert!(matches!(
+            DeployConfig::new(args, &device),
+            Err(Error::MissingDeployOption("game_file_dir"))
+        ));
+
+        let args = DeployArgs {
+            game_id: Some("../escape".to_string()),
+            ..deploy_args(&path)
+        };
+        assert!(matches!(
+            DeployConfig::new(args, &device),
+            Err(Error::InvalidGameId(_))
+        ));
+    }
 }
@@ -0,0 +1,279 @@
+//! The project manifest (decker.toml), holds the deploy options so they
+//! don't need to be typed out on every deploy
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::{Error, Result};
+
+/// Name of the manifest file in the project directory
+pub const MANIFEST_FILE: &str = "decker.toml";
+
+/// Artwork for the shortcut, the paths are relative to the manifest
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Artwork {
+    /// The portrait capsule shown in the library (600x900)
+    pub grid: Option<PathBuf>,
+
+    /// The wide capsule shown in the recent games (920x430)
+    pub wide_grid: Option<PathBuf>,
+
+    /// The banner on the top of the game page (3840x1240)
+    pub hero: Option<PathBuf>,
+
+    /// The logo on top of the hero
+    pub logo: Option<PathBuf>,
+
+    /// The icon of the shortcut
+    pub icon: Option<PathBuf>,
+}
+
+impl Artwork {
+    /// The artwork that is set as (name, path) pairs, the name is what
+    /// decker_util expects
+    pub fn files(&self) -> Vec<(&'static str, &Path)> {
+        let files = [
+            ("grid", &self.grid),
+            ("wide_grid", &self.wide_grid),
+            ("hero", &self.hero),
+            ("logo", &self.logo),
+            ("icon", &self.icon),
+        ];
+
+        files
+            .into_iter()
+            .filter_map(|(name, path)| Some((name, path.as_deref()?)))
+            .collect()
+    }
+}
+
+/// The content of decker.toml, everything is optional because the command
+/// line can fill in the rest
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Manifest {
+    /// The game id the deployment should use
+    pub game_id: Option<String>,
+
+    /// The name shown in Steam, defaults to "Decker: <game_id>"
+    pub name: Option<String>,
+
+    /// The program to run, relative to the game directory on the devkit
+    pub exec: Option<String>,
+
+    /// Arguments passed to the program
+    pub args: Vec<String>,
+
+    /// The starting directory on the devkit, relative to the game directory
+    pub starting_dir: Option<String>,
+
+    /// The directory with the game files, relative to the manifest
+    pub source_dir: Option<PathBuf>,
+
+    /// Patterns of files that should not be sent to the devkit
+    pub exclude: Vec<String>,
+
+    /// Environment variables set when the game runs
+    pub env: BTreeMap<String, String>,
+
+    /// Extra Steam launch options i.e "gamemoderun %command%"
+    pub launch_options: Option<String>,
+
+    pub artwork: Artwork,
+}
+
+impl Manifest {
+    /// Load the manifest, relative paths gets resolved against the
+    /// directory of the manifest
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .map_err(Error::FailedToReadManifest)?;
+        let mut manifest: Manifest =
+            toml::from_str(&data).map_err(Error::FailedToParseManifest)?;
+
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+        let resolve = |path: &mut Option<PathBuf>| {
+            if let Some(path) = path {
+                *path = dir.join(&path);
+            }
+        };
+
+        resolve(&mut manifest.source_dir);
+        resolve(&mut manifest.artwork.grid);
+        resolve(&mut manifest.artwork.wide_grid);
+        resolve(&mut manifest.artwork.hero);
+        resolve(&mut manifest.artwork.logo);
+        resolve(&mut manifest.artwork.icon);
+
+        Ok(manifest)
+    }
+
+    /// Load the manifest from the path or from the current directory,
+    /// it's only an error for the manifest to be missing if the path was
+    /// given
+    pub fn find(path: Option<&Path>) -> Result<Option<Self>> {
+        match path {
+            Some(path) => Self::load(path).map(Some),
+            None => {
+                let path = Path::new(MANIFEST_FILE);
+                if path.exists() {
+                    Self::load(path).map(Some)
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+}
+
+/// The content of a new manifest created by 'decker init'
+pub fn template(game_id: &str, exec: &str) -> String {
+    format!(
+        r#"# The game id the deployment should use, the files end up in
+# ~/decker-games/<game_id> on the devkit
+game_id = {game_id}
+
+# The name shown in Steam
+name = {game_id}
+
+# The program to run, relative to the game directory
+exec = {exec}
+
+# Arguments passed to the program
+# args = ["--fullscreen"]
+
+# The starting directory, relative to the game directory
+# starting_dir = "."
+
+# The directory with the game files, relative to this file
+source_dir = "."
+
+# Files that should not be sent to the devkit
+exclude = [".git", "decker.toml"]
+
+# Extra Steam launch options
+# launch_options = "gamemoderun %command%"
+
+# Environment variables set when the game runs
+[env]
+# RUST_LOG = "info"
+
+# Artwork for the shortcut, relative to this file
+[artwork]
+# grid = "artwork/grid.png"
+# wide_grid = "artwork/wide_grid.png"
+# hero = "artwork/hero.png"
+# logo = "artwork/logo.png"
+# icon = "artwork/icon.png"
+"#,
+        game_id = toml::Value::from(game_id),
+        exec = toml::Value::from(exec),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load() {
+        let temp = mktemp::Temp::new_dir().unwrap();
+        let dir = temp.to_path_buf();
+        let path = dir.join(MANIFEST_FILE);
+        std::fs::write(
+            &path,
+            r#"
+game_id = "game"
+name = "The Game"
+exec = "bin/game"
+args = ["--windowed", "--level", "2"]
+starting_dir = "bin"
+source_dir = "build"
+exclude = [".git"]
+launch_options = "gamemoderun %command%"
+
+[env]
+RUST_LOG = "debug"
+
+[artwork]
+grid = "art/grid.png"
+icon = "/abs/icon.png"
+"#,
+        )
+        .unwrap();
+
+        let manifest = Manifest::load(&path).unwrap();
+        assert_eq!(manifest.game_id.as_deref(), Some("game"));
+        assert_eq!(manifest.name.as_deref(), Some("The Game"));
+        assert_eq!(manifest.exec.as_deref(), Some("bin/game"));
+        assert_eq!(manifest.args, ["--windowed", "--level", "2"]);
+        assert_eq!(manifest.starting_dir.as_deref(), Some("bin"));
+        assert_eq!(manifest.exclude, [".git"]);
+        assert_eq!(manifest.env["RUST_LOG"], "debug");
+        assert_eq!(
+            manifest.launch_options.as_deref(),
+            Some("gamemoderun %command%")
+        );
+
+        // The paths are relative to the manifest
+        assert_eq!(manifest.source_dir, Some(dir.join("build")));
+        assert_eq!(
+            manifest.artwork.files(),
+            [
+                ("grid", dir.join("art/grid.png").as_path()),
+                ("icon", Path::new("/abs/icon.png")),
+            ]
+        );
+    }
+
+    #[test]
+    fn everything_is_optional() {
+        let manifest: Manifest = toml::from_str("").unwrap();
+        assert!(manifest.game_id.is_none());
+        assert!(manifest.exec.is_none());
+        assert!(manifest.args.is_empty());
+        assert!(manifest.env.is_empty());
+        assert!(manifest.artwork.files().is_empty());
+    }
+
+    #[test]
+    fn errors() {
+        let temp = mktemp::Temp::new_dir().unwrap();
+        let dir = temp.to_path_buf();
+
+        let path = dir.join(MANIFEST_FILE);
+        std::fs::write(&path, "game_id = \"game\"\nexe = \"typo\"\n").unwrap();
+        assert!(matches!(
+            Manifest::load(&path),
+            Err(Error::FailedToParseManifest(_))
+        ));
+
+        std::fs::write(&path, "[artwork]\nbanner = \"x.png\"\n").unwrap();
+        assert!(matches!(
+            Manifest::load(&path),
+            Err(Error::FailedToParseManifest(_))
+        ));
+
+        // A manifest given on the command line has to exist
+        assert!(matches!(
+            Manifest::find(Some(&dir.join("missing.toml"))),
+            Err(Error::FailedToReadManifest(_))
+        ));
+    }
+
+    #[test]
+    fn template_parses() {
+        let data = template("my \"game\"", "run game.sh");
+        let manifest: Manifest = toml::from_str(&data).unwrap();
+
+        assert_eq!(manifest.game_id.as_deref(), Some("my \"game\""));
+        assert_eq!(manifest.name.as_deref(), Some("my \"game\""));
+        assert_eq!(manifest.exec.as_deref(), Some("run game.sh"));
+        assert_eq!(manifest.source_dir, Some(PathBuf::from(".")));
+        assert_eq!(manifest.exclude, [".git", "decker.toml"]);
+    }
+}
@@ -0,0 +1,571 @@
+//! Find devkits on the local network with mDNS
+//!
+//! The devkit service advertises itself as "_steamos-devkit._tcp.local", we
+//! send a PTR query for that service and collect the PTR, SRV, TXT and A/AAAA
+//! records in the answers.
+//!
+//! NOTE(patrik): The query is sent from a random port, that makes it a
+//! "legacy unicast" query (RFC 6762, section 6.7) so the responders answer
+//! directly to us and we don't need to bind port 5353
+
+use std::collections::BTreeMap;
+use std::net::{
+    IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, ToSocketAddrs,
+    UdpSocket,
+};
+use std::time::{Duration, Instant};
+
+/// The service the devkit service advertises
+pub const SERVICE: &str = "_steamos-devkit._tcp.local";
+
+/// The mDNS multicast address and port
+pub const MDNS_ADDR: SocketAddrV4 =
+    SocketAddrV4::new(Ipv4Addr::new(224, 0, 0, 251), 5353);
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+
+const CLASS_IN: u16 = 1;
+
+/// A devkit found on the network
+#[derive(Clone, Debug, PartialEq)]
+pub struct Devkit {
+    /// The service instance name i.e "steamdeck"
+    pub name: String,
+
+    /// The hostname from the SRV record i.e "steamdeck.local"
+    pub hostname: String,
+
+    pub addresses: Vec<IpAddr>,
+
+    /// The port of the devkit service
+    pub port: u16,
+
+    /// The TXT properties
+    pub properties: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum RecordData {
+    Ptr(String),
+    Srv { port: u16, target: String },
+    Txt(BTreeMap<String, String>),
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Other,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Record {
+    name: String,
+    data: RecordData,
+}
+
+fn write_name(buf: &mut Vec<u8>, name: &str) {
+    for label in name.trim_end_matches('.').split('.') {
+        buf.push(label.len() as u8);
+        buf.extend_from_slice(label.as_bytes());
+    }
+
+    buf.push(0);
+}
+
+/// Build the PTR query for the service
+fn build_query(service: &str) -> Vec<u8> {
+    let mut buf = Vec::new();
+
+    // Header: id, flags, qdcount, ancount, nscount, arcount
+    buf.extend_from_slice(&[0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+
+    write_name(&mut buf, service);
+    buf.extend_from_slice(&TYPE_PTR.to_be_bytes());
+    buf.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+    buf
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+/// Read a possibly compressed name, returns the name and the offset after
+/// the name
+fn read_name(data: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+
+    // NOTE(patrik): Guard against pointer loops in broken packets
+    for _ in 0..128 {
+        let len = *data.get(offset)? as usize;
+
+        if len == 0 {
+            let end = end.unwrap_or(offset + 1);
+            return Some((labels.join("."), end));
+        }
+
+        if len & 0xc0 == 0xc0 {
+            let pointer = read_u16(data, offset)? as usize & 0x3fff;
+            if end.is_none() {
+                end = Some(offset + 2);
+            }
+
+            offset = pointer;
+            continue;
+        }
+
+        let label = data.get(offset + 1..offset + 1 + len)?;
+        labels.push(String::from_utf8_lossy(label).to_string());
+        offset += 1 + len;
+    }
+
+    None
+}
+
+fn parse_txt(data: &[u8]) -> BTreeMap<String, String> {
+    let mut result = BTreeMap::new();
+
+    let mut offset = 0;
+    while offset < data.len() {
+        let len = data[offset] as usize;
+        let entry = match data.get(offset + 1..offset + 1 + len) {
+            Some(entry) => String::from_utf8_lossy(entry),
+            None => break,
+        };
+        offset += 1 + len;
+
+        if entry.is_empty() {
+            continue;
+        }
+
+        match entry.split_once('=') {
+            Some((key, value)) => {
+                result.insert(key.to_string(), value.to_string())
+            }
+            None => result.insert(entry.to_string(), String::new()),
+        };
+    }
+
+    result
+}
+
+/// Parse all the resource records in a packet, returns None if the packet
+/// is broken
+fn parse_packet(data: &[u8]) -> Option<Vec<Record>> {
+    let flags = read_u16(data, 2)?;
+
+    // NOTE(patrik): Only responses, we might see our own query
+    if flags & 0x8000 == 0 {
+        return Some(Vec::new());
+    }
+
+    let qdcount = read_u16(data, 4)?;
+    let rrcount = read_u16(data, 6)? as usize +
+        read_u16(data, 8)? as usize +
+        read_u16(data, 10)? as usize;
+
+    let mut offset = 12;
+    for _ in 0..qdcount {
+        let (_, end) = read_name(data, offset)?;
+        offset = end + 4;
+    }
+
+    let mut records = Vec::new();
+    for _ in 0..rrcount {
+        let (name, end) = read_name(data, offset)?;
+        let typ = read_u16(data, end)?;
+        let len = read_u16(data, end + 8)? as usize;
+
+        let start = end + 10;
+        let rdata = data.get(start..start + len)?;
+
+        let data = match typ {
+            TYPE_PTR => RecordData::Ptr(read_name(data, start)?.0),
+            TYPE_SRV => RecordData::Srv {
+                port: read_u16(data, start + 4)?,
+                target: read_name(data, start + 6)?.0,
+            },
+            TYPE_TXT => RecordData::Txt(parse_txt(rdata)),
+            TYPE_A if len == 4 => RecordData::A(Ipv4Addr::new(
+                rdata[0], rdata[1], rdata[2], rdata[3],
+            )),
+            TYPE_AAAA if len == 16 => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(rdata);
+                RecordData::Aaaa(Ipv6Addr::from(octets))
+            }
+            _ => RecordData::Other,
+        };
+
+        records.push(Record { name, data });
+        offset = start + len;
+    }
+
+    Some(records)
+}
+
+/// Put the records together into devkits, `sources` is where each packet
+/// came from and is used if there is no address record for the host
+fn collect_devkits(
+    service: &str,
+    records: &[Record],
+    sources: &[(String, IpAddr)],
+) -> Vec<Devkit> {
+    let mut result = Vec::new();
+    let mut seen = Vec::new();
+
+    let instances = records.iter().filter_map(|r| match &r.data {
+        RecordData::Ptr(instance) if r.name.eq_ignore_ascii_case(service) => {
+            Some(instance)
+        }
+        _ => None,
+    });
+
+    for instance in instances {
+        // NOTE(patrik): The devkit can answer more than once
+        if seen.contains(&instance) {
+            continue;
+        }
+        seen.push(instance);
+
+        let mut srv = None;
+        let mut properties = BTreeMap::new();
+        for record in records.iter() {
+            if !record.name.eq_ignore_ascii_case(instance) {
+                continue;
+            }
+
+            match &record.data {
+                RecordData::Srv { port, target } if srv.is_none() => {
+                    srv = Some((*port, target.clone()))
+                }
+                RecordData::Txt(txt) => properties = txt.clone(),
+                _ => {}
+            }
+        }
+
+        let (port, hostname) = match srv {
+            Some(srv) => srv,
+            None => continue,
+        };
+
+        let mut addresses: Vec<IpAddr> = records
+            .iter()
+            .filter(|r| r.name.eq_ignore_ascii_case(&hostname))
+            .filter_map(|r| match r.data {
+                RecordData::A(addr) => Some(IpAddr::V4(addr)),
+                RecordData::Aaaa(addr) => Some(IpAddr::V6(addr)),
+                _ => None,
+            })
+            .collect();
+        addresses.dedup();
+
+        if addresses.is_empty() {
+            addresses.extend(
+                sources
+                    .iter()
+                    .filter(|(name, _)| name.eq_ignore_ascii_case(instance))
+                    .map(|(_, addr)| *addr)
+                    .take(1),
+            );
+        }
+
+        let suffix = format!(".{}", service);
+        let name = match instance.len().checked_sub(suffix.len()) {
+            Some(len) if instance[len..].eq_ignore_ascii_case(&suffix) => {
+                instance[..len].to_string()
+            }
+            _ => instance.clone(),
+        };
+
+        result.push(Devkit {
+            name,
+            hostname,
+            addresses,
+            port,
+            properties,
+        });
+    }
+
+    result
+}
+
+/// Send the query to `target` and collect the answers until the timeout
+pub fn discover_on(
+    target: SocketAddr,
+    timeout: Duration,
+) -> std::io::Result<Vec<Devkit>> {
+    let bind_addr: SocketAddr = if target.ip().is_loopback() {
+        (Ipv4Addr::LOCALHOST, 0).into()
+    } else {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    };
+
+    let socket = UdpSocket::bind(bind_addr)?;
+    socket.set_multicast_ttl_v4(255)?;
+    socket.send_to(&build_query(SERVICE), target)?;
+
+    let mut records = Vec::new();
+    let mut sources = Vec::new();
+
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0; 9000];
+
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+
+        socket.set_read_timeout(Some(deadline - now))?;
+
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(res) => res,
+            Err(e)
+                if e.kind() == std::io::ErrorKind::WouldBlock ||
+                    e.kind() == std::io::ErrorKind::TimedOut =>
+            {
+                break
+            }
+            Err(e) => return Err(e),
+        };
+
+        if let Some(packet) = parse_packet(&buf[..len]) {
+            for record in packet.iter() {
+                if let RecordData::Ptr(instance) = &record.data {
+                    sources.push((instance.clone(), from.ip()));
+                }
+            }
+
+            records.extend(packet);
+        }
+    }
+
+    Ok(collect_devkits(SERVICE, &records, &sources))
+}
+
+/// Find the devkits on the local network
+pub fn discover(timeout: Duration) -> std::io::Result<Vec<Devkit>> {
+    discover_on(MDNS_ADDR.into(), timeout)
+}
+
+/// Check if the name can only be found with mDNS, a '.local' hostname or a
+/// bare name like the service name of a devkit
+fn is_mdns_name(name: &str) -> bool {
+    let name = name.trim_end_matches('.');
+    name.to_lowercase().ends_with(".local") || !name.contains('.')
+}
+
+/// Get the address of a devkit from its hostname or service name, returns
+/// None without looking if `name` is an ip address or a hostname that
+/// resolves through DNS
+pub fn find_address(name: &str) -> Option<IpAddr> {
+    if name.parse::<IpAddr>().is_ok() {
+        return None;
+    }
+
+    // NOTE(patrik): The scan takes a couple of seconds, only do it when
+    // DNS can't find the name
+    if !is_mdns_name(name) && (name, 22).to_socket_addrs().is_ok() {
+        return None;
+    }
+
+    let name = name.trim_end_matches('.');
+    let short = |s: &str| s.trim_end_matches(".local").to_lowercase();
+
+    let devkits = discover(Duration::from_secs(2)).ok()?;
+    devkits
+        .into_iter()
+        .find(|d| {
+            short(&d.hostname) == short(name) ||
+                d.name.eq_ignore_ascii_case(name)
+        })
+        .and_then(|d| d.addresses.first().copied())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mdns_names() {
+        assert!(is_mdns_name("steamdeck.local"));
+        assert!(is_mdns_name("steamdeck.LOCAL."));
+        assert!(is_mdns_name("steamdeck"));
+        assert!(!is_mdns_name("deck.lan"));
+        assert!(!is_mdns_name("deck.example.com"));
+    }
+
+    use std::thread;
+
+    fn write_record(buf: &mut Vec<u8>, name: &str, typ: u16, rdata: &[u8]) {
+        write_name(buf, name);
+        buf.extend_from_slice(&typ.to_be_bytes());
+        buf.extend_from_slice(&(CLASS_IN | 0x8000).to_be_bytes());
+        buf.extend_from_slice(&120u32.to_be_bytes());
+        buf.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        buf.extend_from_slice(rdata);
+    }
+
+    fn name_bytes(name: &str) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_name(&mut buf, name);
+        buf
+    }
+
+    /// What a devkit answers, without name compression
+    fn build_response(
+        instance: &str,
+        hostname: &str,
+        addr: Option<Ipv4Addr>,
+    ) -> Vec<u8> {
+        let full = format!("{}.{}", instance, SERVICE);
+        let count = if addr.is_some() { 3 } else { 2 };
+
+        let mut buf = vec![0, 0, 0x84, 0, 0, 0, 0, 1, 0, 0, 0, count];
+        write_record(&mut buf, SERVICE, TYPE_PTR, &name_bytes(&full));
+
+        let mut srv = vec![0, 0, 0, 0];
+        srv.extend_from_slice(&32000u16.to_be_bytes());
+        srv.extend_from_slice(&name_bytes(hostname));
+        write_record(&mut buf, &full, TYPE_SRV, &srv);
+
+        let mut txt = Vec::new();
+        for entry in ["txtvers=1", "login=deck", "devkit1=steamdeck"] {
+            txt.push(entry.len() as u8);
+            txt.extend_from_slice(entry.as_bytes());
+        }
+        write_record(&mut buf, &full, TYPE_TXT, &txt);
+
+        if let Some(addr) = addr {
+            write_record(&mut buf, hostname, TYPE_A, &addr.octets());
+        }
+
+        buf
+    }
+
+    /// Stand-in for a devkit: answer the first query it gets
+    fn spawn_responder(
+        response: Vec<u8>,
+    ) -> (SocketAddr, thread::JoinHandle<Vec<u8>>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        let handle = thread::spawn(move || {
+            let mut buf = [0; 1500];
+            let (len, from) = socket.recv_from(&mut buf).unwrap();
+            socket.send_to(&response, from).unwrap();
+
+            buf[..len].to_vec()
+        });
+
+        (addr, handle)
+    }
+
+    #[test]
+    fn query() {
+        let query = build_query(SERVICE);
+
+        // One question and no records
+        assert_eq!(&query[..12], &[0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(read_name(&query, 12), Some((SERVICE.to_string(), 40)));
+        assert_eq!(read_u16(&query, 40), Some(TYPE_PTR));
+        assert_eq!(read_u16(&query, 42), Some(CLASS_IN));
+    }
+
+    #[test]
+    fn discover_local_responder() {
+        let response = build_response(
+            "steamdeck",
+            "steamdeck.local",
+            Some(Ipv4Addr::new(192, 168, 1, 50)),
+        );
+        let (addr, responder) = spawn_responder(response);
+
+        let devkits = discover_on(addr, Duration::from_millis(500)).unwrap();
+        let query = responder.join().unwrap();
+
+        assert_eq!(query, build_query(SERVICE));
+        assert_eq!(devkits.len(), 1);
+
+        let devkit = &devkits[0];
+        assert_eq!(devkit.name, "steamdeck");
+        assert_eq!(devkit.hostname, "steamdeck.local");
+        assert_eq!(devkit.port, 32000);
+        assert_eq!(
+            devkit.addresses,
+            vec![IpAddr::V4(Ipv4Addr::new(192, 168, 1, 50))]
+        );
+        assert_eq!(devkit.properties["login"], "deck");
+        assert_eq!(devkit.properties["devkit1"], "steamdeck");
+    }
+
+    #[test]
+    fn address_from_source() {
+        // NOTE(patrik): No A record, the address of the responder is used
+        let response = build_response("deck", "deck.local", None);
+        let (addr, responder) = spawn_responder(response);
+
+        let devkits = discover_on(addr, Duration::from_millis(500)).unwrap();
+        responder.join().unwrap();
+
+        assert_eq!(devkits.len(), 1);
+        assert_eq!(devkits[0].addresses, vec![addr.ip()]);
+    }
+
+    #[test]
+    fn compressed_names() {
+        let mut buf = vec![0, 0, 0x84, 0, 0, 0, 0, 2, 0, 0, 0, 0];
+
+        // PTR with the service at offset 12
+        write_record(
+            &mut buf,
+            SERVICE,
+            TYPE_PTR,
+            &[4, b'd', b'e', b'c', b'k', 0xc0, 12],
+        );
+
+        // SRV with the name pointing to the PTR data at offset 50 and the
+        // target pointing to "local" at offset 33
+        let mut record = vec![0xc0, 50];
+        record.extend_from_slice(&TYPE_SRV.to_be_bytes());
+        record.extend_from_slice(&CLASS_IN.to_be_bytes());
+        record.extend_from_slice(&120u32.to_be_bytes());
+        record.extend_from_slice(&13u16.to_be_bytes());
+        record.extend_from_slice(&[0, 0, 0, 0, 0x7d, 0x00]);
+        record.extend_from_slice(&[4, b'd', b'e', b'c', b'k', 0xc0, 33]);
+        buf.extend_from_slice(&record);
+
+        let records = parse_packet(&buf).unwrap();
+        let instance = format!("deck.{}", SERVICE);
+
+        assert_eq!(records[0].data, RecordData::Ptr(instance.clone()));
+        assert_eq!(records[1].name, instance);
+        assert_eq!(
+            records[1].data,
+            RecordData::Srv {
+                port: 32000,
+                target: "deck.local".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn broken_packets() {
+        assert_eq!(parse_packet(&[]), None);
+        assert_eq!(parse_packet(&[0, 0, 0x84, 0, 0, 0, 0, 1]), None);
+
+        // Pointer to itself
+        let buf = [0, 0, 0x84, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0xc0, 12];
+        assert_eq!(parse_packet(&buf), None);
+
+        // Queries are ignored
+        assert_eq!(parse_packet(&build_query(SERVICE)), Some(Vec::new()));
+    }
+}
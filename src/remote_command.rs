@@ -0,0 +1,177 @@
+//! Command lines for the shell on the devkit
+//!
+//! Every argument gets quoted so game ids, names and paths with spaces or
+//! shell characters stays a single argument and never runs anything. Paths
+//! starting with '~/' keeps the '~' outside of the quotes so the shell still
+//! expands it to the home directory.
+
+use std::fmt;
+
+/// Quote the string for a POSIX shell, decker_util uses the same quoting
+/// for the launch options
+pub use decker_protocol::shell_quote as quote;
+
+/// Quote the path for a POSIX shell but let the shell expand a leading '~'
+pub fn quote_path(path: &str) -> String {
+    match path.strip_prefix("~/") {
+        Some("") => "~/".to_string(),
+        Some(rest) => format!("~/{}", quote(rest)),
+        None if path == "~" => path.to_string(),
+        None => quote(path),
+    }
+}
+
+/// A command line for the devkit
+///
+/// ```ignore
+/// let cmd = RemoteCommand::new("mkdir").arg("-p").path("~/Test Game");
+/// assert_eq!(cmd.to_string(), "mkdir -p ~/'Test Game'");
+/// ```
+#[derive(Clone, Debug)]
+pub struct RemoteCommand {
+    line: String,
+}
+
+impl RemoteCommand {
+    /// Start a command that runs the program, the program can be a path
+    pub fn new(program: &str) -> Self {
+        Self {
+            line: quote_path(program),
+        }
+    }
+
+    /// Add an argument
+    pub fn arg(mut self, arg: &str) -> Self {
+        self.line.push(' ');
+        self.line.push_str(&quote(arg));
+        self
+    }
+
+    /// Add an argument that is a path on the devkit
+    pub fn path(mut self, path: &str) -> Self {
+        self.line.push(' ');
+        self.line.push_str(&quote_path(path));
+        self
+    }
+}
+
+impl fmt::Display for RemoteCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process::Command;
+
+    /// A command that prints every argument on a line
+    fn print_args() -> RemoteCommand {
+        RemoteCommand::new("printf").arg("%s\\n")
+    }
+
+    /// Run the command line with sh and get the lines it printed
+    fn sh_lines(cmd: &RemoteCommand) -> Vec<String> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(cmd.to_string())
+            .env("HOME", "/home/deck")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        stdout.lines().map(|line| line.to_string()).collect()
+    }
+
+    /// PWNED gets replaced with a file that must not exist after the test
+    const HOSTILE: &[&str] = &[
+        "Test Game",
+        "game; touch PWNED",
+        "$(touch PWNED)",
+        "`id`",
+        "it's",
+        "\"quoted\"",
+        "a\\b",
+        "*",
+        "-rf",
+        "tab\tand  spaces",
+        "$HOME",
+        "a|b&c>d<e",
+        "",
+    ];
+
+    /// The hostile arguments and the file they try to create, the file is
+    /// unique to the test and removed before it runs
+    fn hostile(name: &str) -> (std::path::PathBuf, Vec<String>) {
+        let pwned = std::env::temp_dir().join(format!(
+            "decker_pwned_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&pwned);
+
+        let args = HOSTILE
+            .iter()
+            .map(|arg| arg.replace("PWNED", pwned.to_str().unwrap()))
+            .collect();
+
+        (pwned, args)
+    }
+
+    #[test]
+    fn quote_safe() {
+        assert_eq!(quote("game-1.0_x86"), "game-1.0_x86");
+        assert_eq!(
+            quote("/home/deck/decker-games"),
+            "/home/deck/decker-games"
+        );
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("Test Game"), "'Test Game'");
+        assert_eq!(quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn quote_paths() {
+        assert_eq!(quote_path("~/decker/decker_util"), "~/decker/decker_util");
+        assert_eq!(quote_path("~/Test Game"), "~/'Test Game'");
+        assert_eq!(quote_path("~"), "~");
+        assert_eq!(quote_path("/tmp/a b"), "'/tmp/a b'");
+
+        // NOTE(patrik): Only a leading '~/' is expanded
+        assert_eq!(quote_path("/tmp/~/a"), "'/tmp/~/a'");
+        assert_eq!(quote_path("~user/a"), "'~user/a'");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn hostile_arguments() {
+        let (pwned, args) = hostile("arguments");
+        for arg in &args {
+            let cmd = print_args().arg(arg);
+            assert_eq!(sh_lines(&cmd), vec![arg.to_string()], "{}", cmd);
+        }
+
+        assert!(!pwned.exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn hostile_paths() {
+        let (pwned, args) = hostile("paths");
+        for arg in &args {
+            let path = format!("~/decker-games/{}", arg);
+            let cmd = print_args().path(&path);
+            assert_eq!(
+                sh_lines(&cmd),
+                vec![format!("/home/deck/decker-games/{}", arg)],
+                "{}",
+                cmd
+            );
+        }
+
+        assert!(!pwned.exists());
+    }
+}
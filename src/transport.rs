@@ -0,0 +1,1195 @@
+//! The connection to the devkit, all the commands and file transfers goes
+//! through the Transport trait
+//!
+//! There are two backends:
+//!   - Native: In process ssh and sftp with libssh2, works on all platforms
+//!     without any external programs
+//!   - Subprocess: Runs the ssh, scp and rsync programs
+
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use clap::ValueEnum;
+use ssh2::{
+    Channel, CheckResult, HashType, HostKeyType, KnownHostFileKind,
+    KnownHosts, OpenFlags, OpenType, Session, Sftp,
+};
+
+use crate::device::Device;
+use crate::remote_command::{quote_path, RemoteCommand};
+use crate::{Error, Result};
+
+/// The output of a command that ran on the devkit
+#[derive(Debug)]
+pub struct Output {
+    /// The exit code, -1 if the command was killed by a signal
+    pub exit_code: i32,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl Output {
+    pub fn success(&self) -> bool {
+        self.exit_code == 0
+    }
+
+    /// Turn the output of a failed command into an error
+    pub fn check(self) -> std::result::Result<Self, RemoteError> {
+        if self.success() {
+            Ok(self)
+        } else {
+            Err(RemoteError {
+                exit_code: self.exit_code,
+                stderr: String::from_utf8_lossy(&self.stderr)
+                    .trim()
+                    .to_string(),
+            })
+        }
+    }
+}
+
+/// A command on the devkit that failed
+#[derive(Debug)]
+pub struct RemoteError {
+    pub exit_code: i32,
+    pub stderr: String,
+}
+
+impl std::fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.stderr.is_empty() {
+            write!(f, "exited with code {}", self.exit_code)
+        } else {
+            write!(f, "exited with code {}: {}", self.exit_code, self.stderr)
+        }
+    }
+}
+
+impl std::error::Error for RemoteError {}
+
+impl RemoteError {
+    fn from_process(output: std::process::Output) -> Self {
+        Self {
+            exit_code: output.status.code().unwrap_or(-1),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }
+    }
+}
+
+pub trait Transport {
+    /// Run a command on the devkit and wait for it to finish
+    fn execute(&self, cmd: &RemoteCommand) -> Result<Output>;
+
+    /// Run a command on the devkit with the output going to our stdout and
+    /// stderr while it runs, returns the exit code
+    fn execute_streaming(&self, cmd: &RemoteCommand) -> Result<i32>;
+
+    /// Upload a single file to the devkit
+    fn upload(&self, source: &Path, dest: &str) -> Result<()>;
+
+    /// Upload the content of the source directory to the dest directory,
+    /// paths matching one of the exclude patterns are skipped
+    fn sync(
+        &self,
+        source: &Path,
+        dest: &str,
+        exclude: &[String],
+    ) -> Result<()>;
+
+    /// Start an interactive shell
+    fn shell(&self) -> Result<()>;
+
+    /// Keep the connection open while there is nothing to do, should be
+    /// called at least every KEEPALIVE_INTERVAL
+    fn keepalive(&self) -> Result<()>;
+}
+
+/// How often the native transport sends a keepalive message on an idle
+/// connection
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Which transport to use
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TransportKind {
+    #[default]
+    Native,
+    Subprocess,
+}
+
+/// Connect to the device, fails with Error::SSHAuthenticationFailed if the
+/// key isn't registered on the devkit
+pub fn connect(
+    device: &Device,
+    kind: TransportKind,
+) -> Result<Box<dyn Transport>> {
+    match kind {
+        TransportKind::Native => {
+            Ok(Box::new(NativeTransport::connect(device)?))
+        }
+        TransportKind::Subprocess => {
+            Ok(Box::new(SubprocessTransport::connect(device)?))
+        }
+    }
+}
+
+/// Check if the path relative to the synced directory matches one of the
+/// patterns, works like a subset of the rsync exclude patterns:
+///   - "name" matches the file name at any depth
+///   - "dir/name" or "/name" matches the full relative path
+///   - "name/" only matches directories
+///   - '*' matches anything except '/', '**' matches anything and '?' matches
+///     one character
+pub fn is_excluded(exclude: &[String], path: &str, is_dir: bool) -> bool {
+    exclude.iter().any(|pattern| {
+        let mut pattern = pattern.as_str();
+
+        if let Some(p) = pattern.strip_suffix('/') {
+            if !is_dir {
+                return false;
+            }
+
+            pattern = p;
+        }
+
+        if let Some(p) = pattern.strip_prefix('/') {
+            glob_match(p.as_bytes(), path.as_bytes())
+        } else if pattern.contains('/') {
+            glob_match(pattern.as_bytes(), path.as_bytes())
+        } else {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            glob_match(pattern.as_bytes(), name.as_bytes())
+        }
+    })
+}
+
+fn glob_match(pattern: &[u8], s: &[u8]) -> bool {
+    match pattern {
+        [] => s.is_empty(),
+
+        [b'*', b'*', rest @ ..] => {
+            (0..=s.len()).any(|i| glob_match(rest, &s[i..]))
+        }
+
+        [b'*', rest @ ..] => {
+            // NOTE(patrik): A single '*' stops at the first '/'
+            let end = s.iter().position(|c| *c == b'/').unwrap_or(s.len());
+            (0..=end).any(|i| glob_match(rest, &s[i..]))
+        }
+
+        [b'?', rest @ ..] => match s {
+            [c, s @ ..] if *c != b'/' => glob_match(rest, s),
+            _ => false,
+        },
+
+        [p, rest @ ..] => match s {
+            [c, s @ ..] if c == p => glob_match(rest, s),
+            _ => false,
+        },
+    }
+}
+
+/// Transport that runs ssh and sftp inside the process
+pub struct NativeTransport {
+    device: Device,
+    session: Session,
+    sftp: Sftp,
+}
+
+impl NativeTransport {
+    pub fn connect(device: &Device) -> Result<Self> {
+        Self::connect_with_known_hosts(device, known_hosts_path().as_deref())
+    }
+
+    /// Connect and check the host key against the given known_hosts file
+    fn connect_with_known_hosts(
+        device: &Device,
+        known_hosts: Option<&Path>,
+    ) -> Result<Self> {
+        let addrs = (device.address.as_str(), device.port)
+            .to_socket_addrs()
+            .map_err(Error::FailedToConnect)?;
+
+        let mut last_error = None;
+        let mut stream = None;
+        for addr in addrs {
+            match TcpStream::connect_timeout(&addr, Duration::from_secs(10)) {
+                Ok(s) => {
+                    stream = Some(s);
+                    break;
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        let stream = match (stream, last_error) {
+            (Some(stream), _) => stream,
+            (None, Some(e)) => return Err(Error::FailedToConnect(e)),
+            (None, None) => {
+                return Err(Error::FailedToConnect(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    "address didn't resolve",
+                )))
+            }
+        };
+
+        let mut session = Session::new().map_err(Error::SSHFailed)?;
+        session.set_tcp_stream(stream);
+        session.handshake().map_err(Error::SSHFailed)?;
+        session.set_keepalive(false, KEEPALIVE_INTERVAL.as_secs() as u32);
+
+        check_host_key(&session, device, known_hosts)?;
+
+        // NOTE(patrik): Auth failures are reported as an error from
+        // libssh2, check authenticated() so we don't need to look at the
+        // error codes
+        let _ = session.userauth_pubkey_file(
+            device.username(),
+            None,
+            &device.key_path(),
+            None,
+        );
+        if !session.authenticated() {
+            return Err(Error::SSHAuthenticationFailed);
+        }
+
+        let sftp = session.sftp().map_err(Error::SSHFailed)?;
+
+        Ok(Self {
+            device: device.clone(),
+            session,
+            sftp,
+        })
+    }
+
+    /// Create the directory and all the parents, like 'mkdir -p'
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        if path.as_os_str().is_empty() || self.sftp.stat(path).is_ok() {
+            return Ok(());
+        }
+
+        if let Some(parent) = path.parent() {
+            self.create_dir_all(parent)?;
+        }
+
+        self.sftp.mkdir(path, 0o755).map_err(Error::SSHFailed)
+    }
+
+    fn upload_file(&self, source: &Path, dest: &Path) -> Result<()> {
+        let mut source_file = std::fs::File::open(source)
+            .map_err(Error::FailedToReadLocalFile)?;
+        let mode = file_mode(
+            &source_file
+                .metadata()
+                .map_err(Error::FailedToReadLocalFile)?,
+        );
+
+        let mut dest_file = self
+            .sftp
+            .open_mode(
+                dest,
+                OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE,
+                mode,
+                OpenType::File,
+            )
+            .map_err(Error::SSHFailed)?;
+
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let n = source_file
+                .read(&mut buffer)
+                .map_err(Error::FailedToReadLocalFile)?;
+            if n == 0 {
+                break;
+            }
+
+            dest_file
+                .write_all(&buffer[..n])
+                .map_err(Error::FailedToWriteRemoteFile)?;
+        }
+
+        // NOTE(patrik): The mode given to open only applies to new files,
+        // existing files keeps the old mode otherwise
+        let mut stat = dest_file.stat().map_err(Error::SSHFailed)?;
+        stat.perm = Some(mode as u32);
+        dest_file.setstat(stat).map_err(Error::SSHFailed)?;
+
+        Ok(())
+    }
+
+    fn sync_dir(
+        &self,
+        source: &Path,
+        dest: &Path,
+        relative: &str,
+        exclude: &[String],
+    ) -> Result<()> {
+        self.create_dir_all(dest)?;
+
+        let entries =
+            std::fs::read_dir(source).map_err(Error::FailedToReadLocalFile)?;
+        for entry in entries {
+            let entry = entry.map_err(Error::FailedToReadLocalFile)?;
+            let file_type =
+                entry.file_type().map_err(Error::FailedToReadLocalFile)?;
+
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            let relative = if relative.is_empty() {
+                name.to_string()
+            } else {
+                format!("{}/{}", relative, name)
+            };
+
+            // NOTE(patrik): 'rsync -r' skips symlinks, we follow them
+            // because a copy of the target is better than a missing file
+            let is_dir = entry.path().is_dir();
+            if is_excluded(exclude, &relative, is_dir) {
+                continue;
+            }
+
+            let source = entry.path();
+            let dest = dest.join(&*name);
+            if is_dir {
+                self.sync_dir(&source, &dest, &relative, exclude)?;
+            } else if file_type.is_file() || file_type.is_symlink() {
+                self.upload_file(&source, &dest)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// How long to wait before reading again when the command has no new output
+const READ_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Read stdout and stderr of the channel as the output arrives, `on_output`
+/// gets the output and true if it's from stderr
+///
+/// NOTE(patrik): stdout and stderr shares the window, reading one until it
+/// ends would stall a command that fills the window with the other one
+fn read_output<F>(
+    session: &Session,
+    channel: &mut Channel,
+    mut on_output: F,
+) -> Result<()>
+where
+    F: FnMut(bool, &[u8]) -> Result<()>,
+{
+    let mut read = || {
+        let mut streams = [channel.stream(0), channel.stderr()];
+        let mut done = [false, false];
+        let mut buffer = vec![0; 16 * 1024];
+
+        while !done.iter().all(|done| *done) {
+            let mut idle = true;
+
+            for (index, stream) in streams.iter_mut().enumerate() {
+                if done[index] {
+                    continue;
+                }
+
+                match stream.read(&mut buffer) {
+                    // NOTE(patrik): A stream only ends with the channel
+                    Ok(0) => done[index] = channel.eof(),
+                    Ok(n) => {
+                        idle = false;
+                        on_output(index == 1, &buffer[..n])?;
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                    Err(e) => return Err(Error::FailedToReadRemoteOutput(e)),
+                }
+            }
+
+            if idle {
+                std::thread::sleep(READ_POLL_INTERVAL);
+            }
+        }
+
+        Ok(())
+    };
+
+    session.set_blocking(false);
+    let res = read();
+    session.set_blocking(true);
+
+    res
+}
+
+impl Transport for NativeTransport {
+    fn execute(&self, cmd: &RemoteCommand) -> Result<Output> {
+        let mut channel =
+            self.session.channel_session().map_err(Error::SSHFailed)?;
+        channel.exec(&cmd.to_string()).map_err(Error::SSHFailed)?;
+
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        read_output(&self.session, &mut channel, |is_stderr, data| {
+            if is_stderr {
+                stderr.extend_from_slice(data);
+            } else {
+                stdout.extend_from_slice(data);
+            }
+
+            Ok(())
+        })?;
+
+        channel.wait_close().map_err(Error::SSHFailed)?;
+        let exit_code = channel.exit_status().map_err(Error::SSHFailed)?;
+
+        Ok(Output {
+            exit_code,
+            stdout,
+            stderr,
+        })
+    }
+
+    fn execute_streaming(&self, cmd: &RemoteCommand) -> Result<i32> {
+        let mut channel =
+            self.session.channel_session().map_err(Error::SSHFailed)?;
+        channel.exec(&cmd.to_string()).map_err(Error::SSHFailed)?;
+
+        read_output(&self.session, &mut channel, |is_stderr, data| {
+            let res = if is_stderr {
+                let mut stderr = std::io::stderr();
+                stderr.write_all(data).and_then(|_| stderr.flush())
+            } else {
+                let mut stdout = std::io::stdout();
+                stdout.write_all(data).and_then(|_| stdout.flush())
+            };
+
+            res.map_err(Error::FailedToWriteOutput)
+        })?;
+
+        channel.wait_close().map_err(Error::SSHFailed)?;
+        channel.exit_status().map_err(Error::SSHFailed)
+    }
+
+    fn upload(&self, source: &Path, dest: &str) -> Result<()> {
+        let dest = remote_path(dest);
+        if let Some(parent) = dest.parent() {
+            self.create_dir_all(parent)?;
+        }
+
+        self.upload_file(source, &dest)
+    }
+
+    fn sync(
+        &self,
+        source: &Path,
+        dest: &str,
+        exclude: &[String],
+    ) -> Result<()> {
+        self.sync_dir(source, &remote_path(dest), "", exclude)
+    }
+
+    fn shell(&self) -> Result<()> {
+        // NOTE(patrik): An interactive shell needs the terminal in raw
+        // mode which is different on every platform, ssh is shipped with
+        // Windows 10 and later so use the ssh program for the shell
+        ssh_shell(&self.device)
+    }
+
+    fn keepalive(&self) -> Result<()> {
+        self.session.keepalive_send().map_err(Error::SSHFailed)?;
+        Ok(())
+    }
+}
+
+/// The known_hosts file of the user
+fn known_hosts_path() -> Option<PathBuf> {
+    Some(dirs::home_dir()?.join(".ssh").join("known_hosts"))
+}
+
+/// Check the host key against the known_hosts file like ssh does, a changed
+/// key is an error and an unknown host has to be accepted by the user
+/// before it's added to the file
+fn check_host_key(
+    session: &Session,
+    device: &Device,
+    path: Option<&Path>,
+) -> Result<()> {
+    let (key, key_type) = match session.host_key() {
+        Some(key) => key,
+        None => return Ok(()),
+    };
+
+    let path = match path {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+
+    let mut known_hosts = session.known_hosts().map_err(Error::SSHFailed)?;
+    if path.exists() {
+        known_hosts
+            .read_file(path, KnownHostFileKind::OpenSSH)
+            .map_err(Error::SSHFailed)?;
+    }
+
+    match known_hosts.check_port(&device.address, device.port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::Mismatch => {
+            Err(Error::HostKeyMismatch(device.address.clone()))
+        }
+        CheckResult::NotFound | CheckResult::Failure => {
+            let fingerprint = session
+                .host_key_hash(HashType::Sha256)
+                .map(base64)
+                .unwrap_or_default();
+
+            if !accept_host_key(device, key_type, &fingerprint) {
+                return Err(Error::UnknownHostKey(device.address.clone()));
+            }
+
+            // NOTE(patrik): The key is accepted even if it can't be saved,
+            // the user gets asked again next time
+            if let Err(e) =
+                add_known_host(&mut known_hosts, path, device, key, key_type)
+            {
+                println!(
+                    "Warning: failed to add the host key to {:?}: {}",
+                    path, e
+                );
+            }
+
+            Ok(())
+        }
+    }
+}
+
+/// Ask the user to accept the key of an unknown host, without a terminal
+/// there is no one to ask so the key is not accepted
+fn accept_host_key(
+    device: &Device,
+    key_type: HostKeyType,
+    fingerprint: &str,
+) -> bool {
+    use std::io::IsTerminal;
+
+    if !std::io::stdin().is_terminal() {
+        return false;
+    }
+
+    let key_type = match key_type {
+        HostKeyType::Rsa => "RSA",
+        HostKeyType::Dss => "DSA",
+        HostKeyType::Ecdsa256 |
+        HostKeyType::Ecdsa384 |
+        HostKeyType::Ecdsa521 => "ECDSA",
+        HostKeyType::Ed25519 => "ED25519",
+        HostKeyType::Unknown => "Unknown",
+    };
+
+    eprintln!(
+        "The authenticity of host '{}' (port {}) can't be established.",
+        device.address, device.port
+    );
+    eprintln!("{} key fingerprint is SHA256:{}.", key_type, fingerprint);
+    eprint!("Are you sure you want to continue connecting (yes/no)? ");
+
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+
+    matches!(answer.trim().to_lowercase().as_str(), "yes" | "y")
+}
+
+/// Append the host key to the known_hosts file, the rest of the file is
+/// left as it is
+fn add_known_host(
+    known_hosts: &mut KnownHosts,
+    path: &Path,
+    device: &Device,
+    key: &[u8],
+    key_type: HostKeyType,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    // NOTE(patrik): Same as ssh, the port is only written if it's not the
+    // default port
+    let host = if device.port == 22 {
+        device.address.clone()
+    } else {
+        format!("[{}]:{}", device.address, device.port)
+    };
+
+    known_hosts.add(&host, key, "", key_type.into())?;
+    let entry = known_hosts
+        .hosts()?
+        .into_iter()
+        .rev()
+        .find(|h| h.name() == Some(host.as_str()))
+        .ok_or("the added host is missing")?;
+    let line = known_hosts.write_string(&entry, KnownHostFileKind::OpenSSH)?;
+
+    if let Some(dir) = path.parent() {
+        create_ssh_dir(dir)?;
+    }
+
+    let mut data = Vec::new();
+    let existing = std::fs::read(path).unwrap_or_default();
+    if !existing.is_empty() && !existing.ends_with(b"\n") {
+        data.push(b'\n');
+    }
+    data.extend_from_slice(line.trim_end().as_bytes());
+    data.push(b'\n');
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    file.write_all(&data)?;
+
+    Ok(())
+}
+
+/// Create the directory with the same permissions as ssh uses for ~/.ssh
+fn create_ssh_dir(dir: &Path) -> std::io::Result<()> {
+    if dir.exists() {
+        return Ok(());
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+    }
+
+    #[cfg(not(unix))]
+    std::fs::create_dir_all(dir)
+}
+
+/// Base64 without padding, the way ssh prints the fingerprints
+fn base64(data: &[u8]) -> String {
+    const CHARS: &[u8] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut result = String::new();
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+
+        for i in 0..=chunk.len() {
+            result.push(CHARS[(n >> (18 - i * 6)) as usize & 0x3f] as char);
+        }
+    }
+
+    result
+}
+
+/// Paths starting with '~/' gets expanded by the shell for ssh but not by
+/// sftp, sftp paths are relative to the home directory already
+fn remote_path(path: &str) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(path) => PathBuf::from(path),
+        None if path == "~" => PathBuf::from("."),
+        None => PathBuf::from(path),
+    }
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &std::fs::Metadata) -> i32 {
+    use std::os::unix::fs::PermissionsExt;
+
+    (metadata.permissions().mode() & 0o777) as i32
+}
+
+#[cfg(not(unix))]
+fn file_mode(_metadata: &std::fs::Metadata) -> i32 {
+    // NOTE(patrik): There is no executable bit to copy, make everything
+    // executable so the game can be started
+    0o755
+}
+
+/// Run an interactive shell with the ssh program
+fn ssh_shell(device: &Device) -> Result<()> {
+    Command::new("ssh")
+        .arg("-oBatchMode=yes")
+        .arg("-oStrictHostKeyChecking=no")
+        .arg("-p")
+        .arg(device.port.to_string())
+        .arg("-i")
+        .arg(device.key_path())
+        .arg(device.host())
+        .status()
+        .map_err(Error::FailedToExecuteSSH)?;
+
+    Ok(())
+}
+
+/// Transport that runs the ssh, scp and rsync programs
+pub struct SubprocessTransport {
+    device: Device,
+}
+
+impl SubprocessTransport {
+    pub fn connect(device: &Device) -> Result<Self> {
+        let transport = Self {
+            device: device.clone(),
+        };
+
+        // NOTE(patrik): ssh doesn't tell us why it failed, assume it's
+        // because we are not registered yet
+        if !transport.execute(&RemoteCommand::new("true"))?.success() {
+            return Err(Error::SSHAuthenticationFailed);
+        }
+
+        Ok(transport)
+    }
+
+    /// The ssh command that runs the command on the devkit
+    fn ssh(&self, cmd: &RemoteCommand) -> Command {
+        let mut ssh = Command::new("ssh");
+        ssh.arg("-oBatchMode=yes")
+            .arg("-p")
+            .arg(self.device.port.to_string())
+            .arg("-i")
+            .arg(self.device.key_path())
+            .arg(self.device.host())
+            .arg(cmd.to_string());
+        ssh
+    }
+
+    fn remote(&self, path: &str) -> String {
+        format!("{}:{}", self.device.host(), path)
+    }
+
+    fn scp(&self, source: &Path, dest: &str) -> Result<()> {
+        let output = Command::new("scp")
+            .arg("-oBatchMode=yes")
+            .arg("-P")
+            .arg(self.device.port.to_string())
+            .arg("-i")
+            .arg(self.device.key_path())
+            .arg(source)
+            .arg(self.remote(dest))
+            .output()
+            .map_err(Error::FailedToExecuteSCP)?;
+
+        if !output.status.success() {
+            return Err(Error::TransferFailed(RemoteError::from_process(
+                output,
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl Transport for SubprocessTransport {
+    fn execute(&self, cmd: &RemoteCommand) -> Result<Output> {
+        let output =
+            self.ssh(cmd).output().map_err(Error::FailedToExecuteSSH)?;
+
+        Ok(Output {
+            exit_code: output.status.code().unwrap_or(-1),
+            stdout: output.stdout,
+            stderr: output.stderr,
+        })
+    }
+
+    fn execute_streaming(&self, cmd: &RemoteCommand) -> Result<i32> {
+        let status =
+            self.ssh(cmd).status().map_err(Error::FailedToExecuteSSH)?;
+
+        Ok(status.code().unwrap_or(-1))
+    }
+
+    fn upload(&self, source: &Path, dest: &str) -> Result<()> {
+        // NOTE(patrik): Depending on the version scp passes the remote path
+        // through the shell or not, a path that needs quoting gets uploaded
+        // to a safe name and moved into place
+        if quote_path(dest) == dest {
+            return self.scp(source, dest);
+        }
+
+        // NOTE(patrik): mktemp picks a name nobody else has in the home
+        // directory of the user, the printed path is relative to the home
+        // directory so it doesn't need quoting
+        let cmd = RemoteCommand::new("mktemp")
+            .arg("-d")
+            .arg(".decker-upload.XXXXXX");
+        let output =
+            self.execute(&cmd)?.check().map_err(Error::TransferFailed)?;
+        let temp_dir =
+            String::from_utf8_lossy(&output.stdout).trim().to_string();
+        let temp = format!("{}/file", temp_dir);
+
+        let result = self.scp(source, &temp).and_then(|_| {
+            let cmd =
+                RemoteCommand::new("mv").arg("-f").path(&temp).path(dest);
+            self.execute(&cmd)?.check().map_err(Error::TransferFailed)?;
+            Ok(())
+        });
+
+        let _ =
+            self.execute(&RemoteCommand::new("rm").arg("-rf").path(&temp_dir));
+
+        result
+    }
+
+    fn sync(
+        &self,
+        source: &Path,
+        dest: &str,
+        exclude: &[String],
+    ) -> Result<()> {
+        // NOTE(patrik): The trailing slash makes rsync copy the content of
+        // the directory instead of the directory itself
+        let mut source = source.to_string_lossy().to_string();
+        if !source.ends_with('/') {
+            source.push('/');
+        }
+
+        let key = self.device.key_path();
+        let key = key
+            .to_str()
+            .ok_or_else(|| Error::InvalidKeyPath(key.clone()))?;
+
+        // NOTE(patrik): Newer rsync versions protects the remote path from
+        // the shell, old args makes them behave like the older versions so
+        // the quoted path works with both
+        let output = Command::new("rsync")
+            .env("RSYNC_OLD_ARGS", "1")
+            .arg("-e")
+            .arg(format!(
+                "ssh -oBatchMode=yes -p {} -i \"{}\"",
+                self.device.port, key
+            ))
+            .arg("-r")
+            .args(
+                exclude
+                    .iter()
+                    .map(|pattern| format!("--exclude={}", pattern)),
+            )
+            .arg(source)
+            .arg(self.remote(&quote_path(dest)))
+            .output()
+            .map_err(Error::FailedToExecuteRSync)?;
+
+        if !output.status.success() {
+            return Err(Error::TransferFailed(RemoteError::from_process(
+                output,
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn shell(&self) -> Result<()> {
+        ssh_shell(&self.device)
+    }
+
+    fn keepalive(&self) -> Result<()> {
+        // NOTE(patrik): Every command starts a new ssh process, there is no
+        // connection to keep open
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::TcpListener;
+    use std::process::{Child, Stdio};
+
+    fn excluded(patterns: &[&str], path: &str, is_dir: bool) -> bool {
+        let patterns: Vec<String> =
+            patterns.iter().map(|p| p.to_string()).collect();
+        is_excluded(&patterns, path, is_dir)
+    }
+
+    #[test]
+    fn exclude_name() {
+        assert!(excluded(&[".git"], ".git", true));
+        assert!(excluded(&[".git"], "sub/.git", true));
+        assert!(excluded(&["decker.toml"], "decker.toml", false));
+        assert!(!excluded(&["decker.toml"], "decker.toml.bak", false));
+        assert!(!excluded(&[], "anything", false));
+    }
+
+    #[test]
+    fn exclude_wildcards() {
+        assert!(excluded(&["*.pdb"], "bin/game.pdb", false));
+        assert!(!excluded(&["*.pdb"], "bin/game.exe", false));
+        assert!(excluded(&["save?.dat"], "save1.dat", false));
+        assert!(!excluded(&["save?.dat"], "save10.dat", false));
+        assert!(excluded(&["bin/*.pdb"], "bin/game.pdb", false));
+        assert!(!excluded(&["bin/*.pdb"], "bin/sub/game.pdb", false));
+        assert!(excluded(&["bin/**.pdb"], "bin/sub/game.pdb", false));
+    }
+
+    #[test]
+    fn exclude_anchored_and_dirs() {
+        assert!(excluded(&["/build"], "build", true));
+        assert!(!excluded(&["/build"], "src/build", true));
+        assert!(excluded(&["logs/"], "logs", true));
+        assert!(!excluded(&["logs/"], "logs", false));
+    }
+
+    #[test]
+    fn remote_paths() {
+        assert_eq!(remote_path("~/decker/util"), Path::new("decker/util"));
+        assert_eq!(remote_path("~"), Path::new("."));
+        assert_eq!(remote_path("/home/deck/a"), Path::new("/home/deck/a"));
+    }
+
+    /// A sshd running on a random port with a fresh host and user key
+    struct TestServer {
+        child: Child,
+        dir: mktemp::Temp,
+        port: u16,
+    }
+
+    impl Drop for TestServer {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+
+    fn find_sshd() -> Option<PathBuf> {
+        let mut dirs =
+            vec![PathBuf::from("/usr/sbin"), PathBuf::from("/sbin")];
+        if let Some(path) = std::env::var_os("PATH") {
+            dirs.extend(std::env::split_paths(&path));
+        }
+
+        dirs.into_iter()
+            .map(|dir| dir.join("sshd"))
+            .find(|path| path.exists())
+    }
+
+    fn keygen(path: &Path) {
+        let status = Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-f"])
+            .arg(path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    /// Start sshd, panics if sshd isn't installed
+    fn start_sshd() -> TestServer {
+        let sshd = find_sshd().expect("sshd not found");
+
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let path = dir.to_path_buf();
+
+        keygen(&path.join("host_key"));
+        keygen(&path.join("user_key"));
+        std::fs::copy(path.join("user_key.pub"), path.join("authorized_keys"))
+            .unwrap();
+
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let config = format!(
+            "Port {port}\nListenAddress 127.0.0.1\nHostKey \
+             {dir}/host_key\nAuthorizedKeysFile \
+             {dir}/authorized_keys\nPidFile {dir}/sshd.pid\nStrictModes \
+             no\nUsePAM no\nPasswordAuthentication no\nSubsystem sftp \
+             internal-sftp\n",
+            port = port,
+            dir = path.display(),
+        );
+        std::fs::write(path.join("sshd_config"), config).unwrap();
+
+        let child = Command::new(sshd)
+            .arg("-D")
+            .arg("-f")
+            .arg(path.join("sshd_config"))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        let server = TestServer { child, dir, port };
+
+        for _ in 0..50 {
+            if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+                return server;
+            }
+
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        panic!("sshd didn't start");
+    }
+
+    fn current_user() -> String {
+        let output = Command::new("id").arg("-un").output().unwrap();
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    #[test]
+    fn base64_fingerprint() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg");
+        assert_eq!(base64(b"fo"), "Zm8");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foob"), "Zm9vYg");
+        assert_eq!(base64(&[0xfb, 0xff]), "+/8");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[ignore = "needs sshd, run with --ignored"]
+    fn native_against_sshd() {
+        let server = start_sshd();
+
+        let dir = server.dir.to_path_buf();
+
+        let mut device = Device::new("127.0.0.1".to_string());
+        device.port = server.port;
+        device.username = Some(current_user());
+        device.key = Some(dir.join("user_key"));
+
+        // The host key of the server is known
+        let known_hosts = dir.join("known_hosts");
+        let host_key =
+            std::fs::read_to_string(dir.join("host_key.pub")).unwrap();
+        let host_key: Vec<&str> = host_key.split_whitespace().collect();
+        std::fs::write(
+            &known_hosts,
+            format!(
+                "[127.0.0.1]:{} {} {}\n",
+                server.port, host_key[0], host_key[1]
+            ),
+        )
+        .unwrap();
+
+        let transport = NativeTransport::connect_with_known_hosts(
+            &device,
+            Some(&known_hosts),
+        )
+        .unwrap();
+
+        // Commands
+        let cmd = RemoteCommand::new("sh")
+            .arg("-c")
+            .arg("echo hello; echo err >&2");
+        let output = transport.execute(&cmd).unwrap();
+        assert!(output.success());
+        assert_eq!(output.stdout, b"hello\n");
+        assert_eq!(output.stderr, b"err\n");
+
+        let cmd = RemoteCommand::new("sh").arg("-c").arg("exit 3");
+        let output = transport.execute(&cmd).unwrap();
+        assert_eq!(output.exit_code, 3);
+
+        // More output than fits in the channel window on both streams
+        let cmd = RemoteCommand::new("sh")
+            .arg("-c")
+            .arg("head -c 1000000 /dev/zero >&2; head -c 1000000 /dev/zero");
+        let output = transport.execute(&cmd).unwrap();
+        assert!(output.success());
+        assert_eq!(output.stdout.len(), 1000000);
+        assert_eq!(output.stderr.len(), 1000000);
+
+        // Upload of a single file creates the parent directories
+        let source = dir.join("file.txt");
+        std::fs::write(&source, "content").unwrap();
+        let dest = dir.join("remote/sub/file.txt");
+        transport.upload(&source, dest.to_str().unwrap()).unwrap();
+        assert_eq!(std::fs::read_to_string(&dest).unwrap(), "content");
+
+        // Sync keeps the executable bit and skips the excluded files
+        let game = dir.join("game");
+        std::fs::create_dir_all(game.join("data")).unwrap();
+        std::fs::create_dir_all(game.join(".git")).unwrap();
+        std::fs::write(game.join("run.sh"), "#!/bin/sh\n").unwrap();
+        std::fs::write(game.join("data/level.dat"), "level").unwrap();
+        std::fs::write(game.join("data/debug.pdb"), "debug").unwrap();
+        std::fs::write(game.join(".git/HEAD"), "ref").unwrap();
+
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let permissions = std::fs::Permissions::from_mode(0o755);
+            std::fs::set_permissions(game.join("run.sh"), permissions)
+                .unwrap();
+        }
+
+        let dest = dir.join("deployed");
+        let exclude = vec![".git".to_string(), "*.pdb".to_string()];
+        transport
+            .sync(&game, dest.to_str().unwrap(), &exclude)
+            .unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(dest.join("data/level.dat")).unwrap(),
+            "level"
+        );
+        assert!(dest.join("run.sh").exists());
+        assert!(!dest.join("data/debug.pdb").exists());
+        assert!(!dest.join(".git").exists());
+
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(dest.join("run.sh"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o755);
+        }
+
+        // A host key that doesn't match the known key
+        keygen(&dir.join("wrong_host_key"));
+        let wrong_key =
+            std::fs::read_to_string(dir.join("wrong_host_key.pub")).unwrap();
+        let wrong_key: Vec<&str> = wrong_key.split_whitespace().collect();
+        let wrong_hosts = dir.join("wrong_known_hosts");
+        std::fs::write(
+            &wrong_hosts,
+            format!(
+                "[127.0.0.1]:{} {} {}\n",
+                server.port, wrong_key[0], wrong_key[1]
+            ),
+        )
+        .unwrap();
+        assert!(matches!(
+            NativeTransport::connect_with_known_hosts(
+                &device,
+                Some(&wrong_hosts)
+            ),
+            Err(Error::HostKeyMismatch(_))
+        ));
+
+        // An unknown host is refused without a terminal to ask
+        let empty_hosts = dir.join("empty_known_hosts");
+        if !std::io::IsTerminal::is_terminal(&std::io::stdin()) {
+            assert!(matches!(
+                NativeTransport::connect_with_known_hosts(
+                    &device,
+                    Some(&empty_hosts)
+                ),
+                Err(Error::UnknownHostKey(_))
+            ));
+            assert!(!empty_hosts.exists());
+        }
+
+        // A key that isn't authorized
+        keygen(&dir.join("other_key"));
+        device.key = Some(dir.join("other_key"));
+        assert!(matches!(
+            NativeTransport::connect_with_known_hosts(
+                &device,
+                Some(&known_hosts)
+            ),
+            Err(Error::SSHAuthenticationFailed)
+        ));
+    }
+}
@@ -0,0 +1,499 @@
+//! Watch mode for deploy
+//!
+//! The game is deployed like a normal deploy first, after that the game file
+//! directory is watched and only the files that changes gets uploaded, over
+//! the same connection. Changes to the manifest, the shortcut or the artwork
+//! needs a normal deploy.
+
+use std::collections::BTreeSet;
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use crate::device::Device;
+use crate::remote_command::RemoteCommand;
+use crate::transport::{is_excluded, Transport, KEEPALIVE_INTERVAL};
+use crate::{DeployConfig, Error, Result};
+
+/// How long the directory has to be quiet before the changes are uploaded,
+/// a build writes a lot of files in a row
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Upload anyway when the directory never gets quiet, i.e something keeps
+/// writing to a log file
+const MAX_DEBOUNCE: Duration = Duration::from_secs(5);
+
+/// The grace period used when the game is restarted
+const RESTART_GRACE_PERIOD: u64 = 5;
+
+type Events = Receiver<notify::Result<notify::Event>>;
+
+/// The paths that changed since the last upload
+#[derive(Debug, Default)]
+struct Batch {
+    paths: BTreeSet<PathBuf>,
+
+    /// The watcher lost events, everything has to be deployed again
+    rescan: bool,
+
+    /// The game got stopped for the upload and has to be started again
+    stopped_game: bool,
+}
+
+impl Batch {
+    fn add(&mut self, event: notify::Result<notify::Event>) {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                println!("Warning: {}", e);
+                self.rescan = true;
+                return;
+            }
+        };
+
+        if event.need_rescan() {
+            self.rescan = true;
+        }
+
+        // NOTE(patrik): Reading a file doesn't change it
+        if !matches!(event.kind, EventKind::Access(_)) {
+            self.paths.extend(event.paths);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.paths.is_empty() && !self.rescan
+    }
+}
+
+/// What needs to happen on the devkit, the paths are relative to the game
+/// file directory
+#[derive(Debug, Default, PartialEq)]
+struct Changes {
+    /// Directories to create, they might be empty
+    dirs: BTreeSet<String>,
+
+    /// Files to upload
+    upload: BTreeSet<String>,
+
+    /// Files and directories that got removed on the host
+    remove: BTreeSet<String>,
+}
+
+impl Changes {
+    fn is_empty(&self) -> bool {
+        self.dirs.is_empty() &&
+            self.upload.is_empty() &&
+            self.remove.is_empty()
+    }
+}
+
+/// Wait for the first change, the connection is kept open while waiting
+fn wait(
+    events: &Events,
+    batch: &mut Batch,
+    transport: &dyn Transport,
+) -> Result<()> {
+    loop {
+        match events.recv_timeout(KEEPALIVE_INTERVAL) {
+            Ok(event) => {
+                batch.add(event);
+
+                // NOTE(patrik): Only access events, keep waiting
+                if !batch.is_empty() {
+                    return Ok(());
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => transport.keepalive()?,
+            Err(RecvTimeoutError::Disconnected) => {
+                unreachable!("the watcher is alive while watching")
+            }
+        }
+    }
+}
+
+/// Add the events to the batch until nothing has changed for the debounce
+/// time
+fn debounce(
+    events: &Events,
+    batch: &mut Batch,
+    quiet: Duration,
+    max: Duration,
+) {
+    let started = Instant::now();
+    while started.elapsed() < max {
+        match events.recv_timeout(quiet) {
+            Ok(event) => batch.add(event),
+            Err(_) => break,
+        }
+    }
+}
+
+/// The path relative to the directory with '/' as the separator, None for
+/// paths outside of the directory and the directory itself
+fn relative_path(dir: &Path, path: &Path) -> Option<String> {
+    let path = path.strip_prefix(dir).ok()?;
+
+    let mut parts = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_string_lossy()),
+            _ => return None,
+        }
+    }
+
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join("/"))
+    }
+}
+
+/// Check the path and the directories it's in against the exclude patterns
+fn is_path_excluded(exclude: &[String], relative: &str, is_dir: bool) -> bool {
+    let excluded_dir = relative
+        .match_indices('/')
+        .any(|(i, _)| is_excluded(exclude, &relative[..i], true));
+
+    excluded_dir || is_excluded(exclude, relative, is_dir)
+}
+
+fn add_path(
+    changes: &mut Changes,
+    path: &Path,
+    relative: String,
+    exclude: &[String],
+) -> Result<()> {
+    // NOTE(patrik): Symlinks are followed like the sync of deploy does
+    let is_dir = path.is_dir();
+    if is_path_excluded(exclude, &relative, is_dir) {
+        return Ok(());
+    }
+
+    if is_dir {
+        // NOTE(patrik): The watcher might not report the files of a
+        // directory that got moved or created with files in it
+        let entries =
+            std::fs::read_dir(path).map_err(Error::FailedToReadLocalFile)?;
+        for entry in entries {
+            let entry = entry.map_err(Error::FailedToReadLocalFile)?;
+            let name = entry.file_name();
+            let child = format!("{}/{}", relative, name.to_string_lossy());
+            add_path(changes, &entry.path(), child, exclude)?;
+        }
+
+        changes.dirs.insert(relative);
+    } else if path.exists() {
+        changes.upload.insert(relative);
+    } else {
+        changes.remove.insert(relative);
+    }
+
+    Ok(())
+}
+
+/// Turn the changed paths into changes for the devkit
+fn resolve(
+    source_dir: &Path,
+    paths: &BTreeSet<PathBuf>,
+    exclude: &[String],
+) -> Result<Changes> {
+    let mut changes = Changes::default();
+    for path in paths {
+        if let Some(relative) = relative_path(source_dir, path) {
+            add_path(&mut changes, path, relative, exclude)?;
+        }
+    }
+
+    // NOTE(patrik): A removed directory reports the files in it too, they
+    // are gone with the directory
+    let removed_dirs = changes.remove.clone();
+    changes.remove.retain(|relative| {
+        !relative
+            .match_indices('/')
+            .any(|(i, _)| removed_dirs.contains(&relative[..i]))
+    });
+
+    Ok(changes)
+}
+
+/// Remove and upload the files on the devkit
+fn apply(
+    transport: &dyn Transport,
+    source_dir: &Path,
+    game_dir: &str,
+    changes: &Changes,
+) -> Result<()> {
+    let remote = |relative: &str| format!("{}/{}", game_dir, relative);
+
+    if !changes.remove.is_empty() {
+        let mut cmd = RemoteCommand::new("rm").arg("-rf").arg("--");
+        for relative in changes.remove.iter() {
+            cmd = cmd.path(&remote(relative));
+        }
+
+        transport
+            .execute(&cmd)?
+            .check()
+            .map_err(Error::FailedToUpdateGameFiles)?;
+    }
+
+    // NOTE(patrik): scp doesn't create the parent directories
+    let mut dirs = changes.dirs.clone();
+    dirs.extend(changes.upload.iter().filter_map(|relative| {
+        relative.rsplit_once('/').map(|(dir, _)| dir.to_string())
+    }));
+
+    if !dirs.is_empty() {
+        let mut cmd = RemoteCommand::new("mkdir").arg("-p").arg("--");
+        for relative in dirs.iter() {
+            cmd = cmd.path(&remote(relative));
+        }
+
+        transport
+            .execute(&cmd)?
+            .check()
+            .map_err(Error::FailedToUpdateGameFiles)?;
+    }
+
+    for relative in changes.upload.iter() {
+        transport
+            .upload(&source_dir.join(relative), &remote(relative))
+            .map_err(|e| Error::FailedToSyncGameFiles(Box::new(e)))?;
+    }
+
+    Ok(())
+}
+
+/// Upload the changes, with restart a running game is stopped before the
+/// upload and started again after
+fn sync(
+    device: &Device,
+    transport: &dyn Transport,
+    config: &DeployConfig,
+    source_dir: &Path,
+    batch: &mut Batch,
+    restart: bool,
+) -> Result<()> {
+    let changes = if batch.rescan {
+        None
+    } else {
+        let changes = resolve(source_dir, &batch.paths, &config.exclude)?;
+        if changes.is_empty() && !batch.stopped_game {
+            return Ok(());
+        }
+
+        Some(changes)
+    };
+
+    // NOTE(patrik): The game is stopped first, a running executable can't
+    // be overwritten. It's started again by the next upload if this one
+    // fails.
+    if restart && !batch.stopped_game {
+        batch.stopped_game = crate::kill_game(
+            device,
+            transport,
+            &config.game_id,
+            RESTART_GRACE_PERIOD,
+        )?;
+    }
+
+    match changes {
+        Some(changes) => {
+            apply(transport, source_dir, &config.game_dir, &changes)?;
+            println!(
+                "Uploaded {} files, removed {}",
+                changes.upload.len(),
+                changes.remove.len()
+            );
+        }
+        None => {
+            println!("Lost track of the changes, deploying everything");
+            crate::deploy(device, transport, config)?;
+        }
+    }
+
+    if batch.stopped_game {
+        crate::run_game(device, transport, &config.game_id, false)?;
+    }
+
+    Ok(())
+}
+
+/// Deploy the game and upload the changes until decker is stopped
+pub fn watch(
+    device: &Device,
+    transport: &dyn Transport,
+    config: &DeployConfig,
+    restart: bool,
+) -> Result<()> {
+    // NOTE(patrik): The paths from the watcher are canonical on some
+    // platforms
+    let source_dir = config
+        .source_dir
+        .canonicalize()
+        .map_err(Error::FailedToReadLocalFile)?;
+
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)
+        .map_err(|e| Error::FailedToWatch(source_dir.clone(), e))?;
+    watcher
+        .watch(&source_dir, RecursiveMode::Recursive)
+        .map_err(|e| Error::FailedToWatch(source_dir.clone(), e))?;
+
+    // NOTE(patrik): The watcher is started first so the changes made during
+    // the deploy gets uploaded too
+    crate::deploy(device, transport, config)?;
+
+    println!(
+        "Watching {} for changes, press Ctrl-C to stop",
+        source_dir.display()
+    );
+
+    let mut batch = Batch::default();
+    loop {
+        wait(&events, &mut batch, transport)?;
+        debounce(&events, &mut batch, DEBOUNCE, MAX_DEBOUNCE);
+
+        match sync(device, transport, config, &source_dir, &mut batch, restart)
+        {
+            Ok(()) => batch = Batch::default(),
+
+            // NOTE(patrik): The changes are kept and tried again with the
+            // next change, i.e the build wasn't done writing a file
+            Err(e) => {
+                crate::print_error(&e, e.exit_code(), false);
+                println!("Trying again on the next change");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use notify::event::{AccessKind, CreateKind, Flag};
+    use notify::Event;
+
+    fn set(items: &[&str]) -> BTreeSet<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    #[test]
+    fn relative_paths() {
+        let dir = Path::new("/tmp/game");
+        assert_eq!(
+            relative_path(dir, Path::new("/tmp/game/bin/game")),
+            Some("bin/game".to_string())
+        );
+        assert_eq!(relative_path(dir, Path::new("/tmp/game")), None);
+        assert_eq!(relative_path(dir, Path::new("/tmp/other")), None);
+    }
+
+    #[test]
+    fn excluded_parent_dir() {
+        let exclude = vec![".git".to_string(), "/build/".to_string()];
+        assert!(is_path_excluded(&exclude, ".git/index", false));
+        assert!(is_path_excluded(&exclude, "build/obj/main.o", false));
+        assert!(!is_path_excluded(&exclude, "src/build", false));
+        assert!(!is_path_excluded(&exclude, "bin/game", false));
+    }
+
+    #[test]
+    fn resolve_changes() {
+        let temp = mktemp::Temp::new_dir().unwrap();
+        let dir = temp.to_path_buf();
+        std::fs::write(dir.join("game"), "").unwrap();
+        std::fs::create_dir_all(dir.join("data/levels")).unwrap();
+        std::fs::write(dir.join("data/levels/1.lvl"), "").unwrap();
+        std::fs::create_dir_all(dir.join("data/empty")).unwrap();
+        std::fs::write(dir.join("data/game.pdb"), "").unwrap();
+
+        let paths = [
+            "game",
+            "data",
+            "removed.txt",
+            "gone",
+            "gone/file",
+            "data/game.pdb",
+            ".git/index",
+        ]
+        .iter()
+        .map(|path| dir.join(path))
+        .chain([PathBuf::from("/somewhere/else")])
+        .collect();
+        let exclude = vec![".git".to_string(), "*.pdb".to_string()];
+
+        let changes = resolve(&dir, &paths, &exclude).unwrap();
+        assert_eq!(
+            changes,
+            Changes {
+                dirs: set(&["data", "data/empty", "data/levels"]),
+                upload: set(&["data/levels/1.lvl", "game"]),
+                remove: set(&["gone", "removed.txt"]),
+            }
+        );
+    }
+
+    #[test]
+    fn debounce_events() {
+        let (sender, events) = mpsc::channel();
+
+        let create = |path: &str| {
+            Ok(Event::new(EventKind::Create(CreateKind::File))
+                .add_path(PathBuf::from(path)))
+        };
+
+        sender.send(create("/game/a")).unwrap();
+        sender.send(create("/game/b")).unwrap();
+        sender
+            .send(Ok(Event::new(EventKind::Access(AccessKind::Any))
+                .add_path(PathBuf::from("/game/c"))))
+            .unwrap();
+
+        let mut batch = Batch::default();
+        debounce(
+            &events,
+            &mut batch,
+            Duration::from_millis(50),
+            Duration::from_secs(5),
+        );
+        assert_eq!(batch.paths.len(), 2);
+        assert!(!batch.rescan);
+
+        // NOTE(patrik): Events that keeps coming stops at the max time
+        let started = Instant::now();
+        let sender_thread = std::thread::spawn(move || {
+            for _ in 0..50 {
+                if sender.send(create("/game/d")).is_err() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(20));
+            }
+
+            let _ = sender
+                .send(Ok(Event::new(EventKind::Other).set_flag(Flag::Rescan)));
+        });
+
+        debounce(
+            &events,
+            &mut batch,
+            Duration::from_millis(100),
+            Duration::from_millis(200),
+        );
+        assert!(started.elapsed() < Duration::from_millis(800));
+        assert_eq!(batch.paths.len(), 3);
+
+        sender_thread.join().unwrap();
+        debounce(
+            &events,
+            &mut batch,
+            Duration::from_millis(50),
+            Duration::from_secs(5),
+        );
+        assert!(batch.rescan);
+    }
+}
@@ -0,0 +1,1102 @@
+//! Deserialize Rust types from a parsed `Object`
+//!
+//! Works with both the binary and the text format. Because the text format
+//! stores everything as strings, numbers and booleans are parsed from
+//! strings when needed.
+//!
+//! Neither format has a null value, a `None` is written by leaving the key
+//! out. So a missing key deserializes to `None` and a key that exists is
+//! always `Some`. Pointers, wide strings and colors don't have a serde type,
+//! `Value` wraps them in private newtypes so they only keep their type when
+//! they go through this crate's serializer and deserializer.
+
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor,
+};
+use serde::Deserialize;
+
+use crate::{Error, Object, Result, Value};
+
+pub(crate) const POINTER_TOKEN: &str = "$vdf::Pointer";
+pub(crate) const WIDE_STRING_TOKEN: &str = "$vdf::WideString";
+pub(crate) const COLOR_TOKEN: &str = "$vdf::Color";
+
+impl de::Error for Error {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        Error::Message(msg.to_string())
+    }
+}
+
+/// Deserialize a type from an already parsed object
+pub fn from_object<'de, T>(obj: &'de Object) -> Result<T>
+where
+    T: Deserialize<'de>,
+{
+    T::deserialize(ObjectDeserializer { obj })
+}
+
+/// Parse binary VDF data and deserialize a type from it
+pub fn from_bytes<T>(bytes: &[u8]) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    let obj = crate::parse(bytes)?;
+    from_object(&obj)
+}
+
+/// Deserializer for a single value
+pub struct Deserializer<'de> {
+    value: &'de Value,
+}
+
+impl<'de> Deserializer<'de> {
+    pub fn new(value: &'de Value) -> Self {
+        Self { value }
+    }
+
+    fn invalid_type(&self, exp: &dyn de::Expected) -> Error {
+        let unexp = match self.value {
+            Value::Object(_) => de::Unexpected::Map,
+            Value::String(s) => de::Unexpected::Str(s),
+            Value::WideString(_) => de::Unexpected::Other("wide string"),
+            Value::Integer(i) | Value::Pointer(i) => {
+                de::Unexpected::Unsigned(*i as u64)
+            }
+            Value::Float(v) => de::Unexpected::Float(*v as f64),
+            Value::Color(_) => de::Unexpected::Other("color"),
+            Value::Uint64(i) => de::Unexpected::Unsigned(*i),
+            Value::Int64(i) => de::Unexpected::Signed(*i),
+        };
+
+        de::Error::invalid_type(unexp, exp)
+    }
+
+    /// Parse a string value, used for numbers stored as text
+    fn parse_str<T, V>(&self, s: &str, visitor: &V) -> Result<T>
+    where
+        T: std::str::FromStr,
+        V: Visitor<'de>,
+    {
+        s.trim().parse().map_err(|_| self.invalid_type(visitor))
+    }
+}
+
+macro_rules! deserialize_signed {
+    ($method:ident, $visit:ident, $typ:ty) => {
+        fn $method<V>(self, visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+        {
+            match self.value {
+                // NOTE(patrik): Integers are stored as u32 but they are
+                // signed in the format, so reinterpret the bits
+                Value::Integer(i) | Value::Pointer(i) => {
+                    visitor.visit_i32(*i as i32)
+                }
+                Value::Uint64(i) => visitor.visit_u64(*i),
+                Value::Int64(i) => visitor.visit_i64(*i),
+                Value::String(s) => match s.trim().parse::<$typ>() {
+                    Ok(v) => visitor.$visit(v),
+
+                    // NOTE(patrik): Integers written by us are written as
+                    // u32, so try that too
+                    Err(_) => {
+                        let v: u32 = self.parse_str(s, &visitor)?;
+                        visitor.visit_i32(v as i32)
+                    }
+                },
+                _ => Err(self.invalid_type(&visitor)),
+            }
+        }
+    };
+}
+
+macro_rules! deserialize_unsigned {
+    ($method:ident, $visit:ident, $typ:ty) => {
+        fn $method<V>(self, visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+        {
+            match self.value {
+                Value::Integer(i) | Value::Pointer(i) => visitor.visit_u32(*i),
+                Value::Uint64(i) => visitor.visit_u64(*i),
+                Value::Int64(i) => visitor.visit_i64(*i),
+                Value::String(s) => {
+                    let v: $typ = self.parse_str(s, &visitor)?;
+                    visitor.$visit(v)
+                }
+                _ => Err(self.invalid_type(&visitor)),
+            }
+        }
+    };
+}
+
+macro_rules! deserialize_float {
+    ($method:ident, $visit:ident, $typ:ty) => {
+        fn $method<V>(self, visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+        {
+            match self.value {
+                Value::Float(v) => visitor.visit_f32(*v),
+                Value::Integer(i) => visitor.visit_u32(*i),
+                Value::Uint64(i) => visitor.visit_u64(*i),
+                Value::Int64(i) => visitor.visit_i64(*i),
+                Value::String(s) => {
+                    let v: $typ = self.parse_str(s, &visitor)?;
+                    visitor.$visit(v)
+                }
+                _ => Err(self.invalid_type(&visitor)),
+            }
+        }
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.value {
+            Value::Object(o) => visitor.visit_map(MapAccess::new(o)),
+            Value::String(s) => visitor.visit_borrowed_str(s),
+            Value::Integer(i) => visitor.visit_u32(*i),
+            Value::Float(v) => visitor.visit_f32(*v),
+            Value::Uint64(i) => visitor.visit_u64(*i),
+            Value::Int64(i) => visitor.visit_i64(*i),
+
+            // NOTE(patrik): These don't have a matching serde type so they
+            // are wrapped in a newtype, that way `Value` can tell them apart
+            // from the other types
+            Value::Pointer(p) => {
+                visitor.visit_newtype_struct(p.into_deserializer())
+            }
+            Value::WideString(s) => visitor.visit_newtype_struct(
+                de::value::SeqDeserializer::new(s.iter().copied()),
+            ),
+            Value::Color(c) => visitor
+                .visit_newtype_struct(de::value::BytesDeserializer::new(c)),
+        }
+    }
+
+    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.value {
+            Value::Integer(i) => visitor.visit_bool(*i != 0),
+            Value::Uint64(i) => visitor.visit_bool(*i != 0),
+            Value::Int64(i) => visitor.visit_bool(*i != 0),
+            Value::String(s) => match s.trim() {
+                "1" | "true" => visitor.visit_bool(true),
+                "0" | "false" => visitor.visit_bool(false),
+                _ => Err(self.invalid_type(&visitor)),
+            },
+            _ => Err(self.invalid_type(&visitor)),
+        }
+    }
+
+    deserialize_signed!(deserialize_i8, visit_i8, i8);
+    deserialize_signed!(deserialize_i16, visit_i16, i16);
+    deserialize_signed!(deserialize_i32, visit_i32, i32);
+    deserialize_signed!(deserialize_i64, visit_i64, i64);
+    deserialize_unsigned!(deserialize_u8, visit_u8, u8);
+    deserialize_unsigned!(deserialize_u16, visit_u16, u16);
+    deserialize_unsigned!(deserialize_u32, visit_u32, u32);
+    deserialize_unsigned!(deserialize_u64, visit_u64, u64);
+    deserialize_float!(deserialize_f32, visit_f32, f32);
+    deserialize_float!(deserialize_f64, visit_f64, f64);
+
+    fn deserialize_char<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.value {
+            Value::String(s) => visitor.visit_borrowed_str(s),
+            Value::WideString(s) => match String::from_utf16(s) {
+                Ok(s) => visitor.visit_string(s),
+                Err(_) => Err(de::Error::invalid_value(
+                    de::Unexpected::Other("invalid UTF-16"),
+                    &visitor,
+                )),
+            },
+            Value::Integer(i) => visitor.visit_string(i.to_string()),
+            Value::Float(v) => visitor.visit_string(v.to_string()),
+            Value::Uint64(i) => visitor.visit_string(i.to_string()),
+            Value::Int64(i) => visitor.visit_string(i.to_string()),
+            _ => Err(self.invalid_type(&visitor)),
+        }
+    }
+
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.value {
+            Value::Color(c) => visitor.visit_borrowed_bytes(c),
+            Value::String(s) => visitor.visit_borrowed_bytes(s.as_bytes()),
+            _ => Err(self.invalid_type(&visitor)),
+        }
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        // NOTE(patrik): A missing key is how we represent None, so if we
+        // have a value it's always Some
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.value {
+            Value::Object(o) => visitor.visit_seq(SeqAccess::new(o)),
+
+            // NOTE(patrik): So they can be read as i.e [u8; 4] and Vec<u16>
+            Value::WideString(s) => visitor
+                .visit_seq(de::value::SeqDeserializer::new(s.iter().copied())),
+            Value::Color(c) => visitor
+                .visit_seq(de::value::SeqDeserializer::new(c.iter().copied())),
+            _ => Err(self.invalid_type(&visitor)),
+        }
+    }
+
+    fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.value {
+            Value::Object(o) => visitor.visit_map(MapAccess::new(o)),
+            _ => Err(self.invalid_type(&visitor)),
+        }
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.value {
+            Value::Object(o) => visitor.visit_map(MapAccess::first_only(o)),
+            _ => Err(self.invalid_type(&visitor)),
+        }
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.value {
+            // Unit variant
+            Value::String(s) => visitor.visit_enum(
+                de::value::BorrowedStrDeserializer::<Error>::new(s),
+            ),
+
+            // Variant with data, stored as an object with a single key
+            Value::Object(o) if o.values.len() == 1 => {
+                let (variant, value) = &o.values[0];
+                visitor.visit_enum(EnumAccess { variant, value })
+            }
+
+            _ => Err(self.invalid_type(&visitor)),
+        }
+    }
+
+    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+}
+
+/// Deserializer for the top level object
+struct ObjectDeserializer<'de> {
+    obj: &'de Object,
+}
+
+impl<'de> de::Deserializer<'de> for ObjectDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_map(MapAccess::new(self.obj))
+    }
+
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_seq(SeqAccess::new(self.obj))
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_map(MapAccess::first_only(self.obj))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct tuple tuple_struct map enum
+        identifier ignored_any
+    }
+}
+
+/// Deserializer for object keys, keys are always strings but they can be
+/// parsed as numbers i.e the indices of the "shortcuts" object
+struct KeyDeserializer<'de> {
+    key: &'de str,
+}
+
+macro_rules! deserialize_key_number {
+    ($method:ident, $visit:ident) => {
+        fn $method<V>(self, visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+        {
+            match self.key.parse() {
+                Ok(v) => visitor.$visit(v),
+                Err(_) => Err(de::Error::invalid_type(
+                    de::Unexpected::Str(self.key),
+                    &visitor,
+                )),
+            }
+        }
+    };
+}
+
+impl<'de> de::Deserializer<'de> for KeyDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_borrowed_str(self.key)
+    }
+
+    deserialize_key_number!(deserialize_i8, visit_i8);
+    deserialize_key_number!(deserialize_i16, visit_i16);
+    deserialize_key_number!(deserialize_i32, visit_i32);
+    deserialize_key_number!(deserialize_i64, visit_i64);
+    deserialize_key_number!(deserialize_u8, visit_u8);
+    deserialize_key_number!(deserialize_u16, visit_u16);
+    deserialize_key_number!(deserialize_u32, visit_u32);
+    deserialize_key_number!(deserialize_u64, visit_u64);
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_enum(self.key.into_deserializer())
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i128 u128 f32 f64 char str string bytes byte_buf option unit
+        unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct MapAccess<'de> {
+    values: &'de [(String, Value)],
+    index: usize,
+    first_only: bool,
+    value: Option<&'de Value>,
+}
+
+impl<'de> MapAccess<'de> {
+    fn new(obj: &'de Object) -> Self {
+        Self {
+            values: &obj.values,
+            index: 0,
+            first_only: false,
+            value: None,
+        }
+    }
+
+    /// Skip the duplicate keys, structs only get the first entry of a key
+    /// the same way Steam looks up keys
+    fn first_only(obj: &'de Object) -> Self {
+        Self {
+            first_only: true,
+            ..Self::new(obj)
+        }
+    }
+
+    fn next_entry(&mut self) -> Option<&'de (String, Value)> {
+        while let Some(entry) = self.values.get(self.index) {
+            let seen = &self.values[..self.index];
+            self.index += 1;
+
+            if !self.first_only || !seen.iter().any(|v| v.0 == entry.0) {
+                return Some(entry);
+            }
+        }
+
+        None
+    }
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        match self.next_entry() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(KeyDeserializer { key }).map(Some)
+            }
+
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let value = self.value.take().ok_or_else(|| {
+            Error::Message("value without a key".to_string())
+        })?;
+
+        seed.deserialize(Deserializer::new(value))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.values.len() - self.index)
+    }
+}
+
+/// Sequences are stored as objects with the keys "0", "1", "2"... so we
+/// just ignore the keys
+struct SeqAccess<'de> {
+    iter: std::slice::Iter<'de, (String, Value)>,
+}
+
+impl<'de> SeqAccess<'de> {
+    fn new(obj: &'de Object) -> Self {
+        Self {
+            iter: obj.values.iter(),
+        }
+    }
+}
+
+impl<'de> de::SeqAccess<'de> for SeqAccess<'de> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.iter.next() {
+            Some((_, value)) => {
+                seed.deserialize(Deserializer::new(value)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct EnumAccess<'de> {
+    variant: &'de str,
+    value: &'de Value,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess<'de> {
+    type Error = Error;
+    type Variant = Deserializer<'de>;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant)>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let variant =
+            seed.deserialize(KeyDeserializer { key: self.variant })?;
+        Ok((variant, Deserializer::new(self.value)))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Deserializer<'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a vdf value")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> std::result::Result<Value, E> {
+        Ok(Value::Integer(v as u32))
+    }
+
+    fn visit_i32<E>(self, v: i32) -> std::result::Result<Value, E> {
+        Ok(Value::Integer(v as u32))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> std::result::Result<Value, E> {
+        Ok(Value::Int64(v))
+    }
+
+    fn visit_u32<E>(self, v: u32) -> std::result::Result<Value, E> {
+        Ok(Value::Integer(v))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> std::result::Result<Value, E> {
+        Ok(Value::Uint64(v))
+    }
+
+    fn visit_f32<E>(self, v: f32) -> std::result::Result<Value, E> {
+        Ok(Value::Float(v))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> std::result::Result<Value, E> {
+        Ok(Value::Float(v as f32))
+    }
+
+    fn visit_str<E>(self, v: &str) -> std::result::Result<Value, E> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn visit_string<E>(self, v: String) -> std::result::Result<Value, E> {
+        Ok(Value::String(v))
+    }
+
+    fn visit_newtype_struct<D>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<Value, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(SpecialValueVisitor)
+    }
+
+    fn visit_map<A>(self, map: A) -> std::result::Result<Value, A::Error>
+    where
+        A: de::MapAccess<'de>,
+    {
+        Ok(Value::Object(ObjectVisitor.visit_map(map)?))
+    }
+}
+
+/// Visitor for the wrapped values (see `Deserializer::deserialize_any`)
+struct SpecialValueVisitor;
+
+impl<'de> Visitor<'de> for SpecialValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a pointer, wide string or color")
+    }
+
+    fn visit_u32<E>(self, v: u32) -> std::result::Result<Value, E> {
+        Ok(Value::Pointer(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> std::result::Result<Value, E> {
+        Ok(Value::WideString(v.encode_utf16().collect()))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Value, A::Error>
+    where
+        A: de::SeqAccess<'de>,
+    {
+        let mut units = Vec::new();
+        while let Some(unit) = seq.next_element::<u16>()? {
+            units.push(unit);
+        }
+
+        Ok(Value::WideString(units))
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> std::result::Result<Value, E>
+    where
+        E: de::Error,
+    {
+        let color = v
+            .try_into()
+            .map_err(|_| E::invalid_length(v.len(), &"4 bytes"))?;
+
+        Ok(Value::Color(color))
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ObjectVisitor;
+
+impl<'de> Visitor<'de> for ObjectVisitor {
+    type Value = Object;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a vdf object")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> std::result::Result<Object, A::Error>
+    where
+        A: de::MapAccess<'de>,
+    {
+        let mut obj = Object::new();
+
+        while let Some((key, value)) = map.next_entry::<String, Value>()? {
+            obj.push_value(key, value);
+        }
+
+        Ok(obj)
+    }
+}
+
+impl<'de> Deserialize<'de> for Object {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        deserializer.deserialize_map(ObjectVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::{text, to_bytes, to_object};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Game {
+        #[serde(rename = "AppName")]
+        name: String,
+        #[serde(rename = "appid")]
+        app_id: u32,
+        #[serde(rename = "IsHidden")]
+        hidden: bool,
+        #[serde(rename = "Offset")]
+        offset: i32,
+        #[serde(rename = "Scale")]
+        scale: f32,
+        #[serde(rename = "Size")]
+        size: u64,
+        #[serde(rename = "tags")]
+        tags: Vec<String>,
+        #[serde(rename = "Icon")]
+        icon: Option<String>,
+    }
+
+    fn game() -> Game {
+        Game {
+            name: "Test Game".to_string(),
+            app_id: 0x8000_0001,
+            hidden: true,
+            offset: -5,
+            scale: 1.5,
+            size: 1 << 40,
+            tags: vec!["a".to_string(), "b".to_string()],
+            icon: None,
+        }
+    }
+
+    #[test]
+    fn round_trip_binary() {
+        let bytes = to_bytes(&game()).unwrap();
+        assert_eq!(from_bytes::<Game>(&bytes).unwrap(), game());
+
+        let with_icon = Game {
+            icon: Some("icon.png".to_string()),
+            ..game()
+        };
+        let bytes = to_bytes(&with_icon).unwrap();
+        assert_eq!(from_bytes::<Game>(&bytes).unwrap(), with_icon);
+    }
+
+    #[test]
+    fn round_trip_text() {
+        let s = text::to_string(&game()).unwrap();
+        assert_eq!(text::from_str::<Game>(&s).unwrap(), game());
+    }
+
+    #[test]
+    fn rename() {
+        let obj = to_object(&game()).unwrap();
+        let keys = obj
+            .values()
+            .iter()
+            .map(|v| v.0.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            [
+                "AppName", "appid", "IsHidden", "Offset", "Scale", "Size",
+                "tags"
+            ]
+        );
+
+        assert_eq!(
+            obj.value("AppName"),
+            Some(&Value::String("Test Game".to_string()))
+        );
+        assert_eq!(obj.value("IsHidden"), Some(&Value::Integer(1)));
+
+        let tags = match obj.value("tags") {
+            Some(Value::Object(tags)) => tags,
+            value => panic!("{:?}", value),
+        };
+        assert_eq!(tags.value("0"), Some(&Value::String("a".to_string())));
+        assert_eq!(tags.value("1"), Some(&Value::String("b".to_string())));
+    }
+
+    #[test]
+    fn missing_option() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Entry {
+            name: String,
+            icon: Option<String>,
+            last_played: Option<u32>,
+        }
+
+        let entry: Entry =
+            text::from_str("\"name\" \"x\"\n\"last_played\" \"7\"").unwrap();
+        assert_eq!(
+            entry,
+            Entry {
+                name: "x".to_string(),
+                icon: None,
+                last_played: Some(7),
+            }
+        );
+
+        // A key that exists is always Some, even if it's empty
+        let entry: Entry =
+            text::from_str("\"name\" \"x\"\n\"icon\" \"\"").unwrap();
+        assert_eq!(entry.icon, Some(String::new()));
+    }
+
+    #[test]
+    fn numbers_as_strings() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Numbers {
+            int: u32,
+            signed: i32,
+            wrapped: i32,
+            big: u64,
+            float: f32,
+            yes: bool,
+            no: bool,
+        }
+
+        let input = r#"
+            "int"       "42"
+            "signed"    "-3"
+            "wrapped"   "4294967295"
+            "big"       "1099511627776"
+            "float"     " 1.5 "
+            "yes"       "1"
+            "no"        "0"
+        "#;
+        assert_eq!(
+            text::from_str::<Numbers>(input).unwrap(),
+            Numbers {
+                int: 42,
+                signed: -3,
+                wrapped: -1,
+                big: 1 << 40,
+                float: 1.5,
+                yes: true,
+                no: false,
+            }
+        );
+
+        assert!(matches!(
+            text::from_str::<Numbers>("\"int\" \"many\""),
+            Err(Error::Message(_))
+        ));
+    }
+
+    #[test]
+    fn flatten_unknown_keys() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Known {
+            #[serde(rename = "AppName")]
+            name: String,
+            #[serde(flatten)]
+            other: HashMap<String, Value>,
+        }
+
+        let mut obj = Object::new();
+        obj.set_value("AppName".to_string(), Value::String("x".to_string()));
+        obj.set_value("Pointer".to_string(), Value::Pointer(0xdead));
+        obj.set_value(
+            "Wide".to_string(),
+            Value::WideString("w\u{e4}".encode_utf16().collect()),
+        );
+        obj.set_value("Color".to_string(), Value::Color([1, 2, 3, 4]));
+        obj.set_value("Int".to_string(), Value::Integer(3));
+
+        let known: Known = from_bytes(&crate::write(&obj).unwrap()).unwrap();
+        assert_eq!(known.name, "x");
+        assert_eq!(known.other.len(), 4);
+        assert_eq!(known.other["Pointer"], Value::Pointer(0xdead));
+        assert_eq!(known.other["Color"], Value::Color([1, 2, 3, 4]));
+        assert_eq!(known.other["Int"], Value::Integer(3));
+        assert_eq!(
+            known.other["Wide"],
+            Value::WideString("w\u{e4}".encode_utf16().collect())
+        );
+
+        // The unknown keys are written back with their types
+        let obj = to_object(&known).unwrap();
+        assert_eq!(obj.value("Pointer"), Some(&Value::Pointer(0xdead)));
+        assert_eq!(obj.value("Color"), Some(&Value::Color([1, 2, 3, 4])));
+    }
+
+    #[test]
+    fn duplicate_keys() {
+        #[derive(Debug, Deserialize)]
+        struct Entry {
+            name: String,
+            inner: Inner,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Inner {
+            id: u32,
+        }
+
+        let input = r#"
+            "name" "first"
+            "inner" { "id" "1" "id" "2" }
+            "name" "second"
+        "#;
+
+        // Structs get the first entry
+        let entry: Entry = text::from_str(input).unwrap();
+        assert_eq!(entry.name, "first");
+        assert_eq!(entry.inner.id, 1);
+
+        // Objects keep all of them
+        let obj: Object = text::from_str(input).unwrap();
+        let keys = obj
+            .values()
+            .iter()
+            .map(|v| v.0.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, ["name", "inner", "name"]);
+    }
+
+    #[test]
+    fn special_values() {
+        let mut obj = Object::new();
+        obj.set_value("pointer".to_string(), Value::Pointer(0xdead));
+        obj.set_value(
+            "wide".to_string(),
+            Value::WideString(vec![0x41, 0xd800]),
+        );
+        obj.set_value("empty".to_string(), Value::WideString(Vec::new()));
+        obj.set_value("color".to_string(), Value::Color([1, 2, 3, 4]));
+        obj.push_value("color".to_string(), Value::Color([5, 6, 7, 8]));
+
+        // Through our own serializer they keep their types, including
+        // invalid UTF-16 and duplicate keys
+        let copy = to_object(&obj).unwrap();
+        assert_eq!(copy.values(), obj.values());
+
+        let copy: Object = from_object(&obj).unwrap();
+        assert_eq!(copy.values(), obj.values());
+
+        // As normal serde types they are the inner value
+        #[derive(Deserialize)]
+        struct Plain {
+            pointer: u32,
+            wide: Vec<u16>,
+            color: [u8; 4],
+        }
+
+        let plain: Plain = from_object(&obj).unwrap();
+        assert_eq!(plain.pointer, 0xdead);
+        assert_eq!(plain.wide, [0x41, 0xd800]);
+        assert_eq!(plain.color, [1, 2, 3, 4]);
+
+        #[derive(Debug, Deserialize)]
+        struct Wide {
+            #[allow(dead_code)]
+            wide: String,
+        }
+
+        // The wide string is not valid UTF-16 so it can't be a String
+        assert!(from_object::<Wide>(&obj).is_err());
+    }
+}
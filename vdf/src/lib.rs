@@ -1,12 +1,149 @@
+pub mod de;
+pub mod ser;
+pub mod shortcuts;
+pub mod text;
+
+pub use de::{from_bytes, from_object, Deserializer};
+pub use ser::{to_bytes, to_object, Serializer};
+
 #[derive(Debug)]
-pub enum Error {}
+pub enum Error {
+    /// Binary: The data ended before the whole value could be read
+    TruncatedData {
+        offset: usize,
+        expected: &'static str,
+        path: String,
+    },
+
+    /// Binary: A string was missing the null terminator
+    UnterminatedBinaryString { offset: usize, path: String },
+
+    /// Binary: A string was not valid UTF-8
+    InvalidUtf8 { offset: usize, path: String },
+
+    /// Binary: Found a type byte we don't know about
+    UnknownType {
+        offset: usize,
+        typ: u8,
+        path: String,
+    },
+
+    /// Binary: The data ended before the 0x08 object end marker
+    MissingEndMarker { offset: usize, path: String },
+
+    /// Text: The input ended in the middle of a key value pair or object
+    UnexpectedEndOfInput { line: usize },
+
+    /// Text: A quoted string was never closed
+    UnterminatedString { line: usize },
+
+    /// Text: Found a token that is not allowed at this position
+    UnexpectedToken { line: usize, token: String },
+
+    /// Serde: Failed to serialize or deserialize a type
+    Message(String),
+}
+
+impl Error {
+    /// Set the key path of binary errors that don't have one yet
+    fn in_path(mut self, keys: &[String]) -> Self {
+        match &mut self {
+            Error::TruncatedData { path, .. } |
+            Error::UnterminatedBinaryString { path, .. } |
+            Error::InvalidUtf8 { path, .. } |
+            Error::UnknownType { path, .. } |
+            Error::MissingEndMarker { path, .. }
+                if path.is_empty() =>
+            {
+                *path = keys.join("/");
+            }
+
+            _ => {}
+        }
+
+        self
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn path_or_root(path: &str) -> &str {
+            if path.is_empty() {
+                "<root>"
+            } else {
+                path
+            }
+        }
+
+        match self {
+            Error::TruncatedData {
+                offset,
+                expected,
+                path,
+            } => write!(
+                f,
+                "data ended at offset {} while reading {} (at {})",
+                offset,
+                expected,
+                path_or_root(path)
+            ),
+            Error::UnterminatedBinaryString { offset, path } => write!(
+                f,
+                "string starting at offset {} is not null terminated (at {})",
+                offset,
+                path_or_root(path)
+            ),
+            Error::InvalidUtf8 { offset, path } => write!(
+                f,
+                "string starting at offset {} is not valid UTF-8 (at {})",
+                offset,
+                path_or_root(path)
+            ),
+            Error::UnknownType { offset, typ, path } => write!(
+                f,
+                "unknown type {:#04x} at offset {} (at {})",
+                typ,
+                offset,
+                path_or_root(path)
+            ),
+            Error::MissingEndMarker { offset, path } => write!(
+                f,
+                "data ended at offset {} without an object end marker (at {})",
+                offset,
+                path_or_root(path)
+            ),
+            Error::UnexpectedEndOfInput { line } => {
+                write!(f, "unexpected end of input on line {}", line)
+            }
+            Error::UnterminatedString { line } => {
+                write!(f, "string starting on line {} is never closed", line)
+            }
+            Error::UnexpectedToken { line, token } => {
+                write!(f, "unexpected {} on line {}", token, line)
+            }
+            Error::Message(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
 
 type Result<T> = std::result::Result<T, Error>;
 
+#[derive(Clone, PartialEq)]
 pub enum Value {
     Object(Object),
     String(String),
     Integer(u32),
+    Float(f32),
+    Pointer(u32),
+
+    /// The raw UTF-16 code units, Steam doesn't check that they are valid
+    /// so they are kept as is
+    WideString(Vec<u16>),
+    Color([u8; 4]),
+    Uint64(u64),
+    Int64(i64),
 }
 
 impl std::fmt::Debug for Value {
@@ -17,20 +154,38 @@ impl std::fmt::Debug for Value {
             }
             Value::String(s) => write!(f, "String({:?})", s),
             Value::Integer(i) => write!(f, "Integer({})", i),
+            Value::Float(v) => write!(f, "Float({})", v),
+            Value::Pointer(p) => write!(f, "Pointer({:#x})", p),
+            Value::WideString(s) => {
+                write!(f, "WideString({:?})", String::from_utf16_lossy(s))
+            }
+            Value::Color(c) => write!(f, "Color({:?})", c),
+            Value::Uint64(i) => write!(f, "Uint64({})", i),
+            Value::Int64(i) => write!(f, "Int64({})", i),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct Object {
     values: Vec<(String, Value)>,
+
+    /// The object was terminated with the alternate end marker (0x0b)
+    /// instead of 0x08, so we write it back the same way
+    alternate_end: bool,
+
+    /// The comments, conditionals and unquoted strings of a parsed text
+    /// document, so they are written back the same way
+    layout: text::Layout,
 }
 
 impl Object {
     pub fn new() -> Self {
-        Self { values: Vec::new() }
+        Self::default()
     }
 
+    /// Set the value of the first entry with the key, the value is added if
+    /// the key doesn't exist
     pub fn set_value(&mut self, key: String, value: Value) {
         for v in self.values.iter_mut() {
             if v.0 == key {
@@ -42,7 +197,14 @@ impl Object {
         self.values.push((key, value));
     }
 
-    pub fn value(&mut self, key: &str) -> Option<&Value> {
+    /// Add the value even if the key already exists, both formats allows
+    /// duplicate keys
+    pub fn push_value(&mut self, key: String, value: Value) {
+        self.values.push((key, value));
+    }
+
+    /// Get the value of the first entry with the key
+    pub fn value(&self, key: &str) -> Option<&Value> {
         for value in self.values.iter() {
             if value.0 == key {
                 return Some(&value.1);
@@ -62,7 +224,13 @@ impl Object {
         None
     }
 
-    pub fn values(&mut self) -> &Vec<(String, Value)> {
+    /// Remove the first entry with the key
+    pub fn remove_value(&mut self, key: &str) -> Option<Value> {
+        let index = self.values.iter().position(|v| v.0 == key)?;
+        Some(self.values.remove(index).1)
+    }
+
+    pub fn values(&self) -> &Vec<(String, Value)> {
         &self.values
     }
 
@@ -74,40 +242,90 @@ impl Object {
 pub fn parse_string(bytes: &[u8], offset: &mut usize) -> Result<String> {
     let start = *offset;
 
-    loop {
-        let b = bytes[*offset];
+    let len = bytes
+        .get(start..)
+        .and_then(|rest| rest.iter().position(|b| *b == 0x00))
+        .ok_or(Error::UnterminatedBinaryString {
+            offset: start,
+            path: String::new(),
+        })?;
+
+    // String + Terminator Byte
+    *offset += len + 1;
+
+    let s = std::str::from_utf8(&bytes[start..start + len]).map_err(|_| {
+        Error::InvalidUtf8 {
+            offset: start,
+            path: String::new(),
+        }
+    })?;
+
+    Ok(s.to_string())
+}
+
+fn parse_wide_string(bytes: &[u8], offset: &mut usize) -> Result<Vec<u16>> {
+    let start = *offset;
 
-        if b == 0x00 {
+    let mut units = Vec::new();
+    loop {
+        let data = bytes.get(*offset..*offset + 2).ok_or(
+            Error::UnterminatedBinaryString {
+                offset: start,
+                path: String::new(),
+            },
+        )?;
+        *offset += 2;
+
+        let unit = u16::from_le_bytes([data[0], data[1]]);
+        if unit == 0x0000 {
             break;
         }
 
-        *offset += 1;
+        units.push(unit);
     }
 
-    // Terminator Byte
-    *offset += 1;
+    Ok(units)
+}
 
-    let s = std::str::from_utf8(&bytes[start..*offset - 1]).unwrap();
-    Ok(s.to_string())
+/// Read the next N bytes
+fn parse_bytes<const N: usize>(
+    bytes: &[u8],
+    offset: &mut usize,
+    expected: &'static str,
+) -> Result<[u8; N]> {
+    let data =
+        bytes
+            .get(*offset..*offset + N)
+            .ok_or(Error::TruncatedData {
+                offset: *offset,
+                expected,
+                path: String::new(),
+            })?;
+    *offset += N;
+
+    Ok(data.try_into().unwrap())
 }
 
 pub fn parse_int(bytes: &[u8], offset: &mut usize) -> Result<u32> {
-    let result =
-        u32::from_le_bytes(bytes[*offset..*offset + 4].try_into().unwrap());
-    *offset += 4;
-
-    Ok(result)
+    Ok(u32::from_le_bytes(parse_bytes(bytes, offset, "int32")?))
 }
 
-pub fn parse_object(bytes: &[u8], offset: &mut usize) -> Result<Object> {
+fn parse_object_in(
+    bytes: &[u8],
+    offset: &mut usize,
+    path: &mut Vec<String>,
+) -> Result<Object> {
     let mut obj = Object::new();
 
     loop {
         if *offset >= bytes.len() {
-            // TODO(patrik): This is should be an error, i think
-            break;
+            return Err(Error::MissingEndMarker {
+                offset: *offset,
+                path: path.join("/"),
+            });
         }
 
+        let typ_offset = *offset;
         let typ = bytes[*offset];
         *offset += 1;
 
@@ -116,32 +334,81 @@ pub fn parse_object(bytes: &[u8], offset: &mut usize) -> Result<Object> {
             break;
         }
 
-        let name = parse_string(bytes, offset)?;
-
-        match typ {
-            // Object
-            0x00 => {
-                let o = parse_object(bytes, offset)?;
-                obj.set_value(name, Value::Object(o));
-            }
+        // Alternate object end marker
+        if typ == 0x0b {
+            obj.alternate_end = true;
+            break;
+        }
 
-            0x01 => {
-                let s = parse_string(bytes, offset)?;
-                obj.set_value(name, Value::String(s));
-            }
+        let name = parse_string(bytes, offset).map_err(|e| e.in_path(path))?;
+        path.push(name);
 
-            0x02 => {
-                let value = parse_int(bytes, offset)?;
-                obj.set_value(name, Value::Integer(value));
+        let value = match typ {
+            // Object
+            0x00 => Value::Object(parse_object_in(bytes, offset, path)?),
+
+            0x01 => Value::String(
+                parse_string(bytes, offset).map_err(|e| e.in_path(path))?,
+            ),
+
+            0x02 => Value::Integer(
+                parse_int(bytes, offset).map_err(|e| e.in_path(path))?,
+            ),
+
+            0x03 => Value::Float(f32::from_le_bytes(
+                parse_bytes(bytes, offset, "float32")
+                    .map_err(|e| e.in_path(path))?,
+            )),
+
+            0x04 => Value::Pointer(u32::from_le_bytes(
+                parse_bytes(bytes, offset, "pointer")
+                    .map_err(|e| e.in_path(path))?,
+            )),
+
+            0x05 => Value::WideString(
+                parse_wide_string(bytes, offset)
+                    .map_err(|e| e.in_path(path))?,
+            ),
+
+            0x06 => Value::Color(
+                parse_bytes(bytes, offset, "color")
+                    .map_err(|e| e.in_path(path))?,
+            ),
+
+            0x07 => Value::Uint64(u64::from_le_bytes(
+                parse_bytes(bytes, offset, "uint64")
+                    .map_err(|e| e.in_path(path))?,
+            )),
+
+            0x0a => Value::Int64(i64::from_le_bytes(
+                parse_bytes(bytes, offset, "int64")
+                    .map_err(|e| e.in_path(path))?,
+            )),
+
+            _ => {
+                return Err(Error::UnknownType {
+                    offset: typ_offset,
+                    typ,
+                    path: path.join("/"),
+                })
             }
+        };
 
-            _ => unimplemented!("Typ: {:#x}", typ),
-        }
+        // NOTE(patrik): Duplicate keys are kept so the data is written back
+        // the same way
+        let name = path.pop().unwrap();
+        obj.push_value(name, value);
     }
 
     Ok(obj)
 }
 
+pub fn parse_object(bytes: &[u8], offset: &mut usize) -> Result<Object> {
+    parse_object_in(bytes, offset, &mut Vec::new())
+}
+
+/// Parse a binary KeyValues document, the data has to end with the 0x08 end
+/// marker of the top level object so empty data is a MissingEndMarker error
 pub fn parse(bytes: &[u8]) -> Result<Object> {
     let mut offset = 0;
     parse_object(bytes, &mut offset)
@@ -153,6 +420,14 @@ fn write_string(buffer: &mut Vec<u8>, s: &String) -> Result<()> {
     Ok(())
 }
 
+fn write_wide_string(buffer: &mut Vec<u8>, units: &[u16]) -> Result<()> {
+    for unit in units {
+        buffer.extend_from_slice(&unit.to_le_bytes());
+    }
+    buffer.extend_from_slice(&[0x00, 0x00]);
+    Ok(())
+}
+
 fn write_integer(buffer: &mut Vec<u8>, i: u32) -> Result<()> {
     buffer.extend_from_slice(&i.to_le_bytes());
     Ok(())
@@ -178,10 +453,50 @@ fn write_object(buffer: &mut Vec<u8>, obj: &Object) -> Result<()> {
                 write_string(buffer, &value.0)?;
                 write_integer(buffer, *i)?;
             }
+
+            Value::Float(v) => {
+                buffer.push(0x03);
+                write_string(buffer, &value.0)?;
+                buffer.extend_from_slice(&v.to_le_bytes());
+            }
+
+            Value::Pointer(p) => {
+                buffer.push(0x04);
+                write_string(buffer, &value.0)?;
+                buffer.extend_from_slice(&p.to_le_bytes());
+            }
+
+            Value::WideString(s) => {
+                buffer.push(0x05);
+                write_string(buffer, &value.0)?;
+                write_wide_string(buffer, s)?;
+            }
+
+            Value::Color(c) => {
+                buffer.push(0x06);
+                write_string(buffer, &value.0)?;
+                buffer.extend_from_slice(c);
+            }
+
+            Value::Uint64(i) => {
+                buffer.push(0x07);
+                write_string(buffer, &value.0)?;
+                buffer.extend_from_slice(&i.to_le_bytes());
+            }
+
+            Value::Int64(i) => {
+                buffer.push(0x0a);
+                write_string(buffer, &value.0)?;
+                buffer.extend_from_slice(&i.to_le_bytes());
+            }
         }
     }
 
-    buffer.push(0x08);
+    if obj.alternate_end {
+        buffer.push(0x0b);
+    } else {
+        buffer.push(0x08);
+    }
 
     Ok(())
 }
@@ -192,3 +507,289 @@ pub fn write(obj: &Object) -> Result<Vec<u8>> {
 
     Ok(buffer)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build binary data from parts, strings get a null terminator
+    macro_rules! data {
+        ($($part:expr),* $(,)?) => {{
+            let mut data: Vec<u8> = Vec::new();
+            $(data.extend_from_slice(&Part::from($part).0);)*
+            data
+        }};
+    }
+
+    struct Part(Vec<u8>);
+
+    impl From<u8> for Part {
+        fn from(b: u8) -> Self {
+            Part(vec![b])
+        }
+    }
+
+    impl From<&str> for Part {
+        fn from(s: &str) -> Self {
+            let mut data = s.as_bytes().to_vec();
+            data.push(0);
+            Part(data)
+        }
+    }
+
+    impl From<&[u8]> for Part {
+        fn from(bytes: &[u8]) -> Self {
+            Part(bytes.to_vec())
+        }
+    }
+
+    #[test]
+    fn empty_data() {
+        assert!(matches!(
+            parse(&[]),
+            Err(Error::MissingEndMarker { offset: 0, ref path }) if path.is_empty()
+        ));
+
+        // Just the end marker is an empty object
+        assert_eq!(parse(&[0x08]).unwrap(), Object::new());
+    }
+
+    #[test]
+    fn truncated_data() {
+        let data = data!(0x00, "obj", 0x02, "int", &[1u8, 2][..]);
+        match parse(&data) {
+            Err(Error::TruncatedData {
+                offset,
+                expected,
+                path,
+            }) => {
+                assert_eq!(offset, 10);
+                assert_eq!(expected, "int32");
+                assert_eq!(path, "obj/int");
+            }
+            res => panic!("{:?}", res),
+        }
+
+        let data = data!(0x07, "big", &[0u8; 7][..]);
+        assert!(matches!(
+            parse(&data),
+            Err(Error::TruncatedData {
+                offset: 5,
+                expected: "uint64",
+                ..
+            })
+        ));
+
+        let data = data!(0x03, "f");
+        assert!(matches!(
+            parse(&data),
+            Err(Error::TruncatedData {
+                offset: 3,
+                expected: "float32",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn unterminated_strings() {
+        // The key
+        let data = data!(0x00, "obj", 0x01, &b"ke"[..]);
+        match parse(&data) {
+            Err(Error::UnterminatedBinaryString { offset, path }) => {
+                assert_eq!(offset, 6);
+                assert_eq!(path, "obj");
+            }
+            res => panic!("{:?}", res),
+        }
+
+        // The value
+        let data = data!(0x01, "key", &b"value"[..]);
+        match parse(&data) {
+            Err(Error::UnterminatedBinaryString { offset, path }) => {
+                assert_eq!(offset, 5);
+                assert_eq!(path, "key");
+            }
+            res => panic!("{:?}", res),
+        }
+
+        // A wide string needs two null bytes
+        let data = data!(0x05, "wide", &[0x41u8, 0x00, 0x00][..]);
+        match parse(&data) {
+            Err(Error::UnterminatedBinaryString { offset, path }) => {
+                assert_eq!(offset, 6);
+                assert_eq!(path, "wide");
+            }
+            res => panic!("{:?}", res),
+        }
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let data = data!(0x00, "obj", 0x01, "name", &[0xffu8, 0xfe, 0][..]);
+        match parse(&data) {
+            Err(Error::InvalidUtf8 { offset, path }) => {
+                assert_eq!(offset, 11);
+                assert_eq!(path, "obj/name");
+            }
+            res => panic!("{:?}", res),
+        }
+    }
+
+    #[test]
+    fn unknown_type() {
+        let data = data!(0x00, "obj", 0x09, "what", 0x08, 0x08);
+        match parse(&data) {
+            Err(Error::UnknownType { offset, typ, path }) => {
+                assert_eq!(offset, 5);
+                assert_eq!(typ, 0x09);
+                assert_eq!(path, "obj/what");
+            }
+            res => panic!("{:?}", res),
+        }
+    }
+
+    #[test]
+    fn missing_end_marker() {
+        let data = data!(0x00, "obj", 0x01, "key", "value");
+        match parse(&data) {
+            Err(Error::MissingEndMarker { offset, path }) => {
+                assert_eq!(offset, data.len());
+                assert_eq!(path, "obj");
+            }
+            res => panic!("{:?}", res),
+        }
+
+        // The nested object is closed but the top level object isn't
+        let data = data!(0x00, "obj", 0x08);
+        match parse(&data) {
+            Err(Error::MissingEndMarker { offset, path }) => {
+                assert_eq!(offset, 6);
+                assert_eq!(path, "");
+            }
+            res => panic!("{:?}", res),
+        }
+    }
+
+    /// Parse the data and check that writing it back gives the same bytes
+    fn round_trip(data: &[u8]) -> Object {
+        let obj = parse(data).unwrap();
+        assert_eq!(write(&obj).unwrap(), data);
+        obj
+    }
+
+    #[test]
+    fn round_trip_types() {
+        let obj = round_trip(&data!(0x00, "obj", 0x08, 0x08));
+        assert_eq!(obj.value("obj"), Some(&Value::Object(Object::new())));
+
+        let obj = round_trip(&data!(0x01, "s", "hello", 0x08));
+        assert_eq!(obj.value("s"), Some(&Value::String("hello".to_string())));
+
+        let obj = round_trip(&data!(0x02, "i", &[1u8, 2, 3, 4][..], 0x08));
+        assert_eq!(obj.value("i"), Some(&Value::Integer(0x04030201)));
+
+        let obj =
+            round_trip(&data!(0x03, "f", &1.5f32.to_le_bytes()[..], 0x08));
+        assert_eq!(obj.value("f"), Some(&Value::Float(1.5)));
+
+        let obj = round_trip(&data!(
+            0x04,
+            "p",
+            &[0xefu8, 0xbe, 0xad, 0xde][..],
+            0x08
+        ));
+        assert_eq!(obj.value("p"), Some(&Value::Pointer(0xdeadbeef)));
+
+        let obj = round_trip(&data!(
+            0x05,
+            "w",
+            &[0x41u8, 0, 0xe4, 0, 0, 0][..],
+            0x08
+        ));
+        assert_eq!(
+            obj.value("w"),
+            Some(&Value::WideString("A\u{e4}".encode_utf16().collect()))
+        );
+
+        let obj = round_trip(&data!(0x06, "c", &[1u8, 2, 3, 4][..], 0x08));
+        assert_eq!(obj.value("c"), Some(&Value::Color([1, 2, 3, 4])));
+
+        let obj =
+            round_trip(&data!(0x07, "u", &u64::MAX.to_le_bytes()[..], 0x08));
+        assert_eq!(obj.value("u"), Some(&Value::Uint64(u64::MAX)));
+
+        let obj =
+            round_trip(&data!(0x0a, "n", &(-2i64).to_le_bytes()[..], 0x08));
+        assert_eq!(obj.value("n"), Some(&Value::Int64(-2)));
+    }
+
+    #[test]
+    fn round_trip_end_markers() {
+        // Nested object with the alternate end marker
+        round_trip(&data!(0x00, "obj", 0x01, "k", "v", 0x0b, 0x08));
+
+        // Top level object with the alternate end marker
+        round_trip(&data!(0x00, "obj", 0x08, 0x0b));
+        round_trip(&data!(0x0b));
+    }
+
+    #[test]
+    fn round_trip_duplicate_keys() {
+        let data = data!(
+            0x01,
+            "k",
+            "1",
+            0x01,
+            "k",
+            "2",
+            0x00,
+            "obj",
+            0x02,
+            "i",
+            &[1u8, 0, 0, 0][..],
+            0x02,
+            "i",
+            &[2u8, 0, 0, 0][..],
+            0x08,
+            0x08,
+        );
+        let obj = round_trip(&data);
+
+        let keys = obj
+            .values()
+            .iter()
+            .map(|v| v.0.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, ["k", "k", "obj"]);
+
+        // Lookups get the first entry
+        assert_eq!(obj.value("k"), Some(&Value::String("1".to_string())));
+    }
+
+    #[test]
+    fn round_trip_invalid_utf16() {
+        // A lone surrogate is not valid UTF-16 but is kept as is
+        let data =
+            data!(0x05, "w", &[0x41u8, 0x00, 0x00, 0xd8, 0, 0][..], 0x08);
+        let obj = round_trip(&data);
+        assert_eq!(
+            obj.value("w"),
+            Some(&Value::WideString(vec![0x41, 0xd800]))
+        );
+    }
+
+    #[test]
+    fn error_messages() {
+        let data = data!(0x00, "obj", 0x09, "what", 0x08, 0x08);
+        assert_eq!(
+            parse(&data).unwrap_err().to_string(),
+            "unknown type 0x09 at offset 5 (at obj/what)"
+        );
+
+        assert_eq!(
+            parse(&[]).unwrap_err().to_string(),
+            "data ended at offset 0 without an object end marker (at <root>)"
+        );
+    }
+}
@@ -0,0 +1,688 @@
+//! Serialize Rust types into an `Object`
+//!
+//! Structs and maps become objects, sequences become objects with the keys
+//! "0", "1", "2"... (the same way Steam stores lists like "tags"), `None`
+//! values are left out and booleans are stored as 0 or 1.
+
+use serde::ser::{self, Serialize};
+
+use crate::de::{COLOR_TOKEN, POINTER_TOKEN, WIDE_STRING_TOKEN};
+use crate::{Error, Object, Result, Value};
+
+impl ser::Error for Error {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        Error::Message(msg.to_string())
+    }
+}
+
+/// Serialize a type into an object, the type needs to serialize to a struct
+/// or a map
+pub fn to_object<T>(value: &T) -> Result<Object>
+where
+    T: Serialize + ?Sized,
+{
+    match value.serialize(Serializer)? {
+        Some(Value::Object(obj)) => Ok(obj),
+        _ => Err(Error::Message(
+            "the top level value needs to be a struct or a map".to_string(),
+        )),
+    }
+}
+
+/// Serialize a type into binary VDF data
+pub fn to_bytes<T>(value: &T) -> Result<Vec<u8>>
+where
+    T: Serialize + ?Sized,
+{
+    crate::write(&to_object(value)?)
+}
+
+/// Serializer producing a `Value`, `None` means that the value should be
+/// left out of the parent object
+pub struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Ok = Option<Value>;
+    type Error = Error;
+
+    type SerializeSeq = SerializeSeq;
+    type SerializeTuple = SerializeSeq;
+    type SerializeTupleStruct = SerializeSeq;
+    type SerializeTupleVariant = SerializeSeq;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = SerializeMap;
+    type SerializeStructVariant = SerializeMap;
+
+    fn serialize_bool(self, v: bool) -> Result<Self::Ok> {
+        Ok(Some(Value::Integer(v as u32)))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Self::Ok> {
+        self.serialize_i32(v as i32)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Self::Ok> {
+        self.serialize_i32(v as i32)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Self::Ok> {
+        Ok(Some(Value::Integer(v as u32)))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Self::Ok> {
+        Ok(Some(Value::Int64(v)))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Self::Ok> {
+        self.serialize_u32(v as u32)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Self::Ok> {
+        self.serialize_u32(v as u32)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Self::Ok> {
+        Ok(Some(Value::Integer(v)))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Self::Ok> {
+        Ok(Some(Value::Uint64(v)))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Self::Ok> {
+        Ok(Some(Value::Float(v)))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Self::Ok> {
+        Ok(Some(Value::Float(v as f32)))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Self::Ok> {
+        Ok(Some(Value::String(v.to_string())))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Self::Ok> {
+        Ok(Some(Value::String(v.to_string())))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok> {
+        // NOTE(patrik): The only binary data the format has is the color
+        let color = v.try_into().map_err(|_| {
+            Error::Message("only 4 byte colors can be serialized".to_string())
+        })?;
+
+        Ok(Some(Value::Color(color)))
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok> {
+        Ok(None)
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<Self::Ok>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok> {
+        Ok(None)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Self::Ok> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok>
+    where
+        T: Serialize + ?Sized,
+    {
+        let value = value.serialize(self)?;
+
+        let value = match (name, value) {
+            (POINTER_TOKEN, Some(Value::Integer(p))) => Value::Pointer(p),
+            (WIDE_STRING_TOKEN, Some(Value::Object(units))) => {
+                Value::WideString(wide_string_units(&units)?)
+            }
+            (COLOR_TOKEN, Some(Value::Color(c))) => Value::Color(c),
+
+            (_, value) => return Ok(value),
+        };
+
+        Ok(Some(value))
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok>
+    where
+        T: Serialize + ?Sized,
+    {
+        let mut obj = Object::new();
+        if let Some(value) = value.serialize(self)? {
+            obj.set_value(variant.to_string(), value);
+        }
+
+        Ok(Some(Value::Object(obj)))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Ok(SerializeSeq {
+            obj: Object::new(),
+            variant: None,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Ok(SerializeSeq {
+            obj: Object::new(),
+            variant: Some(variant),
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Ok(SerializeMap {
+            obj: Object::new(),
+            key: None,
+            variant: None,
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Ok(SerializeMap {
+            obj: Object::new(),
+            key: None,
+            variant: Some(variant),
+        })
+    }
+}
+
+/// The code units of a wide string that got serialized as a sequence
+fn wide_string_units(obj: &Object) -> Result<Vec<u16>> {
+    obj.values
+        .iter()
+        .map(|(_, value)| match value {
+            Value::Integer(unit) => u16::try_from(*unit).ok(),
+            _ => None,
+        })
+        .collect::<Option<Vec<u16>>>()
+        .ok_or_else(|| {
+            Error::Message("a wide string needs UTF-16 code units".to_string())
+        })
+}
+
+/// Wrap the object inside another object if we are serializing an enum
+/// variant i.e { "Variant": { ... } }
+fn finish_object(obj: Object, variant: Option<&'static str>) -> Value {
+    match variant {
+        Some(variant) => {
+            let mut outer = Object::new();
+            outer.set_value(variant.to_string(), Value::Object(obj));
+            Value::Object(outer)
+        }
+
+        None => Value::Object(obj),
+    }
+}
+
+pub struct SerializeSeq {
+    obj: Object,
+    variant: Option<&'static str>,
+}
+
+impl SerializeSeq {
+    fn push<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        if let Some(value) = value.serialize(Serializer)? {
+            let index = self.obj.values.len();
+            self.obj.values.push((index.to_string(), value));
+        }
+
+        Ok(())
+    }
+}
+
+impl ser::SerializeSeq for SerializeSeq {
+    type Ok = Option<Value>;
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        Ok(Some(finish_object(self.obj, self.variant)))
+    }
+}
+
+impl ser::SerializeTuple for SerializeSeq {
+    type Ok = Option<Value>;
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeSeq {
+    type Ok = Option<Value>;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeSeq {
+    type Ok = Option<Value>;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+pub struct SerializeMap {
+    obj: Object,
+    key: Option<String>,
+    variant: Option<&'static str>,
+}
+
+impl SerializeMap {
+    fn insert<T>(&mut self, key: String, value: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        // NOTE(patrik): Pushed so the duplicate keys of an `Object` are kept
+        if let Some(value) = value.serialize(Serializer)? {
+            self.obj.push_value(key, value);
+        }
+
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = Option<Value>;
+    type Error = Error;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.key = Some(key.serialize(KeySerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        let key = self.key.take().ok_or_else(|| {
+            Error::Message(
+                "serialize_value called before serialize_key".into(),
+            )
+        })?;
+
+        self.insert(key, value)
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        Ok(Some(finish_object(self.obj, self.variant)))
+    }
+}
+
+impl ser::SerializeStruct for SerializeMap {
+    type Ok = Option<Value>;
+    type Error = Error;
+
+    fn serialize_field<T>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.insert(key.to_string(), value)
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        ser::SerializeMap::end(self)
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeMap {
+    type Ok = Option<Value>;
+    type Error = Error;
+
+    fn serialize_field<T>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.insert(key.to_string(), value)
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        ser::SerializeMap::end(self)
+    }
+}
+
+/// Serializer for object keys, keys can only be strings so numbers are
+/// converted to strings
+struct KeySerializer;
+
+fn key_must_be_a_string() -> Error {
+    Error::Message("object keys must be strings or numbers".to_string())
+}
+
+impl ser::Serializer for KeySerializer {
+    type Ok = String;
+    type Error = Error;
+
+    type SerializeSeq = ser::Impossible<String, Error>;
+    type SerializeTuple = ser::Impossible<String, Error>;
+    type SerializeTupleStruct = ser::Impossible<String, Error>;
+    type SerializeTupleVariant = ser::Impossible<String, Error>;
+    type SerializeMap = ser::Impossible<String, Error>;
+    type SerializeStruct = ser::Impossible<String, Error>;
+    type SerializeStructVariant = ser::Impossible<String, Error>;
+
+    fn serialize_bool(self, v: bool) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<String> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<String> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_char(self, v: char) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<String> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_none(self) -> Result<String> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<String>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<String> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<String> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<String> {
+        Ok(variant.to_string())
+    }
+
+    fn serialize_newtype_struct<T>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<String>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<String>
+    where
+        T: Serialize + ?Sized,
+    {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Err(key_must_be_a_string())
+    }
+}
+
+impl Serialize for Value {
+    fn serialize<S>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        match self {
+            Value::Object(o) => o.serialize(serializer),
+            Value::String(s) => serializer.serialize_str(s),
+            Value::Integer(i) => serializer.serialize_u32(*i),
+            Value::Float(v) => serializer.serialize_f32(*v),
+            Value::Uint64(i) => serializer.serialize_u64(*i),
+            Value::Int64(i) => serializer.serialize_i64(*i),
+
+            // NOTE(patrik): Wrapped so our serializer can tell them apart,
+            // other serializers will just see the inner value
+            Value::Pointer(p) => {
+                serializer.serialize_newtype_struct(POINTER_TOKEN, p)
+            }
+            Value::WideString(s) => {
+                serializer.serialize_newtype_struct(WIDE_STRING_TOKEN, s)
+            }
+            Value::Color(c) => serializer
+                .serialize_newtype_struct(COLOR_TOKEN, &Bytes(c.as_slice())),
+        }
+    }
+}
+
+/// Serialize a byte slice with `serialize_bytes` instead of as a sequence
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Serialize for Bytes<'a> {
+    fn serialize<S>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+impl Serialize for Object {
+    fn serialize<S>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        use ser::SerializeMap;
+
+        let mut map = serializer.serialize_map(Some(self.values.len()))?;
+        for (key, value) in self.values.iter() {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
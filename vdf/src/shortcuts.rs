@@ -0,0 +1,502 @@
+//! Typed model of Steam's `shortcuts.vdf`, the file that holds the
+//! non-Steam games of a user
+//!
+//! The file is a binary VDF file with a single "shortcuts" object, the
+//! entries are stored inside that object with the keys "0", "1", "2"...
+//! Any other top level keys are kept and written back as they were.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Object, Result, Value};
+
+/// The last play time of new shortcuts, the value Steam had in the
+/// shortcuts decker was first made against
+pub const DEFAULT_LAST_PLAY_TIME: u32 = 1663261394;
+
+/// A single non-Steam game entry
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShortcutEntry {
+    pub appid: u32,
+
+    #[serde(rename = "AppName", alias = "appname")]
+    pub app_name: String,
+
+    #[serde(rename = "Exe", alias = "exe")]
+    pub exe: String,
+
+    #[serde(rename = "StartDir")]
+    pub start_dir: String,
+
+    pub icon: String,
+
+    #[serde(rename = "ShortcutPath")]
+    pub shortcut_path: String,
+
+    #[serde(rename = "LaunchOptions")]
+    pub launch_options: String,
+
+    #[serde(rename = "IsHidden")]
+    pub is_hidden: bool,
+
+    #[serde(rename = "AllowDesktopConfig")]
+    pub allow_desktop_config: bool,
+
+    #[serde(rename = "AllowOverlay")]
+    pub allow_overlay: bool,
+
+    #[serde(rename = "OpenVR")]
+    pub open_vr: bool,
+
+    #[serde(rename = "Devkit")]
+    pub devkit: bool,
+
+    #[serde(rename = "DevkitGameID")]
+    pub devkit_game_id: String,
+
+    #[serde(rename = "DevkitOverrideAppID")]
+    pub devkit_override_app_id: u32,
+
+    #[serde(rename = "LastPlayTime")]
+    pub last_play_time: u32,
+
+    #[serde(rename = "FlatpakAppID")]
+    pub flatpak_app_id: String,
+
+    pub tags: Vec<String>,
+
+    /// Keys we don't know about, kept so we can write them back
+    #[serde(flatten)]
+    pub extra: Object,
+}
+
+impl Default for ShortcutEntry {
+    fn default() -> Self {
+        Self {
+            appid: 0,
+            app_name: String::new(),
+            exe: String::new(),
+            start_dir: String::new(),
+            icon: String::new(),
+            shortcut_path: String::new(),
+            launch_options: String::new(),
+            is_hidden: false,
+            allow_desktop_config: true,
+            allow_overlay: true,
+            open_vr: false,
+            devkit: false,
+            devkit_game_id: String::new(),
+            devkit_override_app_id: 0,
+            last_play_time: DEFAULT_LAST_PLAY_TIME,
+            flatpak_app_id: String::new(),
+            tags: Vec::new(),
+            extra: Object::new(),
+        }
+    }
+}
+
+impl ShortcutEntry {
+    pub fn new(
+        appid: u32,
+        app_name: String,
+        exe: String,
+        start_dir: String,
+    ) -> Self {
+        Self {
+            appid,
+            app_name,
+            exe,
+            start_dir,
+            ..Default::default()
+        }
+    }
+}
+
+/// All the shortcuts of a user
+#[derive(Clone, Debug)]
+pub struct Shortcuts {
+    /// The name of the top level key, Steam uses "shortcuts" but we keep
+    /// whatever the file had
+    key: String,
+
+    /// The whole top level object so the other keys are written back
+    root: Object,
+
+    entries: Vec<ShortcutEntry>,
+}
+
+impl Default for Shortcuts {
+    fn default() -> Self {
+        Self {
+            key: "shortcuts".to_string(),
+            root: Object::new(),
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl Shortcuts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse the content of a `shortcuts.vdf` file, empty data gives no
+    /// shortcuts
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let mut result = Self::new();
+
+        if bytes.is_empty() {
+            return Ok(result);
+        }
+
+        let obj = crate::parse(bytes)?;
+
+        let (key, list) = match obj
+            .values()
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case("shortcuts"))
+        {
+            Some((key, Value::Object(list))) => (key, list),
+            Some((key, _)) => {
+                return Err(Error::Message(format!(
+                    "'{}' is not an object",
+                    key
+                )))
+            }
+            None => {
+                result.root = obj;
+                return Ok(result);
+            }
+        };
+
+        result.key = key.clone();
+
+        let mut entries = Vec::new();
+        for (index, value) in list.values() {
+            let entry =
+                ShortcutEntry::deserialize(crate::Deserializer::new(value))
+                    .map_err(|e| {
+                        Error::Message(format!("{}/{}: {}", key, index, e))
+                    })?;
+
+            let index = index.parse::<u32>().unwrap_or(u32::MAX);
+            entries.push((index, entry));
+        }
+
+        entries.sort_by_key(|(index, _)| *index);
+        result.entries = entries.into_iter().map(|(_, e)| e).collect();
+        result.root = obj;
+
+        Ok(result)
+    }
+
+    /// Write the shortcuts as a `shortcuts.vdf` file, the entries get
+    /// re-indexed from 0
+    pub fn write(&self) -> Result<Vec<u8>> {
+        let mut list = Object::new();
+        for (index, entry) in self.entries.iter().enumerate() {
+            list.set_value(
+                index.to_string(),
+                Value::Object(crate::to_object(entry)?),
+            );
+        }
+
+        // NOTE(patrik): Replaces the list in place so the order of the top
+        // level keys stays the same
+        let mut obj = self.root.clone();
+        obj.set_value(self.key.clone(), Value::Object(list));
+
+        crate::write(&obj)
+    }
+
+    pub fn entries(&self) -> &[ShortcutEntry] {
+        &self.entries
+    }
+
+    pub fn entries_mut(&mut self) -> &mut Vec<ShortcutEntry> {
+        &mut self.entries
+    }
+
+    /// Add a new entry at the end of the list
+    pub fn add(&mut self, entry: ShortcutEntry) -> &mut ShortcutEntry {
+        self.entries.push(entry);
+        self.entries.last_mut().unwrap()
+    }
+
+    pub fn find_by_appid(&self, appid: u32) -> Option<&ShortcutEntry> {
+        self.entries.iter().find(|e| e.appid == appid)
+    }
+
+    pub fn find_by_appid_mut(
+        &mut self,
+        appid: u32,
+    ) -> Option<&mut ShortcutEntry> {
+        self.entries.iter_mut().find(|e| e.appid == appid)
+    }
+
+    pub fn find_by_devkit_game_id(
+        &self,
+        game_id: &str,
+    ) -> Option<&ShortcutEntry> {
+        self.entries.iter().find(|e| e.devkit_game_id == game_id)
+    }
+
+    pub fn find_by_devkit_game_id_mut(
+        &mut self,
+        game_id: &str,
+    ) -> Option<&mut ShortcutEntry> {
+        self.entries
+            .iter_mut()
+            .find(|e| e.devkit_game_id == game_id)
+    }
+
+    /// Remove all the entries with the appid, returns the removed entries
+    pub fn remove_by_appid(&mut self, appid: u32) -> Vec<ShortcutEntry> {
+        self.remove_where(|e| e.appid == appid)
+    }
+
+    /// Remove all the entries with the game id, returns the removed entries
+    pub fn remove_by_devkit_game_id(
+        &mut self,
+        game_id: &str,
+    ) -> Vec<ShortcutEntry> {
+        self.remove_where(|e| e.devkit_game_id == game_id)
+    }
+
+    fn remove_where<F>(&mut self, f: F) -> Vec<ShortcutEntry>
+    where
+        F: Fn(&ShortcutEntry) -> bool,
+    {
+        let (removed, kept) = self.entries.drain(..).partition(f);
+        self.entries = kept;
+
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An entry the way Steam writes it
+    fn steam_entry(appid: u32, name: &str, game_id: &str) -> Object {
+        let values = [
+            ("appid", Value::Integer(appid)),
+            ("AppName", Value::String(name.to_string())),
+            ("Exe", Value::String("/bin/game".to_string())),
+            ("StartDir", Value::String("/bin".to_string())),
+            ("icon", Value::String(String::new())),
+            ("ShortcutPath", Value::String(String::new())),
+            ("LaunchOptions", Value::String("--fast".to_string())),
+            ("IsHidden", Value::Integer(0)),
+            ("AllowDesktopConfig", Value::Integer(1)),
+            ("AllowOverlay", Value::Integer(1)),
+            ("OpenVR", Value::Integer(0)),
+            ("Devkit", Value::Integer(1)),
+            ("DevkitGameID", Value::String(game_id.to_string())),
+            ("DevkitOverrideAppID", Value::Integer(0)),
+            ("LastPlayTime", Value::Integer(1700000000)),
+            ("FlatpakAppID", Value::String(String::new())),
+        ];
+
+        let mut obj = Object::new();
+        for (key, value) in values {
+            obj.set_value(key.to_string(), value);
+        }
+
+        let mut tags = Object::new();
+        tags.set_value("0".to_string(), Value::String("Dev".to_string()));
+        obj.set_value("tags".to_string(), Value::Object(tags));
+
+        obj
+    }
+
+    fn steam_file(entries: Vec<(&str, Object)>) -> Vec<u8> {
+        let mut list = Object::new();
+        for (index, entry) in entries {
+            list.set_value(index.to_string(), Value::Object(entry));
+        }
+
+        let mut obj = Object::new();
+        obj.set_value("shortcuts".to_string(), Value::Object(list));
+        crate::write(&obj).unwrap()
+    }
+
+    fn names(shortcuts: &Shortcuts) -> Vec<&str> {
+        shortcuts
+            .entries()
+            .iter()
+            .map(|e| e.app_name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn defaults() {
+        let entry = ShortcutEntry::new(
+            1,
+            "Game".to_string(),
+            "/bin/game".to_string(),
+            "/bin".to_string(),
+        );
+        assert_eq!(entry.appid, 1);
+        assert!(!entry.is_hidden);
+        assert!(entry.allow_desktop_config);
+        assert!(entry.allow_overlay);
+        assert!(!entry.open_vr);
+        assert!(!entry.devkit);
+        assert_eq!(entry.last_play_time, DEFAULT_LAST_PLAY_TIME);
+        assert!(entry.tags.is_empty());
+
+        // Keys missing in the file get the defaults
+        let mut obj = Object::new();
+        obj.set_value("AppName".to_string(), Value::String("x".to_string()));
+        let data = steam_file(vec![("0", obj)]);
+        let shortcuts = Shortcuts::parse(&data).unwrap();
+        let entry = &shortcuts.entries()[0];
+        assert_eq!(entry.app_name, "x");
+        assert!(entry.allow_overlay);
+        assert_eq!(entry.last_play_time, DEFAULT_LAST_PLAY_TIME);
+    }
+
+    #[test]
+    fn empty() {
+        let shortcuts = Shortcuts::parse(&[]).unwrap();
+        assert!(shortcuts.entries().is_empty());
+
+        let data = shortcuts.write().unwrap();
+        assert_eq!(data, b"\x00shortcuts\x00\x08\x08");
+        assert!(Shortcuts::parse(&data).unwrap().entries().is_empty());
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = steam_file(vec![
+            ("0", steam_entry(0x80000001, "First", "first")),
+            ("1", steam_entry(0x80000002, "Second", "second")),
+        ]);
+
+        let shortcuts = Shortcuts::parse(&data).unwrap();
+        assert_eq!(names(&shortcuts), ["First", "Second"]);
+
+        let entry = &shortcuts.entries()[1];
+        assert_eq!(entry.appid, 0x80000002);
+        assert_eq!(entry.exe, "/bin/game");
+        assert_eq!(entry.start_dir, "/bin");
+        assert_eq!(entry.launch_options, "--fast");
+        assert!(entry.devkit);
+        assert_eq!(entry.devkit_game_id, "second");
+        assert_eq!(entry.last_play_time, 1700000000);
+        assert_eq!(entry.tags, ["Dev"]);
+        assert!(entry.extra.values().is_empty());
+
+        assert_eq!(shortcuts.write().unwrap(), data);
+    }
+
+    #[test]
+    fn round_trip_unknown_keys() {
+        let mut entry = steam_entry(1, "Game", "game");
+        entry.set_value("SortAs".to_string(), Value::String("a".to_string()));
+        entry.set_value("Pointer".to_string(), Value::Pointer(0xdead));
+
+        let mut list = Object::new();
+        list.set_value("0".to_string(), Value::Object(entry));
+
+        let mut obj = Object::new();
+        obj.set_value("before".to_string(), Value::Integer(1));
+        obj.set_value("shortcuts".to_string(), Value::Object(list));
+        obj.set_value("after".to_string(), Value::String("x".to_string()));
+        let data = crate::write(&obj).unwrap();
+
+        let shortcuts = Shortcuts::parse(&data).unwrap();
+        let extra = &shortcuts.entries()[0].extra;
+        assert_eq!(extra.value("SortAs"), Some(&Value::String("a".into())));
+        assert_eq!(extra.value("Pointer"), Some(&Value::Pointer(0xdead)));
+
+        assert_eq!(shortcuts.write().unwrap(), data);
+
+        // A file without the list keeps its keys too
+        let mut obj = Object::new();
+        obj.set_value("other".to_string(), Value::Integer(1));
+        let shortcuts =
+            Shortcuts::parse(&crate::write(&obj).unwrap()).unwrap();
+        let obj = crate::parse(&shortcuts.write().unwrap()).unwrap();
+        assert_eq!(obj.value("other"), Some(&Value::Integer(1)));
+        assert!(matches!(obj.value("shortcuts"), Some(Value::Object(_))));
+    }
+
+    #[test]
+    fn add_find_remove() {
+        let mut shortcuts = Shortcuts::new();
+
+        let entry = shortcuts.add(ShortcutEntry::new(
+            1,
+            "First".to_string(),
+            String::new(),
+            String::new(),
+        ));
+        entry.devkit_game_id = "first".to_string();
+
+        let mut second = ShortcutEntry::new(
+            2,
+            "Second".to_string(),
+            String::new(),
+            String::new(),
+        );
+        second.devkit_game_id = "second".to_string();
+        shortcuts.add(second.clone());
+        shortcuts.add(second);
+
+        assert_eq!(names(&shortcuts), ["First", "Second", "Second"]);
+
+        let found = shortcuts.find_by_devkit_game_id("second").unwrap();
+        assert_eq!(found.appid, 2);
+        assert!(shortcuts.find_by_devkit_game_id("third").is_none());
+        assert_eq!(shortcuts.find_by_appid(1).unwrap().app_name, "First");
+
+        shortcuts
+            .find_by_devkit_game_id_mut("first")
+            .unwrap()
+            .launch_options = "-x".to_string();
+        assert_eq!(shortcuts.find_by_appid(1).unwrap().launch_options, "-x");
+
+        // Both duplicates are removed
+        let removed = shortcuts.remove_by_devkit_game_id("second");
+        assert_eq!(removed.len(), 2);
+        assert_eq!(names(&shortcuts), ["First"]);
+
+        assert!(shortcuts.remove_by_appid(5).is_empty());
+        assert_eq!(shortcuts.remove_by_appid(1).len(), 1);
+        assert!(shortcuts.entries().is_empty());
+    }
+
+    #[test]
+    fn reindex() {
+        let data = steam_file(vec![
+            ("5", steam_entry(5, "Five", "five")),
+            ("0", steam_entry(0, "Zero", "zero")),
+            ("2", steam_entry(2, "Two", "two")),
+        ]);
+
+        // The entries are sorted by their index
+        let mut shortcuts = Shortcuts::parse(&data).unwrap();
+        assert_eq!(names(&shortcuts), ["Zero", "Two", "Five"]);
+
+        shortcuts.remove_by_devkit_game_id("two");
+
+        let obj = crate::parse(&shortcuts.write().unwrap()).unwrap();
+        let list = match obj.value("shortcuts") {
+            Some(Value::Object(list)) => list,
+            value => panic!("{:?}", value),
+        };
+
+        let keys = list
+            .values()
+            .iter()
+            .map(|v| v.0.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, ["0", "1"]);
+
+        let shortcuts = Shortcuts::parse(&shortcuts.write().unwrap()).unwrap();
+        assert_eq!(names(&shortcuts), ["Zero", "Five"]);
+    }
+}
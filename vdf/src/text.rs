@@ -0,0 +1,919 @@
+//! Text KeyValues format, used by files like `config.vdf`,
+//! `libraryfolders.vdf`, `loginusers.vdf` and `appmanifest_*.acf`
+//!
+//! The text format only knows about strings and objects, so every value we
+//! parse becomes a `Value::String` or a `Value::Object`. When writing, other
+//! value types are written as their string representation.
+//!
+//! Comments, platform conditionals, unquoted strings and duplicate keys are
+//! kept, a parsed document is written back with the same content. Only the
+//! whitespace is not kept, the writer indents with tabs like Steam does.
+
+use std::collections::HashMap;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::{Error, Object, Result, Value};
+
+/// Tokens the text format is made of
+#[derive(Debug, PartialEq)]
+enum Token {
+    /// Quoted string
+    String(String),
+
+    /// String without quotes
+    Unquoted(String),
+
+    /// '{'
+    ObjectStart,
+
+    /// '}'
+    ObjectEnd,
+
+    /// Platform conditional i.e "[$WIN32]", we don't evaluate them
+    Conditional(String),
+}
+
+/// How an entry looked in the document
+#[derive(Clone, Debug, Default, PartialEq)]
+struct EntryFormat {
+    /// Comments on the lines before the entry, the text after the '//'
+    comments: Vec<String>,
+
+    /// Comment on the same line after the value
+    line_comment: Option<String>,
+
+    /// Platform conditional between the key and the value, without the
+    /// brackets
+    key_conditional: Option<String>,
+
+    /// Platform conditional after the value, without the brackets
+    conditional: Option<String>,
+
+    unquoted_key: bool,
+    unquoted_value: bool,
+}
+
+/// The parts of a text document that are not in the values of an `Object`
+///
+/// The entries are found by their key and the number of entries with the
+/// same key before them, that way the format stays with the right entry when
+/// entries are added or removed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Layout {
+    entries: HashMap<(String, usize), EntryFormat>,
+
+    /// Comments after the last entry
+    trailing_comments: Vec<String>,
+}
+
+/// A '//' comment, the text after the slashes
+struct Comment {
+    text: String,
+
+    /// The comment is not on the same line as a previous token
+    own_line: bool,
+}
+
+struct Lexer<'a> {
+    input: &'a str,
+    offset: usize,
+    line: usize,
+
+    /// Comments that haven't been given to an entry yet
+    comments: Vec<Comment>,
+
+    /// There has been a newline since the last token
+    newline: bool,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(input: &'a str) -> Self {
+        Self {
+            input,
+            offset: 0,
+            line: 1,
+            comments: Vec::new(),
+            newline: true,
+        }
+    }
+
+    fn take_comments(&mut self) -> Vec<String> {
+        self.comments
+            .drain(..)
+            .map(|comment| comment.text)
+            .collect()
+    }
+
+    fn peek_char(&self) -> Option<char> {
+        self.input[self.offset..].chars().next()
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.peek_char()?;
+        self.offset += c.len_utf8();
+
+        if c == '\n' {
+            self.line += 1;
+        }
+
+        Some(c)
+    }
+
+    /// Skip whitespace and collect the comments
+    fn skip_trivia(&mut self) {
+        loop {
+            match self.peek_char() {
+                Some(c) if c.is_whitespace() => {
+                    if c == '\n' {
+                        self.newline = true;
+                    }
+
+                    self.next_char();
+                }
+
+                Some('/') if self.input[self.offset..].starts_with("//") => {
+                    self.offset += 2;
+
+                    let mut text = String::new();
+                    while let Some(c) = self.peek_char() {
+                        if c == '\n' {
+                            break;
+                        }
+
+                        text.push(c);
+                        self.next_char();
+                    }
+
+                    if text.ends_with('\r') {
+                        text.pop();
+                    }
+
+                    self.comments.push(Comment {
+                        text,
+                        own_line: self.newline,
+                    });
+                }
+
+                _ => break,
+            }
+        }
+    }
+
+    fn quoted_string(&mut self) -> Result<String> {
+        let line = self.line;
+        let mut result = String::new();
+
+        loop {
+            let c =
+                self.next_char().ok_or(Error::UnterminatedString { line })?;
+
+            match c {
+                '"' => break,
+
+                '\\' => {
+                    let c = self
+                        .next_char()
+                        .ok_or(Error::UnterminatedString { line })?;
+
+                    match c {
+                        'n' => result.push('\n'),
+                        't' => result.push('\t'),
+                        '\\' => result.push('\\'),
+                        '"' => result.push('"'),
+
+                        // NOTE(patrik): Steam keeps unknown escapes as is
+                        _ => {
+                            result.push('\\');
+                            result.push(c);
+                        }
+                    }
+                }
+
+                _ => result.push(c),
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn unquoted_string(&mut self) -> String {
+        let mut result = String::new();
+
+        while let Some(c) = self.peek_char() {
+            if c.is_whitespace() || matches!(c, '"' | '{' | '}') {
+                break;
+            }
+
+            if self.input[self.offset..].starts_with("//") {
+                break;
+            }
+
+            result.push(c);
+            self.next_char();
+        }
+
+        result
+    }
+
+    fn conditional(&mut self) -> Result<String> {
+        let line = self.line;
+        let mut result = String::new();
+
+        loop {
+            match self.next_char() {
+                Some(']') => break,
+                Some('\n') | None => {
+                    return Err(Error::UnexpectedToken {
+                        line,
+                        token: format!("[{}", result),
+                    })
+                }
+                Some(c) => result.push(c),
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>> {
+        self.skip_trivia();
+
+        let c = match self.peek_char() {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+        self.newline = false;
+
+        let token = match c {
+            '{' => {
+                self.next_char();
+                Token::ObjectStart
+            }
+
+            '}' => {
+                self.next_char();
+                Token::ObjectEnd
+            }
+
+            '"' => {
+                self.next_char();
+                Token::String(self.quoted_string()?)
+            }
+
+            '[' => {
+                self.next_char();
+                Token::Conditional(self.conditional()?)
+            }
+
+            _ => Token::Unquoted(self.unquoted_string()),
+        };
+
+        Ok(Some(token))
+    }
+}
+
+struct Parser<'a> {
+    lexer: Lexer<'a>,
+    peeked: Option<Token>,
+}
+
+impl<'a> Parser<'a> {
+    fn next_token(&mut self) -> Result<Option<Token>> {
+        if let Some(token) = self.peeked.take() {
+            return Ok(Some(token));
+        }
+
+        self.lexer.next_token()
+    }
+
+    /// Read the conditional following a key value pair
+    fn conditional(&mut self) -> Result<Option<String>> {
+        match self.next_token()? {
+            Some(Token::Conditional(conditional)) => Ok(Some(conditional)),
+            Some(token) => {
+                self.peeked = Some(token);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn parse_object(&mut self, nested: bool) -> Result<Object> {
+        let mut obj = Object::new();
+        let mut counts: HashMap<String, usize> = HashMap::new();
+
+        loop {
+            let line = self.lexer.line;
+
+            let mut format = EntryFormat::default();
+            let key = match self.next_token()? {
+                Some(Token::String(key)) => key,
+                Some(Token::Unquoted(key)) => {
+                    format.unquoted_key = true;
+                    key
+                }
+
+                Some(Token::ObjectEnd) if nested => break,
+
+                None if !nested => break,
+                None => return Err(Error::UnexpectedEndOfInput { line }),
+
+                Some(token) => {
+                    return Err(Error::UnexpectedToken {
+                        line,
+                        token: token_to_string(&token),
+                    })
+                }
+            };
+            format.comments = self.lexer.take_comments();
+
+            // NOTE(patrik): Valve's reader also takes the conditional before
+            // the value i.e "key" [$WIN32] { ... }
+            format.key_conditional = self.conditional()?;
+            format.comments.extend(self.lexer.take_comments());
+
+            let line = self.lexer.line;
+            let value = match self.next_token()? {
+                Some(Token::String(s)) => Value::String(s),
+                Some(Token::Unquoted(s)) => {
+                    format.unquoted_value = true;
+                    Value::String(s)
+                }
+                Some(Token::ObjectStart) => {
+                    // NOTE(patrik): Comments between the key and the '{' are
+                    // moved in front of the key
+                    format.comments.extend(self.lexer.take_comments());
+                    Value::Object(self.parse_object(true)?)
+                }
+
+                None => return Err(Error::UnexpectedEndOfInput { line }),
+
+                Some(token) => {
+                    return Err(Error::UnexpectedToken {
+                        line,
+                        token: token_to_string(&token),
+                    })
+                }
+            };
+            format.comments.extend(self.lexer.take_comments());
+
+            format.conditional = self.conditional()?;
+            if self.peeked.is_none() {
+                self.lexer.skip_trivia();
+            }
+
+            // NOTE(patrik): The comments on the next lines belongs to the
+            // next entry
+            if matches!(self.lexer.comments.first(), Some(c) if !c.own_line) {
+                format.line_comment = Some(self.lexer.comments.remove(0).text);
+            }
+
+            let count = counts.entry(key.clone()).or_insert(0);
+            if format != EntryFormat::default() {
+                obj.layout.entries.insert((key.clone(), *count), format);
+            }
+            *count += 1;
+
+            obj.push_value(key, value);
+        }
+
+        obj.layout.trailing_comments = self.lexer.take_comments();
+
+        Ok(obj)
+    }
+}
+
+fn token_to_string(token: &Token) -> String {
+    match token {
+        Token::String(s) => format!("\"{}\"", s),
+        Token::Unquoted(s) => s.clone(),
+        Token::ObjectStart => "{".to_string(),
+        Token::ObjectEnd => "}".to_string(),
+        Token::Conditional(s) => format!("[{}]", s),
+    }
+}
+
+/// Parse a text KeyValues document, the top level key value pairs are
+/// returned as the values of the object
+pub fn parse(input: &str) -> Result<Object> {
+    // Skip the UTF-8 byte order mark if there is one
+    let input = input.strip_prefix('\u{feff}').unwrap_or(input);
+
+    let mut parser = Parser {
+        lexer: Lexer::new(input),
+        peeked: None,
+    };
+
+    parser.parse_object(false)
+}
+
+/// Check if the lexer reads the string back the same way without quotes
+fn can_be_unquoted(s: &str) -> bool {
+    !s.is_empty() &&
+        !s.starts_with('[') &&
+        !s.contains("//") &&
+        s.chars()
+            .all(|c| !c.is_whitespace() && !matches!(c, '"' | '{' | '}'))
+}
+
+fn write_string(buffer: &mut String, s: &str, unquoted: bool) {
+    if unquoted && can_be_unquoted(s) {
+        buffer.push_str(s);
+        return;
+    }
+
+    buffer.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => buffer.push_str("\\\""),
+            '\\' => buffer.push_str("\\\\"),
+            '\n' => buffer.push_str("\\n"),
+            '\t' => buffer.push_str("\\t"),
+            _ => buffer.push(c),
+        }
+    }
+
+    buffer.push('"');
+}
+
+fn write_indent(buffer: &mut String, indent: usize) {
+    for _ in 0..indent {
+        buffer.push('\t');
+    }
+}
+
+fn write_comments(buffer: &mut String, comments: &[String], indent: usize) {
+    for comment in comments {
+        write_indent(buffer, indent);
+        buffer.push_str("//");
+        buffer.push_str(comment);
+        buffer.push('\n');
+    }
+}
+
+fn write_object(buffer: &mut String, obj: &Object, indent: usize) {
+    let default_format = EntryFormat::default();
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+
+    for (key, value) in obj.values.iter() {
+        let count = counts.entry(key).or_insert(0);
+        let format = obj
+            .layout
+            .entries
+            .get(&(key.clone(), *count))
+            .unwrap_or(&default_format);
+        *count += 1;
+
+        write_comments(buffer, &format.comments, indent);
+
+        write_indent(buffer, indent);
+        write_string(buffer, key, format.unquoted_key);
+
+        if let Some(conditional) = &format.key_conditional {
+            buffer.push_str(" [");
+            buffer.push_str(conditional);
+            buffer.push(']');
+        }
+
+        // NOTE(patrik): The text format only has strings, the other values
+        // are written as their string representation
+        let text = match value {
+            Value::Object(o) => {
+                buffer.push('\n');
+                write_indent(buffer, indent);
+                buffer.push_str("{\n");
+
+                write_object(buffer, o, indent + 1);
+
+                write_indent(buffer, indent);
+                buffer.push('}');
+
+                None
+            }
+
+            Value::String(s) => Some(s.clone()),
+            Value::WideString(s) => Some(String::from_utf16_lossy(s)),
+            Value::Integer(i) => Some(i.to_string()),
+            Value::Float(v) => Some(v.to_string()),
+            Value::Pointer(p) => Some(p.to_string()),
+            Value::Color(c) => {
+                Some(format!("{} {} {} {}", c[0], c[1], c[2], c[3]))
+            }
+            Value::Uint64(i) => Some(i.to_string()),
+            Value::Int64(i) => Some(i.to_string()),
+        };
+
+        if let Some(text) = text {
+            buffer.push_str("\t\t");
+            write_string(buffer, &text, format.unquoted_value);
+        }
+
+        if let Some(conditional) = &format.conditional {
+            buffer.push_str(" [");
+            buffer.push_str(conditional);
+            buffer.push(']');
+        }
+
+        if let Some(comment) = &format.line_comment {
+            buffer.push_str(" //");
+            buffer.push_str(comment);
+        }
+
+        buffer.push('\n');
+    }
+
+    write_comments(buffer, &obj.layout.trailing_comments, indent);
+}
+
+/// Write the object as a text KeyValues document, the values of the object
+/// becomes the top level key value pairs
+pub fn write(obj: &Object) -> Result<String> {
+    let mut buffer = String::new();
+    write_object(&mut buffer, obj, 0);
+
+    Ok(buffer)
+}
+
+/// Parse a text KeyValues document and deserialize a type from it
+pub fn from_str<T>(input: &str) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    let obj = parse(input)?;
+    crate::from_object(&obj)
+}
+
+/// Serialize a type into a text KeyValues document
+pub fn to_string<T>(value: &T) -> Result<String>
+where
+    T: Serialize + ?Sized,
+{
+    write(&crate::to_object(value)?)
+}
+
+/// An entry to remove, the byte range of the entry and its key
+type Span = (usize, usize, String);
+
+/// Find the entries with one of the keys in the object at the path, only
+/// the first object with a matching key is searched the same way
+/// `Object::value` works
+fn find_entries(
+    lexer: &mut Lexer,
+    path: Option<&[&str]>,
+    keys: &[&str],
+    nested: bool,
+    spans: &mut Vec<Span>,
+) -> Result<()> {
+    let mut found = false;
+
+    loop {
+        lexer.skip_trivia();
+        lexer.comments.clear();
+
+        let start = lexer.offset;
+        let line = lexer.line;
+        let key = match lexer.next_token()? {
+            Some(Token::String(key)) | Some(Token::Unquoted(key)) => key,
+
+            Some(Token::ObjectEnd) if nested => return Ok(()),
+
+            None if !nested => return Ok(()),
+            None => return Err(Error::UnexpectedEndOfInput { line }),
+
+            Some(token) => {
+                return Err(Error::UnexpectedToken {
+                    line,
+                    token: token_to_string(&token),
+                })
+            }
+        };
+
+        let line = lexer.line;
+        let mut value = lexer.next_token()?;
+        if let Some(Token::Conditional(_)) = value {
+            value = lexer.next_token()?;
+        }
+
+        match value {
+            Some(Token::String(_)) | Some(Token::Unquoted(_)) => {}
+
+            Some(Token::ObjectStart) => {
+                let inner = match path {
+                    Some([first, rest @ ..])
+                        if !found && first.eq_ignore_ascii_case(&key) =>
+                    {
+                        found = true;
+                        Some(rest)
+                    }
+                    _ => None,
+                };
+
+                find_entries(lexer, inner, keys, true, spans)?;
+            }
+
+            None => return Err(Error::UnexpectedEndOfInput { line }),
+
+            Some(token) => {
+                return Err(Error::UnexpectedToken {
+                    line,
+                    token: token_to_string(&token),
+                })
+            }
+        }
+
+        let mut end = lexer.offset;
+
+        lexer.skip_trivia();
+        if lexer.peek_char() == Some('[') {
+            lexer.next_token()?;
+            end = lexer.offset;
+        }
+
+        if path == Some(&[]) && keys.contains(&key.as_str()) {
+            spans.push((start, end, key));
+        }
+    }
+}
+
+/// Grow the span to the whole line if the entry is alone on it, a comment
+/// after the entry goes with it
+fn line_span(input: &str, start: usize, end: usize) -> (usize, usize) {
+    let line_start = input[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = input[end..].find('\n').map_or(input.len(), |i| end + i);
+
+    let before = input[line_start..start].trim();
+    let after = input[end..line_end].trim();
+
+    if before.is_empty() && (after.is_empty() || after.starts_with("//")) {
+        (line_start, (line_end + 1).min(input.len()))
+    } else {
+        (start, end)
+    }
+}
+
+/// Remove the entries with one of the keys from the object at the path
+/// without writing the document again, everything else is kept byte for
+/// byte. Returns the new document and the keys of the removed entries.
+pub fn remove_entries(
+    input: &str,
+    path: &[&str],
+    keys: &[&str],
+) -> Result<(String, Vec<String>)> {
+    let body = input.strip_prefix('\u{feff}').unwrap_or(input);
+    let bom = &input[..input.len() - body.len()];
+
+    let mut spans = Vec::new();
+    find_entries(&mut Lexer::new(body), Some(path), keys, false, &mut spans)?;
+
+    let mut result = bom.to_string();
+    let mut removed = Vec::new();
+    let mut last = 0;
+
+    for (start, end, key) in spans {
+        let (start, end) = line_span(body, start, end);
+        result.push_str(&body[last..start]);
+        last = end;
+
+        removed.push(key);
+    }
+    result.push_str(&body[last..]);
+
+    Ok((result, removed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"// Steam config
+"InstallConfigStore"
+{
+	"Software"
+	{
+		"Valve"
+		{
+			"Steam"
+			{
+				// Keep the tools in sync
+				"CompatToolMapping"
+				{
+					"0"
+					{
+						"name"		"proton_experimental"
+						"config"		""
+						"priority"		"75" // user choice
+					}
+				}
+				"RunningOnWindows"		"1" [$WIN32]
+				"RunningOnWindows"		"0" [!$WIN32]
+				Unquoted		value
+				"Path"		"C:\\Program Files\\Steam"
+				"Message"		"say \"hi\"\n\tbye"
+			}
+		}
+	}
+	// nothing after this
+}
+"#;
+
+    fn steam(obj: &Object) -> &Object {
+        let mut obj = obj;
+        for key in ["InstallConfigStore", "Software", "Valve", "Steam"] {
+            obj = match obj.value(key) {
+                Some(Value::Object(o)) => o,
+                _ => panic!("missing {}", key),
+            };
+        }
+
+        obj
+    }
+
+    #[test]
+    fn parse_values() {
+        let obj = parse(CONFIG).unwrap();
+        let steam = steam(&obj);
+
+        assert_eq!(
+            steam.value("Unquoted"),
+            Some(&Value::String("value".to_string()))
+        );
+        assert_eq!(
+            steam.value("Path"),
+            Some(&Value::String("C:\\Program Files\\Steam".to_string()))
+        );
+        assert_eq!(
+            steam.value("Message"),
+            Some(&Value::String("say \"hi\"\n\tbye".to_string()))
+        );
+
+        // Duplicate keys are all kept
+        let running: Vec<&Value> = steam
+            .values()
+            .iter()
+            .filter(|(key, _)| key == "RunningOnWindows")
+            .map(|(_, value)| value)
+            .collect();
+        assert_eq!(
+            running,
+            vec![
+                &Value::String("1".to_string()),
+                &Value::String("0".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let obj = parse(CONFIG).unwrap();
+        let written = write(&obj).unwrap();
+        assert_eq!(parse(&written).unwrap(), obj);
+
+        // NOTE(patrik): The input is indented the same way as the writer
+        // does, so nothing should change
+        assert_eq!(written, CONFIG);
+    }
+
+    #[test]
+    fn round_trip_keeps_format() {
+        let input = "a   b // after\n// before c\n\"c\" { } [$X]\n\"k\" \
+                     \"1\" \"k\" \"2\"\n// end";
+        let obj = parse(input).unwrap();
+        let written = write(&obj).unwrap();
+
+        assert_eq!(
+            written,
+            "a\t\tb // after\n// before c\n\"c\"\n{\n} \
+             [$X]\n\"k\"\t\t\"1\"\n\"k\"\t\t\"2\"\n// end\n"
+        );
+        assert_eq!(parse(&written).unwrap(), obj);
+    }
+
+    #[test]
+    fn round_trip_key_conditional() {
+        let input = "\"a\" [$WIN32] { \"x\" \"1\" }\n\"b\" [!$X] \"2\" [$Y]\n";
+        let obj = parse(input).unwrap();
+        assert_eq!(obj.value("b"), Some(&Value::String("2".to_string())));
+
+        let written = write(&obj).unwrap();
+        assert_eq!(
+            written,
+            "\"a\" [$WIN32]\n{\n\t\"x\"\t\t\"1\"\n}\n\"b\" [!$X]\t\t\"2\" \
+             [$Y]\n"
+        );
+        assert_eq!(parse(&written).unwrap(), obj);
+        assert_eq!(write(&parse(&written).unwrap()).unwrap(), written);
+
+        // The entries are still found when removing
+        let (result, removed) = remove_entries(input, &["a"], &["x"]).unwrap();
+        assert_eq!(removed, ["x"]);
+        assert_eq!(result, "\"a\" [$WIN32] {  }\n\"b\" [!$X] \"2\" [$Y]\n");
+    }
+
+    #[test]
+    fn format_follows_the_entry() {
+        let mut obj = parse("// first\na 1\n// second\nb 2 [$X]\n").unwrap();
+
+        obj.remove_value("a");
+        obj.set_value("b".to_string(), Value::String("two words".into()));
+        obj.set_value("c".to_string(), Value::String("3".into()));
+
+        assert_eq!(
+            write(&obj).unwrap(),
+            "// second\nb\t\t\"two words\" [$X]\n\"c\"\t\t\"3\"\n"
+        );
+    }
+
+    #[test]
+    fn write_other_values() {
+        let mut obj = Object::new();
+        obj.set_value("int".to_string(), Value::Integer(7));
+        obj.set_value("float".to_string(), Value::Float(1.5));
+        obj.set_value("color".to_string(), Value::Color([1, 2, 3, 4]));
+        obj.set_value("int64".to_string(), Value::Int64(-2));
+
+        let obj = parse(&write(&obj).unwrap()).unwrap();
+        let text = |key| match obj.value(key) {
+            Some(Value::String(s)) => s.as_str(),
+            value => panic!("{:?}", value),
+        };
+
+        assert_eq!(text("int"), "7");
+        assert_eq!(text("float"), "1.5");
+        assert_eq!(text("color"), "1 2 3 4");
+        assert_eq!(text("int64"), "-2");
+    }
+
+    #[test]
+    fn remove_entries_keeps_the_rest() {
+        const MAPPING: [&str; 5] = [
+            "InstallConfigStore",
+            "Software",
+            "Valve",
+            "Steam",
+            "CompatToolMapping",
+        ];
+
+        let (result, removed) =
+            remove_entries(CONFIG, &MAPPING, &["0", "1"]).unwrap();
+        assert_eq!(removed, ["0"]);
+
+        let start = CONFIG.find("\t\t\t\t\t\"0\"").unwrap();
+        let end = CONFIG.find("\t\t\t\t}\n\t\t\t\t\"Running").unwrap();
+        assert_eq!(result, format!("{}{}", &CONFIG[..start], &CONFIG[end..]));
+
+        // Nothing to remove
+        let (result, removed) =
+            remove_entries(CONFIG, &MAPPING, &["2"]).unwrap();
+        assert!(removed.is_empty());
+        assert_eq!(result, CONFIG);
+
+        let (result, removed) =
+            remove_entries(CONFIG, &["Missing"], &["0"]).unwrap();
+        assert!(removed.is_empty());
+        assert_eq!(result, CONFIG);
+    }
+
+    #[test]
+    fn remove_entries_lines() {
+        let input = "\u{feff}\"a\" {\r\n  \"x\" \"1\" // one\r\n  \"y\" \
+                     \"2\" [$X]\r\n  \"x\" \"3\" \"z\" \"4\"\r\n}\r\n\"x\" \
+                     \"5\"";
+        let (result, removed) = remove_entries(input, &["a"], &["x"]).unwrap();
+
+        // All the duplicates but only in the object at the path
+        assert_eq!(removed, ["x", "x"]);
+        assert_eq!(
+            result,
+            "\u{feff}\"a\" {\r\n  \"y\" \"2\" [$X]\r\n   \"z\" \
+             \"4\"\r\n}\r\n\"x\" \"5\""
+        );
+
+        assert!(matches!(
+            remove_entries("\"a\" {", &["a"], &["x"]),
+            Err(Error::UnexpectedEndOfInput { .. })
+        ));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(
+            parse("\"a\" {\n\"b\" \"c\"\n"),
+            Err(Error::UnexpectedEndOfInput { line: 3 })
+        ));
+        assert!(matches!(
+            parse("\"a\"\n\"b"),
+            Err(Error::UnterminatedString { line: 2 })
+        ));
+        assert!(matches!(
+            parse("\"a\" \"b\"\n}"),
+            Err(Error::UnexpectedToken { line: 2, .. })
+        ));
+        assert!(matches!(
+            parse("[$X] \"a\""),
+            Err(Error::UnexpectedToken { line: 1, .. })
+        ));
+    }
+}